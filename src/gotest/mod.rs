@@ -0,0 +1,315 @@
+/*
+ * Copyright (c) 2020 Stephen Connolly and CloudBees, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use crate::reports::{TestCase, TestResult, TestSuite};
+use chrono::{DateTime, Duration, Utc};
+use regex::Regex;
+use serde::Deserialize;
+use std::borrow::Cow;
+use std::io::BufRead;
+
+/// An event of `go test -json`, see `go doc test2json`
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct TestEvent {
+    #[serde(default)]
+    time: Option<String>,
+    action: String,
+    #[serde(default)]
+    package: Option<String>,
+    #[serde(default)]
+    test: Option<String>,
+    #[serde(default)]
+    elapsed: Option<f64>,
+    #[serde(default)]
+    output: Option<String>,
+}
+
+struct GoPackage {
+    name: String,
+    start: Option<DateTime<Utc>>,
+    output: Vec<String>,
+    tests: Vec<GoTest>,
+    failed: bool,
+}
+
+struct GoTest {
+    name: String,
+    start: Option<DateTime<Utc>>,
+    end: Option<DateTime<Utc>>,
+    elapsed: Option<f64>,
+    output: Vec<String>,
+    action: Option<String>,
+}
+
+/// Parses the events of `go test -json` into a test suite per package. Subtests are reported as
+/// test cases of their own, named by their full path, e.g. `TestParse/empty`. A parent test that
+/// only failed because its subtests did is reported as a success so each failure counts once.
+pub fn read_gotest<'a, R: BufRead>(input: &'_ mut R) -> anyhow::Result<Vec<TestSuite<'a>>> {
+    let framing =
+        Regex::new(r"^\s*(=== (RUN|PAUSE|CONT|NAME)\s|--- (PASS|FAIL|SKIP|BENCH): )").unwrap();
+    let log = Regex::new(r"^\s+(?P<file>[^\s:]+\.go):(?P<line>\d+): (?P<message>.*)$").unwrap();
+    let mut packages: Vec<GoPackage> = Vec::new();
+    for line in input.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let event: TestEvent = match serde_json::from_str(&line) {
+            Ok(event) => event,
+            Err(e) => {
+                // e.g. build errors when stderr is redirected to stdout
+                debug!("Ignoring non-JSON line {:?}: {}", line, e);
+                continue;
+            }
+        };
+        let package_name = match &event.package {
+            Some(package) => package.clone(),
+            None => continue,
+        };
+        let time = event
+            .time
+            .as_deref()
+            .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+            .map(|t| t.with_timezone(&Utc));
+        let package = match packages.iter().position(|p| p.name == package_name) {
+            Some(index) => &mut packages[index],
+            None => {
+                packages.push(GoPackage {
+                    name: package_name,
+                    start: time,
+                    output: Vec::new(),
+                    tests: Vec::new(),
+                    failed: false,
+                });
+                packages.last_mut().unwrap()
+            }
+        };
+        let test_name = match &event.test {
+            Some(test) => test,
+            None => {
+                match event.action.as_str() {
+                    "output" => package.output.extend(event.output),
+                    "fail" => package.failed = true,
+                    _ => {}
+                }
+                continue;
+            }
+        };
+        let test = match package.tests.iter().position(|t| &t.name == test_name) {
+            Some(index) => &mut package.tests[index],
+            None => {
+                package.tests.push(GoTest {
+                    name: test_name.clone(),
+                    start: time,
+                    end: None,
+                    elapsed: None,
+                    output: Vec::new(),
+                    action: None,
+                });
+                package.tests.last_mut().unwrap()
+            }
+        };
+        match event.action.as_str() {
+            "output" => test.output.extend(event.output),
+            "pass" | "fail" | "skip" | "bench" => {
+                test.end = time;
+                test.elapsed = event.elapsed;
+                test.action = Some(event.action);
+            }
+            _ => {}
+        }
+    }
+    Ok(packages
+        .into_iter()
+        .filter(|p| !p.tests.is_empty() || p.failed)
+        .map(|p| to_test_suite(p, &framing, &log))
+        .collect())
+}
+
+fn to_test_suite<'a>(package: GoPackage, framing: &Regex, log: &Regex) -> TestSuite<'a> {
+    let mut suite = TestSuite::new(&package.name);
+    if let Some(start) = package.start {
+        suite = suite.with_timestamp(start);
+    }
+    let mut failed = false;
+    for test in &package.tests {
+        let prefix = format!("{}/", test.name);
+        let subtests_failed = package.tests.iter().any(|t| {
+            t.name.starts_with(&prefix) && !matches!(t.action.as_deref(), Some("pass" | "skip"))
+        });
+        let case = to_test_case(&package.name, test, subtests_failed, framing, log);
+        failed |= matches!(
+            case.result(),
+            TestResult::Failure { .. } | TestResult::Error { .. }
+        );
+        suite = suite.push(case);
+    }
+    if package.failed && !failed {
+        // e.g. the build failed, TestMain failed, or a panic in an init function
+        let output = package.output.concat();
+        let message = output
+            .lines()
+            .find(|l| l.starts_with("FAIL") || l.starts_with("panic: "))
+            .unwrap_or("package failed")
+            .to_string();
+        suite = suite.push(TestCase::new_with_output(
+            "[package]",
+            &package.name,
+            &TestResult::error(&message),
+            Cow::Owned(output),
+            Cow::Borrowed(""),
+            Duration::zero(),
+        ));
+    }
+    suite
+}
+
+fn to_test_case<'a>(
+    package: &str,
+    test: &GoTest,
+    subtests_failed: bool,
+    framing: &Regex,
+    log: &Regex,
+) -> TestCase<'a> {
+    let output: String = test
+        .output
+        .iter()
+        .filter(|l| !framing.is_match(l))
+        .map(|l| l.as_str())
+        .collect();
+    let location = output.lines().find_map(|l| log.captures(l));
+    let message = location
+        .as_ref()
+        .map(|cap| cap.name("message").unwrap().as_str())
+        .unwrap_or("");
+    let panic = output.lines().find(|l| l.starts_with("panic: "));
+    let result = match test.action.as_deref() {
+        Some("pass") | Some("bench") => TestResult::success(),
+        Some("skip") => TestResult::skipped(message),
+        Some("fail") => match panic {
+            Some(panic) => TestResult::error(panic),
+            // the failures of the subtests are already reported by their own test cases
+            None if location.is_none() && subtests_failed => TestResult::success(),
+            None => TestResult::failure(message),
+        },
+        // the test binary exited before the test finished, e.g. a panic or a timeout
+        _ => TestResult::error(panic.unwrap_or("test did not complete")),
+    };
+    let time = match (test.start, test.end) {
+        (Some(start), Some(end)) => end.signed_duration_since(start),
+        _ => test
+            .elapsed
+            .map(|e| Duration::milliseconds((e * 1000.0) as i64))
+            .unwrap_or_else(Duration::zero),
+    };
+    let case = TestCase::new_with_output(
+        &test.name,
+        package,
+        &result,
+        Cow::Owned(output.clone()),
+        Cow::Borrowed(""),
+        time,
+    );
+    match location {
+        Some(cap) => case.with_location(
+            cap.name("file").unwrap().as_str(),
+            cap.name("line").unwrap().as_str().parse().ok(),
+        ),
+        None => case,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gotest::read_gotest;
+    use crate::reports::TestResult;
+    use chrono::Duration;
+    use std::io::{BufReader, Cursor};
+
+    #[test]
+    fn packages() {
+        let input = include_str!("../../test/gotest/packages.json");
+        let mut reader = BufReader::new(Cursor::new(input.as_bytes()));
+        let suites = read_gotest(&mut reader).unwrap();
+        assert_eq!(suites.len(), 2);
+
+        let suite = &suites[0];
+        assert_eq!(suite.name(), "example.com/calc");
+        assert!(suite.timestamp().is_some());
+        assert_eq!(suite.test_count(), 5);
+        // TestDivide only failed because TestDivide/by_zero did
+        assert_eq!(suite.failure_count(), 1);
+        assert_eq!(suite.skipped_count(), 1);
+        assert_eq!(suite.error_count(), 0);
+        let names: Vec<&str> = suite.cases().iter().map(|c| c.name()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "TestAdd",
+                "TestDivide",
+                "TestDivide/by_zero",
+                "TestDivide/by_one",
+                "TestNetwork"
+            ]
+        );
+        let add = &suite.cases()[0];
+        assert_eq!(add.class(), "example.com/calc");
+        assert_eq!(add.time(), Duration::milliseconds(1500));
+        assert_eq!(suite.cases()[1].result(), &TestResult::success());
+        let by_zero = &suite.cases()[2];
+        assert_eq!(
+            by_zero.result(),
+            &TestResult::failure("expected error, got <nil>")
+        );
+        assert_eq!(by_zero.file(), Some("calc_test.go"));
+        assert_eq!(by_zero.line(), Some(21));
+        assert_eq!(
+            by_zero.stdout(),
+            "    calc_test.go:21: expected error, got <nil>\n"
+        );
+        assert_eq!(
+            suite.cases()[4].result(),
+            &TestResult::skipped("needs network")
+        );
+
+        let suite = &suites[1];
+        assert_eq!(suite.name(), "example.com/calc/parse");
+        assert_eq!(suite.test_count(), 2);
+        assert_eq!(suite.error_count(), 2);
+        assert_eq!(
+            suite.cases()[0].result(),
+            &TestResult::error(
+                "panic: runtime error: index out of range [1] with length 1 [recovered]"
+            )
+        );
+        assert_eq!(
+            suite.cases()[1].result(),
+            &TestResult::error("test did not complete")
+        );
+    }
+
+    #[test]
+    fn build_failed() {
+        let input = include_str!("../../test/gotest/build-failed.json");
+        let mut reader = BufReader::new(Cursor::new(input.as_bytes()));
+        let suites = read_gotest(&mut reader).unwrap();
+        assert_eq!(suites.len(), 1);
+        assert_eq!(suites[0].test_count(), 1);
+        assert_eq!(
+            suites[0].cases()[0].result(),
+            &TestResult::error("FAIL\texample.com/broken [build failed]")
+        );
+    }
+}
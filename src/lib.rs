@@ -15,6 +15,9 @@
 #[macro_use]
 extern crate log;
 
+pub mod gotest;
+pub mod libtest;
+pub mod python;
 pub mod reports;
 pub mod streams;
 pub mod suite;
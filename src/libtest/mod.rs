@@ -0,0 +1,405 @@
+/*
+ * Copyright (c) 2020 Stephen Connolly and CloudBees, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use crate::reports::{TestCase, TestResult, TestSuite};
+use chrono::Duration;
+use regex::Regex;
+use serde::Deserialize;
+use std::borrow::Cow;
+use std::io::BufRead;
+
+/// An event of libtest's `--format json` output
+#[derive(Debug, Deserialize)]
+struct TestEvent {
+    #[serde(rename = "type")]
+    type_: String,
+    event: String,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    stdout: Option<String>,
+    #[serde(default)]
+    message: Option<String>,
+    #[serde(default)]
+    exec_time: Option<f64>,
+}
+
+/// The tests of a single test binary
+struct Binary {
+    name: Option<String>,
+    started: bool,
+    tests: Vec<LibTest>,
+}
+
+struct LibTest {
+    name: String,
+    status: String,
+    message: Option<String>,
+    stdout: String,
+    time: Duration,
+    bench: Option<String>,
+}
+
+/// The patterns that are matched against each test
+struct CasePatterns {
+    doc_test: Regex,
+    panic: Regex,
+    legacy_panic: Regex,
+}
+
+/// Parses the output of `cargo test`, in either libtest's human readable format or its JSON
+/// format, into a test suite per test binary.
+///
+/// The suites are named after the `Running` and `Doc-tests` lines that cargo writes to STDERR,
+/// e.g. `juxr/src/lib.rs` or `juxr/doc-tests`, when these are included in the input. Otherwise
+/// the suites are named `{name}-1`, `{name}-2`, etc. in the order that the binaries ran.
+pub fn read_libtest<'a, R: BufRead>(
+    input: &'_ mut R,
+    name: &'_ str,
+) -> anyhow::Result<Vec<TestSuite<'a>>> {
+    let running =
+        Regex::new(r"^\s*Running (unittests )?(?P<source>\S+)( \((?P<binary>[^)]+)\))?$").unwrap();
+    let doc_tests = Regex::new(r"^\s*Doc-tests (?P<crate>\S+)$").unwrap();
+    let start = Regex::new(r"^running \d+ tests?$").unwrap();
+    let test = Regex::new(
+        r"^test (?P<name>.+?) \.\.\. ((?P<status>ok|FAILED|ignored)(, (?P<reason>.*?))?( <(?P<time>[0-9.]+)s>)?|bench: +(?P<bench>.+))$",
+    )
+    .unwrap();
+    let captured = Regex::new(r"^---- (?P<name>.+) stdout ----$").unwrap();
+    let hash = Regex::new(r"-[0-9a-f]{16}(\.exe)?$").unwrap();
+    let patterns = CasePatterns {
+        doc_test: Regex::new(r"^(?P<file>.+) - (?P<item>.+) \(line (?P<line>\d+)\)$").unwrap(),
+        // since Rust 1.73 the message follows the location
+        panic: Regex::new(
+            r"(?m)^thread '[^']*' panicked at (?P<file>[^:'\n]+):(?P<line>\d+):\d+:$",
+        )
+        .unwrap(),
+        legacy_panic: Regex::new(
+            r"(?s)thread '[^']*' panicked at '(?P<message>.*?)', (?P<file>[^:\n]+):(?P<line>\d+):\d+",
+        )
+        .unwrap(),
+    };
+
+    let mut binaries: Vec<Binary> = Vec::new();
+    let mut capturing: Option<usize> = None;
+    for line in input.lines() {
+        let line = line?;
+        if line.starts_with('{') {
+            if let Ok(event) = serde_json::from_str::<TestEvent>(&line) {
+                match (event.type_.as_str(), event.event.as_str()) {
+                    ("suite", "started") => start_binary(&mut binaries),
+                    // a timeout is only a warning that the test is slow
+                    ("test", "started") | ("test", "timeout") | ("suite", _) => {}
+                    ("test", status) => {
+                        let binary = current_binary(&mut binaries);
+                        binary.tests.push(LibTest {
+                            name: event.name.unwrap_or_default(),
+                            status: status.to_string(),
+                            message: event.message,
+                            stdout: event.stdout.unwrap_or_default(),
+                            time: seconds(event.exec_time),
+                            bench: None,
+                        });
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+        }
+        if let Some(cap) = running.captures(&line) {
+            let source = cap.name("source").unwrap().as_str();
+            let name = match cap.name("binary") {
+                Some(binary) => format!("{}/{}", crate_name(binary.as_str(), &hash), source),
+                // before cargo 1.56 only the binary was named
+                None => crate_name(source, &hash),
+            };
+            binaries.push(Binary {
+                name: Some(name),
+                started: false,
+                tests: Vec::new(),
+            });
+            capturing = None;
+        } else if let Some(cap) = doc_tests.captures(&line) {
+            binaries.push(Binary {
+                name: Some(format!("{}/doc-tests", cap.name("crate").unwrap().as_str())),
+                started: false,
+                tests: Vec::new(),
+            });
+            capturing = None;
+        } else if start.is_match(&line) {
+            start_binary(&mut binaries);
+            capturing = None;
+        } else if let Some(cap) = test.captures(&line) {
+            let binary = current_binary(&mut binaries);
+            binary.tests.push(LibTest {
+                name: cap
+                    .name("name")
+                    .unwrap()
+                    .as_str()
+                    // benchmark names are padded to line up their results
+                    .trim_end()
+                    .trim_end_matches(" - should panic")
+                    .to_string(),
+                status: match cap.name("status").map(|m| m.as_str()) {
+                    Some("ok") | None => "ok".to_string(),
+                    Some("FAILED") => "failed".to_string(),
+                    _ => "ignored".to_string(),
+                },
+                message: cap.name("reason").map(|m| m.as_str().to_string()),
+                stdout: String::new(),
+                time: seconds(cap.name("time").and_then(|m| m.as_str().parse().ok())),
+                bench: cap.name("bench").map(|m| m.as_str().trim().to_string()),
+            });
+        } else if let Some(cap) = captured.captures(&line) {
+            let name = cap.name("name").unwrap().as_str();
+            capturing = binaries
+                .last()
+                .and_then(|b| b.tests.iter().position(|t| t.name == name));
+        } else if line == "failures:" || line.starts_with("test result: ") {
+            capturing = None;
+        } else if let Some(index) = capturing {
+            let stdout = &mut current_binary(&mut binaries).tests[index].stdout;
+            stdout.push_str(&line);
+            stdout.push('\n');
+        }
+    }
+
+    Ok(binaries
+        .into_iter()
+        .filter(|b| !b.tests.is_empty())
+        .enumerate()
+        .map(|(index, binary)| {
+            let suite_name = binary
+                .name
+                .unwrap_or_else(|| format!("{}-{}", name, index + 1));
+            let mut suite = TestSuite::new(&suite_name);
+            for test in binary.tests {
+                suite = suite.push(to_test_case(&suite_name, test, &patterns));
+            }
+            suite
+        })
+        .collect())
+}
+
+/// starts a binary, unless it has just been named by cargo
+fn start_binary(binaries: &mut Vec<Binary>) {
+    match binaries.last_mut() {
+        Some(binary) if !binary.started => binary.started = true,
+        _ => binaries.push(Binary {
+            name: None,
+            started: true,
+            tests: Vec::new(),
+        }),
+    }
+}
+
+fn current_binary(binaries: &mut Vec<Binary>) -> &mut Binary {
+    if binaries.is_empty() {
+        start_binary(binaries);
+    }
+    binaries.last_mut().unwrap()
+}
+
+/// the crate name of a test binary, e.g. `target/debug/deps/juxr-0123456789abcdef`
+fn crate_name(binary: &str, hash: &Regex) -> String {
+    let file = binary.rsplit(['/', '\\']).next().unwrap_or(binary);
+    hash.replace(file, "").to_string()
+}
+
+fn seconds(time: Option<f64>) -> Duration {
+    time.map(|t| Duration::milliseconds((t * 1000.0) as i64))
+        .unwrap_or_else(Duration::zero)
+}
+
+fn to_test_case<'a>(suite: &str, test: LibTest, patterns: &CasePatterns) -> TestCase<'a> {
+    let (class, name, location) = if let Some(cap) = patterns.doc_test.captures(&test.name) {
+        let file = cap.name("file").unwrap().as_str();
+        (
+            file.to_string(),
+            format!(
+                "{} (line {})",
+                cap.name("item").unwrap().as_str(),
+                cap.name("line").unwrap().as_str()
+            ),
+            Some((
+                file.to_string(),
+                cap.name("line").unwrap().as_str().parse().ok(),
+            )),
+        )
+    } else {
+        match test.name.rsplit_once("::") {
+            Some((module, name)) => (module.to_string(), name.to_string(), None),
+            None => (suite.to_string(), test.name.clone(), None),
+        }
+    };
+    let (result, location) = match test.status.as_str() {
+        "ok" => (TestResult::success(), location),
+        "ignored" => (
+            TestResult::skipped(test.message.as_deref().unwrap_or("")),
+            location,
+        ),
+        _ => {
+            let panic = panic_message(&test.stdout, patterns);
+            let message = test
+                .message
+                .as_deref()
+                .or_else(|| panic.as_ref().map(|p| p.0.as_str()))
+                .unwrap_or("")
+                .to_string();
+            (
+                TestResult::failure(&message),
+                panic.map(|p| (p.1, p.2)).or(location),
+            )
+        }
+    };
+    let case = TestCase::new_with_output(
+        &name,
+        &class,
+        &result,
+        Cow::Owned(test.stdout),
+        Cow::Borrowed(""),
+        test.time,
+    );
+    let case = match test.bench {
+        Some(bench) => case.with_property("bench", &bench),
+        None => case,
+    };
+    match location {
+        Some((file, line)) => case.with_location(&file, line),
+        None => case,
+    }
+}
+
+/// extracts the message and location of the panic from the captured output of a test
+fn panic_message(stdout: &str, patterns: &CasePatterns) -> Option<(String, String, Option<u32>)> {
+    if let Some(cap) = patterns.panic.captures(stdout) {
+        let message: Vec<&str> = stdout[cap.get(0).unwrap().end()..]
+            .lines()
+            .skip(1)
+            .take_while(|l| !l.starts_with("note: ") && !l.starts_with("stack backtrace:"))
+            .collect();
+        Some((
+            message.join("\n").trim_end().to_string(),
+            cap.name("file").unwrap().as_str().to_string(),
+            cap.name("line").unwrap().as_str().parse().ok(),
+        ))
+    } else {
+        patterns.legacy_panic.captures(stdout).map(|cap| {
+            (
+                cap.name("message").unwrap().as_str().to_string(),
+                cap.name("file").unwrap().as_str().to_string(),
+                cap.name("line").unwrap().as_str().parse().ok(),
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::libtest::read_libtest;
+    use crate::reports::TestResult;
+    use chrono::Duration;
+    use std::io::{BufReader, Cursor};
+
+    #[test]
+    fn human() {
+        let input = include_str!("../../test/libtest/human.txt");
+        let mut reader = BufReader::new(Cursor::new(input.as_bytes()));
+        let suites = read_libtest(&mut reader, "cargo").unwrap();
+        assert_eq!(suites.len(), 2);
+
+        let suite = &suites[0];
+        assert_eq!(suite.name(), "calc/src/lib.rs");
+        assert_eq!(suite.test_count(), 4);
+        assert_eq!(suite.failure_count(), 2);
+        assert_eq!(suite.skipped_count(), 1);
+        let add = &suite.cases()[0];
+        assert_eq!(add.name(), "add");
+        assert_eq!(add.class(), "tests");
+        assert_eq!(add.time(), Duration::milliseconds(12));
+        let divide = &suite.cases()[1];
+        assert_eq!(
+            divide.result(),
+            &TestResult::failure("assertion `left == right` failed\n  left: 1\n right: 2")
+        );
+        assert_eq!(divide.file(), Some("src/lib.rs"));
+        assert_eq!(divide.line(), Some(21));
+        assert!(divide.stdout().starts_with("dividing 1 by 0\nthread"));
+        assert_eq!(
+            suite.cases()[2].result(),
+            &TestResult::skipped("needs network")
+        );
+        assert_eq!(suite.cases()[3].name(), "overflows");
+        assert_eq!(
+            suite.cases()[3].result(),
+            &TestResult::failure("attempt to add with overflow")
+        );
+
+        let suite = &suites[1];
+        assert_eq!(suite.name(), "calc/doc-tests");
+        assert_eq!(suite.test_count(), 1);
+        let doc = &suite.cases()[0];
+        assert_eq!(doc.class(), "src/lib.rs");
+        assert_eq!(doc.name(), "add (line 3)");
+        assert_eq!(doc.line(), Some(3));
+    }
+
+    #[test]
+    fn bench() {
+        let input = include_str!("../../test/libtest/bench.txt");
+        let mut reader = BufReader::new(Cursor::new(input.as_bytes()));
+        let suites = read_libtest(&mut reader, "cargo").unwrap();
+        assert_eq!(suites.len(), 2);
+
+        let suite = &suites[1];
+        assert_eq!(suite.name(), "arithmetic/benches/arithmetic.rs");
+        assert_eq!(suite.test_count(), 2);
+        assert_eq!(suite.failure_count(), 0);
+        let divide = &suite.cases()[1];
+        assert_eq!(divide.name(), "bench_divide");
+        assert_eq!(divide.result(), &TestResult::success());
+        assert_eq!(
+            divide.properties(),
+            &[("bench".into(), "1,234 ns/iter (+/- 56)".into())]
+        );
+    }
+
+    #[test]
+    fn json() {
+        let input = include_str!("../../test/libtest/json.txt");
+        let mut reader = BufReader::new(Cursor::new(input.as_bytes()));
+        let suites = read_libtest(&mut reader, "cargo").unwrap();
+        assert_eq!(suites.len(), 2);
+        assert_eq!(suites[0].name(), "cargo-1");
+        assert_eq!(suites[1].name(), "cargo-2");
+
+        let suite = &suites[0];
+        assert_eq!(suite.test_count(), 4);
+        assert_eq!(suite.failure_count(), 2);
+        assert_eq!(suite.skipped_count(), 1);
+        assert_eq!(suite.cases()[0].time(), Duration::milliseconds(2));
+        let divide = &suite.cases()[1];
+        assert_eq!(
+            divide.result(),
+            &TestResult::failure("attempt to divide by zero")
+        );
+        assert_eq!(divide.file(), Some("src/lib.rs"));
+        assert_eq!(divide.line(), Some(9));
+        assert_eq!(
+            suite.cases()[2].result(),
+            &TestResult::failure("test did not panic as expected")
+        );
+        assert_eq!(suite.cases()[3].result(), &TestResult::skipped(""));
+    }
+}
@@ -17,23 +17,34 @@ extern crate log;
 
 use std::cell::RefCell;
 use std::fs::File;
-use std::io::{copy, stderr, stdin, stdout, BufRead, BufReader, BufWriter, Write};
+use std::io::{copy, stderr, stdin, stdout, BufRead, BufReader, BufWriter, Cursor, Read, Write};
 use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
 use std::str::FromStr;
+use std::sync::mpsc;
 use std::{env, fs, process, thread};
 
 use base64::read::DecoderReader;
 use base64::write::EncoderWriter;
+use chrono::{DateTime, Utc};
 use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 use pretty_env_logger::env_logger::DEFAULT_FILTER_ENV;
 use xml::EventWriter;
 
-use juxr::reports::{pretty_xml_output, ReportProcessor, TestSuite};
+use juxr::gotest::read_gotest;
+use juxr::libtest::read_libtest;
+use juxr::python::read_python;
+use juxr::reports::{
+    pretty_xml_output, read_json, read_junit, validate_junit, write_annotations, write_json,
+    AnnotationFormat, FlakyDetector, FlakyMark, HtmlReport, IncrementalReport, MarkdownSummary,
+    ReportProcessor, RerunMerger, TestCase, TestSuite,
+};
 use juxr::streams::TrimFilterReader;
 use juxr::streams::{EmbeddedStreams, Needle};
 use juxr::suite;
-use juxr::tap::read_tap;
+use juxr::tap::{
+    write_tap, TapDirectiveResult, TapListener, TapMode, TapOutput, TapParser, TapTiming,
+};
 
 fn main() {
     let args = LocalizedArgs::new();
@@ -58,6 +69,16 @@ fn main() {
         "test" => test(subcommand_args),
         "run" => run(subcommand_args),
         "tap" => tap(subcommand_args),
+        "gotest" => gotest(subcommand_args),
+        "cargo" => cargo(subcommand_args),
+        "python" => python(subcommand_args),
+        "flaky" => flaky(subcommand_args),
+        "merge" => merge(subcommand_args),
+        "validate" => validate(subcommand_args),
+        "html" => html(subcommand_args),
+        "convert" => convert(subcommand_args),
+        "summary" => summary(subcommand_args),
+        "annotate" => annotate(subcommand_args),
         _ => 1,
     });
 }
@@ -67,75 +88,814 @@ fn tap(args: &ArgMatches) -> i32 {
     let dir = output_dir(args);
     let suite = args.value_of("name").expect("Name provided").to_string();
     println!("Running {}", suite);
-    let (suite_results, status) = if let Some(command) = args.values_of_lossy("command") {
+    let parser = TapParser::new()
+        .timing(match args.value_of("timing") {
+            Some("recorded") => TapTiming::Recorded,
+            _ => TapTiming::WallClock,
+        })
+        .mode(if args.is_present("strict") {
+            TapMode::Strict
+        } else {
+            TapMode::Lenient
+        })
+        .todo_failed(directive_result(args.value_of("todo_failed")))
+        .todo_passed(directive_result(args.value_of("todo_passed")))
+        .skip(directive_result(args.value_of("skip")));
+    let tee = args.is_present("tee");
+    let interval = args.value_of("flush_interval").unwrap_or("10");
+    let interval = match u64::from_str(interval) {
+        Ok(0) => {
+            error!("Invalid flush interval 0: the interval must be at least 1 second");
+            return 1;
+        }
+        Ok(interval) => std::time::Duration::from_secs(interval),
+        Err(e) => {
+            error!("Invalid flush interval {}: {:?}", interval, e);
+            return 1;
+        }
+    };
+    let mut progress = TapProgress {
+        dir: dir.clone(),
+        name: suite.clone(),
+        progress: args.is_present("progress"),
+        interval,
+        reports: Vec::new(),
+    };
+    let start = Utc::now();
+    let (suites, status) = match parse_tap_output(args, tee, |output| {
+        parser.parse_output_with(output, &mut progress)
+    }) {
+        Ok(parsed) => parsed,
+        Err(code) => return code,
+    };
+
+    let suites: Vec<TestSuite> = match suites {
+        Ok(suites) => suites
+            .into_iter()
+            .map(|suite| started(suite, start))
+            .collect(),
+        Err(e) => {
+            error!("Could not parse TAP results {:?}", e);
+            return 11;
+        }
+    };
+
+    // stop writing the partial reports before the final reports replace them
+    let partial: Vec<PathBuf> = progress
+        .reports
+        .drain(..)
+        .map(|report| report.path().to_path_buf())
+        .collect();
+    if suites.len() > 1 {
+        // the partial report of the first stream was written before there were others
+        if let Some(path) = partial.first() {
+            let _ = fs::remove_file(path);
+        }
+    }
+    // a report for each stream when the input has more than one
+    let exit_code = match write_reports(&dir, &suites, |suite_results| {
+        if suites.len() == 1 {
+            suite.clone()
+        } else {
+            format!("{}-{}", &suite, suite_results.name())
+        }
+    }) {
+        Ok(exit_code) => exit_code,
+        Err(e) => {
+            error!("Could not write test results: {:?}", e);
+            return 11;
+        }
+    };
+    if args.is_present("ignore_failures") {
+        0
+    } else if status > 0 {
+        status
+    } else {
+        exit_code
+    }
+}
+
+/// prints the tests of the TAP streams as they are read and keeps a partial report of each stream
+/// on disk, so that a run that is killed still leaves the results of the tests that completed
+struct TapProgress {
+    dir: PathBuf,
+    name: String,
+    progress: bool,
+    interval: std::time::Duration,
+    reports: Vec<IncrementalReport>,
+}
+
+impl TapListener for TapProgress {
+    fn test_point(&mut self, _stream: &str, case: &TestCase<'static>) {
+        if self.progress {
+            println!("{}", case.as_progress_str());
+        }
+    }
+
+    fn test_case(&mut self, stream: &str, case: &TestCase<'static>) {
+        if self.reports.last().map(|r| r.name()) != Some(stream) {
+            // the previous stream is complete
+            if let Some(report) = self.reports.last_mut() {
+                if let Err(e) = report.flush() {
+                    warn!("Could not write partial test results: {:?}", e);
+                }
+            }
+            // the streams after the first are only named when there is more than one
+            let file_name = match self.reports.len() {
+                0 => format!("TEST-{}.xml", &self.name),
+                _ => format!("TEST-{}-{}.xml", &self.name, stream),
+            };
+            self.reports.push(
+                IncrementalReport::new(
+                    &self.dir.join(file_name),
+                    started(TestSuite::new(stream), Utc::now()),
+                )
+                .interval(self.interval),
+            );
+        }
+        if let Some(report) = self.reports.last_mut() {
+            if let Err(e) = report.push(case.clone()) {
+                warn!("Could not write partial test results: {:?}", e);
+            }
+        }
+    }
+}
+
+fn directive_result(value: Option<&str>) -> TapDirectiveResult {
+    match value {
+        Some("failure") => TapDirectiveResult::Failure,
+        Some("skipped") => TapDirectiveResult::Skipped,
+        _ => TapDirectiveResult::Success,
+    }
+}
+
+/// runs a command or parses STDIN for `go test -json` events
+fn gotest(args: &ArgMatches) -> i32 {
+    let dir = output_dir(args);
+    let (suites, status) = match parse_output(args, |reader| read_gotest(reader)) {
+        Ok(parsed) => parsed,
+        Err(code) => return code,
+    };
+    let suites = match suites {
+        Ok(suites) => suites,
+        Err(e) => {
+            error!("Could not parse go test events {:?}", e);
+            return 11;
+        }
+    };
+    let exit_code = match write_suites(&dir, &suites) {
+        Ok(exit_code) => exit_code,
+        Err(e) => {
+            error!("Could not write test results: {:?}", e);
+            return 11;
+        }
+    };
+    if args.is_present("ignore_failures") {
+        0
+    } else if status > 0 {
+        status
+    } else {
+        exit_code
+    }
+}
+
+/// runs a command or parses STDIN for the output of `cargo test`
+fn cargo(args: &ArgMatches) -> i32 {
+    let dir = output_dir(args);
+    let name = args.value_of("name").unwrap_or("cargo");
+    let (suites, status) = match parse_output(args, |reader| read_libtest(reader, name)) {
+        Ok(parsed) => parsed,
+        Err(code) => return code,
+    };
+    let suites = match suites {
+        Ok(suites) => suites,
+        Err(e) => {
+            error!("Could not parse cargo test output {:?}", e);
+            return 11;
+        }
+    };
+    let exit_code = match write_suites(&dir, &suites) {
+        Ok(exit_code) => exit_code,
+        Err(e) => {
+            error!("Could not write test results: {:?}", e);
+            return 11;
+        }
+    };
+    if args.is_present("ignore_failures") {
+        0
+    } else if status > 0 {
+        status
+    } else {
+        exit_code
+    }
+}
+
+/// runs a command or parses STDIN for the output of pytest or unittest
+fn python(args: &ArgMatches) -> i32 {
+    let dir = output_dir(args);
+    let name = args.value_of("name").expect("Name provided");
+    println!("Running {}", name);
+    let (suite_results, status) = match parse_output(args, |reader| read_python(reader, name)) {
+        Ok(parsed) => parsed,
+        Err(code) => return code,
+    };
+    let suite_results = match suite_results {
+        Ok(suite_results) => suite_results,
+        Err(e) => {
+            error!("Could not parse Python test output {:?}", e);
+            return 11;
+        }
+    };
+    let exit_code = match write_suites(&dir, &[suite_results]) {
+        Ok(exit_code) => exit_code,
+        Err(e) => {
+            error!("Could not write test results: {:?}", e);
+            return 11;
+        }
+    };
+    if args.is_present("ignore_failures") {
+        0
+    } else if status > 0 {
+        status
+    } else {
+        exit_code
+    }
+}
+
+/// writes a `TEST-{suite}.xml` report for each suite, returning the exit code for the results
+fn write_suites(dir: &Path, suites: &[TestSuite<'_>]) -> anyhow::Result<i32> {
+    // package and source paths contain slashes
+    write_reports(dir, suites, |suite| suite.name().replace('/', "."))
+}
+
+/// writes a `TEST-{report}.xml` report for each suite with the name given by the function,
+/// returning the exit code for the results
+fn write_reports<F>(dir: &Path, suites: &[TestSuite<'_>], report: F) -> anyhow::Result<i32>
+where
+    F: Fn(&TestSuite<'_>) -> String,
+{
+    let mut exit_code = 0;
+    for suite_results in suites {
+        println!("{}", suite_results.as_end_str());
+        let path = dir.join(Path::new(
+            format!("TEST-{}.xml", report(suite_results)).as_str(),
+        ));
+        let file = File::create(&path)?;
+        suite_results.write(&mut EventWriter::new_with_config(file, pretty_xml_output()))?;
+        exit_code = exit_code.max(suite_results.as_exit_code());
+    }
+    Ok(exit_code)
+}
+
+/// runs the command of the args, parsing its STDOUT and STDERR merged in the order that the lines
+/// are read, or parses STDIN if there is no command.
+/// Returns the parsed result and the exit code of the command, or the exit code to fail with if
+/// the command could not be run.
+fn parse_output<T, F>(args: &ArgMatches, parse: F) -> Result<(anyhow::Result<T>, i32), i32>
+where
+    F: for<'r> FnOnce(&mut Box<dyn BufRead + 'r>) -> anyhow::Result<T>,
+{
+    parse_tap_output(args, false, |output| {
+        let mut reader: Box<dyn BufRead + '_> = Box::new(BufReader::new(OutputReader {
+            output,
+            line: Cursor::new(Vec::new()),
+        }));
+        parse(&mut reader)
+    })
+}
+
+/// reads the lines of the output of a command as a single stream of text
+struct OutputReader<'o> {
+    output: &'o mut dyn Iterator<Item = TapOutput>,
+    line: Cursor<Vec<u8>>,
+}
+
+impl Read for OutputReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            let read = self.line.read(buf)?;
+            if read > 0 || buf.is_empty() {
+                return Ok(read);
+            }
+            match self.output.next() {
+                Some(TapOutput::Stdout(line)) | Some(TapOutput::Stderr(line)) => {
+                    self.line = Cursor::new((line + "\n").into_bytes())
+                }
+                None => return Ok(0),
+            }
+        }
+    }
+}
+
+/// runs the command with both its STDOUT and STDERR captured, in the order that the lines are
+/// read, or parses STDIN, optionally echoing the lines to the console as they are read
+fn parse_tap_output<T, F>(
+    args: &ArgMatches,
+    tee: bool,
+    parse: F,
+) -> Result<(anyhow::Result<T>, i32), i32>
+where
+    F: FnOnce(&mut dyn Iterator<Item = TapOutput>) -> anyhow::Result<T>,
+{
+    if let Some(command) = args.values_of_lossy("command") {
         let mut child = Command::new(
             command
-                .get(0)
+                .first()
                 .expect("A command to execute has been supplied"),
         );
         if command.len() > 1 {
             let _ = child.args(&command[1..]);
         };
         debug!("Forking {:?}", command);
-        let mut child = match child.stdout(Stdio::piped()).spawn() {
+        let mut child = match child.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn() {
             Err(e) => {
                 error!(
                     "The `{}` command failed to start: {:?}",
                     command.join(" "),
                     e
                 );
-                return 11;
+                return Err(11);
             }
             Ok(child) => child,
         };
-        let result = {
-            let mut child_stdout = child.stdout.as_mut().unwrap();
-            let mut reader = BufReader::new(&mut child_stdout);
-            read_tap(&mut reader)
-        };
+        let (sender, receiver) = mpsc::channel();
+        let child_stdout = BufReader::new(child.stdout.take().unwrap());
+        let out_sender = sender.clone();
+        let out_reader = thread::spawn(move || {
+            // lines that are not valid UTF-8 are skipped
+            #[allow(clippy::lines_filter_map_ok)]
+            for line in child_stdout.lines().flat_map(|l| l.ok()) {
+                if tee {
+                    println!("{}", line);
+                }
+                // keep reading after the parser has finished so the command is not blocked
+                let _ = out_sender.send(TapOutput::Stdout(line));
+            }
+        });
+        let child_stderr = BufReader::new(child.stderr.take().unwrap());
+        let err_reader = thread::spawn(move || {
+            // lines that are not valid UTF-8 are skipped
+            #[allow(clippy::lines_filter_map_ok)]
+            for line in child_stderr.lines().flat_map(|l| l.ok()) {
+                if tee {
+                    eprintln!("{}", line);
+                }
+                // keep reading after the parser has finished so the command is not blocked
+                let _ = sender.send(TapOutput::Stderr(line));
+            }
+        });
+        let result = parse(&mut receiver.iter());
+        drop(receiver);
         let status = match child.wait() {
             Err(e) => {
                 error!("The `{}`command didn't start: {:?}", command.join(" "), e);
-                return 11;
+                return Err(11);
             }
             Ok(status) => status,
         };
-        (result, status.code().unwrap_or(0))
+        out_reader.join().unwrap_or_default();
+        err_reader.join().unwrap_or_default();
+        Ok((result, status.code().unwrap_or(0)))
     } else {
-        let stdin = stdin();
-        let mut lock = stdin.lock();
-        (read_tap(&mut lock), 0)
-    };
+        let input = stdin();
+        // lines that are not valid UTF-8 are skipped
+        #[allow(clippy::lines_filter_map_ok)]
+        let mut lines = input.lock().lines().flat_map(|l| l.ok()).map(|line| {
+            if tee {
+                println!("{}", line);
+            }
+            TapOutput::Stdout(line)
+        });
+        Ok((parse(&mut lines), 0))
+    }
+}
 
-    let suite_results = match suite_results {
-        Ok(suite_results) => suite_results,
-        Err(e) => {
-            error!("Could not parse TAP results {:?}", e);
-            return 11;
+/// analyses multiple runs of the same reports for tests with varying results
+fn flaky(args: &ArgMatches) -> i32 {
+    let mut detector = FlakyDetector::new();
+    if let Some(min_runs) = args.value_of("min_runs") {
+        match usize::from_str(min_runs) {
+            Ok(min_runs) => detector = detector.min_runs(min_runs),
+            Err(e) => {
+                error!("Invalid minimum number of runs {}: {:?}", min_runs, e);
+                return 1;
+            }
         }
+    }
+    if let Some(min_rate) = args.value_of("min_rate") {
+        match f64::from_str(min_rate) {
+            Ok(min_rate) => detector = detector.min_rate(min_rate),
+            Err(e) => {
+                error!("Invalid minimum flake rate {}: {:?}", min_rate, e);
+                return 1;
+            }
+        }
+    }
+    let mut latest = Vec::new();
+    for run_glob in args.values_of("runs").unwrap_or_default() {
+        let (suites, reports) = match read_run(run_glob) {
+            Ok(run) => run,
+            Err(e) => {
+                error!("Could not read reports of run {}: {:?}", run_glob, e);
+                return 1;
+            }
+        };
+        detector.add_run(&suites);
+        latest = reports;
+    }
+    let flaky = detector.flaky();
+    println!(
+        "Runs analysed: {}, Flaky tests: {}",
+        detector.runs(),
+        flaky.len()
+    );
+    for test in &flaky {
+        println!("{}", test.as_str());
+    }
+    let mark = match args.value_of("mark") {
+        Some("property") => FlakyMark::Property,
+        Some("skipped") => FlakyMark::Skipped,
+        _ => return 0,
     };
+    let dir = output_dir(args);
+    let cwd = env::current_dir().unwrap_or_default();
+    for report in latest {
+        let content = match fs::read(&report) {
+            Ok(content) => content,
+            Err(e) => {
+                error!("Could not read report {}: {:?}", report.display(), e);
+                return 1;
+            }
+        };
+        let report = report.canonicalize().unwrap_or(report);
+        let path = dir.join(report.strip_prefix(&cwd).unwrap_or(&report));
+        if let Some(parent) = path.parent() {
+            if let Err(e) = fs::create_dir_all(parent) {
+                error!("Could not create directory {}: {:?}", parent.display(), e);
+                return 1;
+            }
+        }
+        let result = File::create(&path)
+            .map_err(|e| e.into())
+            .and_then(|mut file| detector.mark(Cursor::new(content), &mut file, mark));
+        match result {
+            Ok(count) => debug!("Marked {} flaky tests in {}", count, path.display()),
+            Err(e) => {
+                error!("Could not write report {}: {:?}", path.display(), e);
+                return 1;
+            }
+        }
+    }
+    0
+}
 
-    println!("{}", suite_results.as_end_str());
-
-    let path = dir.join(Path::new(format!("TEST-{}.xml", &suite).as_str()));
-    let file = File::create(&path).unwrap();
-    if let Err(e) =
-        suite_results.write(&mut EventWriter::new_with_config(file, pretty_xml_output()))
-    {
-        error!("Could not write test results: {:?}", e);
-        return 11;
-    };
+/// merges the reports of an original run and its retries into one result per test
+fn merge(args: &ArgMatches) -> i32 {
+    let dir = output_dir(args);
+    let mut merger = RerunMerger::new();
+    for run_glob in args.values_of("runs").unwrap_or_default() {
+        match read_run(run_glob) {
+            Ok((suites, _)) => merger.add_run(&suites),
+            Err(e) => {
+                error!("Could not read reports of run {}: {:?}", run_glob, e);
+                return 1;
+            }
+        }
+    }
+    let mut exit_code = 0;
+    for suite_results in merger.suites() {
+        println!("{}", suite_results.as_end_str());
+        let path = dir.join(Path::new(
+            format!("TEST-{}.xml", suite_results.name()).as_str(),
+        ));
+        let file = match File::create(&path) {
+            Ok(file) => file,
+            Err(e) => {
+                error!(
+                    "Could not write test results to {}: {:?}",
+                    path.display(),
+                    e
+                );
+                return 1;
+            }
+        };
+        if let Err(e) =
+            suite_results.write(&mut EventWriter::new_with_config(file, pretty_xml_output()))
+        {
+            error!(
+                "Could not write test results to {}: {:?}",
+                path.display(),
+                e
+            );
+            return 1;
+        };
+        if suite_results.as_exit_code() != 0 {
+            exit_code = 1
+        }
+    }
     if args.is_present("ignore_failures") {
         0
-    } else if status > 0 {
-        status
     } else {
-        suite_results.as_exit_code()
+        exit_code
     }
 }
 
+/// checks reports for problems that would stop consumers from reading them correctly
+fn validate(args: &ArgMatches) -> i32 {
+    let mut reports = 0;
+    let mut errors = 0;
+    let mut warnings = 0;
+    for report_glob in args.values_of("reports").unwrap_or_default() {
+        for report in globwalk::glob(report_glob).unwrap().flatten() {
+            let path = report.path();
+            reports += 1;
+            let diagnostics = match File::open(path)
+                .map_err(|e| e.into())
+                .and_then(validate_junit)
+            {
+                Ok(diagnostics) => diagnostics,
+                Err(e) => {
+                    error!("Could not read report {}: {:?}", path.display(), e);
+                    errors += 1;
+                    continue;
+                }
+            };
+            for diagnostic in diagnostics {
+                println!("{}: {}", path.display(), diagnostic);
+                if diagnostic.is_error() {
+                    errors += 1;
+                } else {
+                    warnings += 1;
+                }
+            }
+        }
+    }
+    println!(
+        "Reports validated: {}, Errors: {}, Warnings: {}",
+        reports, errors, warnings
+    );
+    if errors > 0 || (warnings > 0 && args.is_present("strict")) {
+        1
+    } else {
+        0
+    }
+}
+
+/// renders reports as a self-contained HTML page
+fn html(args: &ArgMatches) -> i32 {
+    let dir = output_dir(args);
+    let mut suites = Vec::new();
+    for report_glob in args.values_of("reports").unwrap_or_default() {
+        match read_run(report_glob) {
+            Ok((mut report_suites, _)) => suites.append(&mut report_suites),
+            Err(e) => {
+                error!("Could not read reports {}: {:?}", report_glob, e);
+                return 1;
+            }
+        }
+    }
+    let mut report = HtmlReport::new().output_dir(&dir);
+    if let Ok(cwd) = env::current_dir() {
+        report = report.base_dir(&cwd.canonicalize().unwrap_or(cwd));
+    }
+    if let Some(title) = args.value_of("title") {
+        report = report.title(title);
+    }
+    let path = dir.join("index.html");
+    let result: anyhow::Result<()> = File::create(&path).map_err(|e| e.into()).and_then(|file| {
+        let mut writer = BufWriter::new(file);
+        report.write(&suites, &mut writer)?;
+        writer.flush()?;
+        Ok(())
+    });
+    if let Err(e) = result {
+        error!("Could not write HTML report to {}: {:?}", path.display(), e);
+        return 1;
+    }
+    println!("HTML report written to {}", path.display());
+    0
+}
+
+/// converts reports between JUnit XML and CTRF JSON
+fn convert(args: &ArgMatches) -> i32 {
+    let dir = output_dir(args);
+    let mut suites = Vec::new();
+    for report_glob in args.values_of("reports").unwrap_or_default() {
+        for report in globwalk::glob(report_glob).unwrap().flatten() {
+            let path = report.path();
+            let json = match args.value_of("from") {
+                Some(from) => from == "json",
+                None => path.extension().is_some_and(|e| e == "json"),
+            };
+            debug!("Reading report: {}", path.display());
+            let result = File::open(path).map_err(|e| e.into()).and_then(|file| {
+                if json {
+                    read_json(file)
+                } else {
+                    read_junit(file)
+                }
+            });
+            match result {
+                Ok(mut report_suites) => suites.append(&mut report_suites),
+                Err(e) => {
+                    error!("Could not read report {}: {:?}", path.display(), e);
+                    return 1;
+                }
+            }
+        }
+    }
+    match args.value_of("to").unwrap_or_default() {
+        "json" => {
+            let path = dir.join("ctrf-report.json");
+            let result: anyhow::Result<()> =
+                File::create(&path).map_err(|e| e.into()).and_then(|file| {
+                    let mut writer = BufWriter::new(file);
+                    write_json(&suites, &mut writer)?;
+                    writer.flush()?;
+                    Ok(())
+                });
+            if let Err(e) = result {
+                error!(
+                    "Could not write test results to {}: {:?}",
+                    path.display(),
+                    e
+                );
+                return 11;
+            }
+            println!(
+                "Converted {} test suites to {}",
+                suites.len(),
+                path.display()
+            );
+        }
+        "tap" => {
+            let path = dir.join("results.tap");
+            let result: anyhow::Result<()> =
+                File::create(&path).map_err(|e| e.into()).and_then(|file| {
+                    let mut writer = BufWriter::new(file);
+                    write_tap(&suites, &mut writer)?;
+                    writer.flush()?;
+                    Ok(())
+                });
+            if let Err(e) = result {
+                error!(
+                    "Could not write test results to {}: {:?}",
+                    path.display(),
+                    e
+                );
+                return 11;
+            }
+            println!(
+                "Converted {} test suites to {}",
+                suites.len(),
+                path.display()
+            );
+        }
+        _ => {
+            for suite_results in &suites {
+                let path = dir.join(Path::new(
+                    format!("TEST-{}.xml", suite_results.name()).as_str(),
+                ));
+                let file = match File::create(&path) {
+                    Ok(file) => file,
+                    Err(e) => {
+                        error!(
+                            "Could not write test results to {}: {:?}",
+                            path.display(),
+                            e
+                        );
+                        return 1;
+                    }
+                };
+                if let Err(e) = suite_results
+                    .write(&mut EventWriter::new_with_config(file, pretty_xml_output()))
+                {
+                    error!(
+                        "Could not write test results to {}: {:?}",
+                        path.display(),
+                        e
+                    );
+                    return 1;
+                };
+                println!("{}", suite_results.as_end_str());
+            }
+        }
+    }
+    0
+}
+
+/// renders reports as a Markdown summary
+fn summary(args: &ArgMatches) -> i32 {
+    let mut suites = Vec::new();
+    for report_glob in args.values_of("reports").unwrap_or_default() {
+        match read_run(report_glob) {
+            Ok((mut report_suites, _)) => suites.append(&mut report_suites),
+            Err(e) => {
+                error!("Could not read reports {}: {:?}", report_glob, e);
+                return 1;
+            }
+        }
+    }
+    let mut markdown = MarkdownSummary::new();
+    if let Some(title) = args.value_of("title") {
+        markdown = markdown.title(title);
+    }
+    if let Some(lines) = args.value_of("max_output_lines") {
+        match usize::from_str(lines) {
+            Ok(lines) => markdown = markdown.max_output_lines(lines),
+            Err(e) => {
+                error!("Invalid maximum number of output lines {}: {:?}", lines, e);
+                return 1;
+            }
+        }
+    }
+    let result = match args.value_of_os("append") {
+        Some(path) => fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| e.into())
+            .and_then(|file| {
+                let mut writer = BufWriter::new(file);
+                markdown.write(&suites, &mut writer)?;
+                writer.flush()?;
+                Ok(())
+            }),
+        None => markdown.write(&suites, &mut stdout().lock()),
+    };
+    if let Err(e) = result {
+        error!("Could not write summary: {:?}", e);
+        return 1;
+    }
+    0
+}
+
+/// prints failures as annotations for CI systems to show inline in the diff view
+fn annotate(args: &ArgMatches) -> i32 {
+    let mut suites = Vec::new();
+    for report_glob in args.values_of("reports").unwrap_or_default() {
+        match read_run(report_glob) {
+            Ok((mut report_suites, _)) => suites.append(&mut report_suites),
+            Err(e) => {
+                error!("Could not read reports {}: {:?}", report_glob, e);
+                return 1;
+            }
+        }
+    }
+    let format = match args.value_of("format") {
+        Some("gitlab") => AnnotationFormat::GitLab,
+        _ => AnnotationFormat::GitHub,
+    };
+    let result = match args.value_of_os("output") {
+        Some(path) => File::create(path).map_err(|e| e.into()).and_then(|file| {
+            let mut writer = BufWriter::new(file);
+            let count = write_annotations(&suites, format, &mut writer)?;
+            writer.flush()?;
+            Ok(count)
+        }),
+        None => write_annotations(&suites, format, &mut stdout().lock()),
+    };
+    match result {
+        Ok(count) => {
+            debug!("Wrote {} annotations", count);
+            0
+        }
+        Err(e) => {
+            error!("Could not write annotations: {:?}", e);
+            1
+        }
+    }
+}
+
+/// reads the test suites from all the reports matching the glob of a single run
+fn read_run<'a>(run_glob: &str) -> anyhow::Result<(Vec<TestSuite<'a>>, Vec<PathBuf>)> {
+    let mut suites = Vec::new();
+    let mut reports = Vec::new();
+    for report in globwalk::glob(run_glob)?.flatten() {
+        let path = report.path().to_path_buf();
+        debug!("Reading report: {}", path.display());
+        let mut report_suites = read_junit(File::open(&path)?)
+            .map_err(|e| anyhow::anyhow!("{}: {:?}", path.display(), e))?;
+        suites.append(&mut report_suites);
+        reports.push(path);
+    }
+    Ok((suites, reports))
+}
+
 fn run(args: &ArgMatches) -> i32 {
     let dir = output_dir(args);
+    let jobs = args.value_of("jobs").unwrap_or("1");
+    let jobs = match usize::from_str(jobs) {
+        Ok(jobs) if jobs > 0 => jobs,
+        Ok(_) => {
+            error!("Invalid number of jobs {}: must be at least 1", jobs);
+            return 1;
+        }
+        Err(e) => {
+            error!("Invalid number of jobs {}: {:?}", jobs, e);
+            return 1;
+        }
+    };
     let mut exit_code = 0;
     for suite_filename in args.values_of("suites").unwrap_or_default() {
         let suite_path = Path::new(suite_filename);
@@ -165,12 +925,10 @@ fn run(args: &ArgMatches) -> i32 {
         };
         let suite_name = suite_path.file_stem().unwrap().to_string_lossy();
 
-        let mut suite_results = TestSuite::new(suite_name.as_ref());
+        let mut suite_results = started(TestSuite::new(suite_name.as_ref()), Utc::now());
         println!("{}", suite_results.as_start_str());
-        for (test_name, test) in suite_tests.iter() {
-            if let Some(test_case) = test.run(suite_name.as_ref(), test_name) {
-                suite_results = suite_results.push(test_case);
-            }
+        for test_case in suite_tests.run(suite_name.as_ref(), jobs) {
+            suite_results = suite_results.push(test_case);
         }
         println!("{}", suite_results.as_end_str());
         let path = dir.join(Path::new(format!("TEST-{}.xml", &suite_name).as_str()));
@@ -196,6 +954,30 @@ fn run(args: &ArgMatches) -> i32 {
     }
 }
 
+/// records when a test suite that juxr runs started and the host running it
+fn started(suite: TestSuite<'_>, timestamp: DateTime<Utc>) -> TestSuite<'_> {
+    let suite = suite.with_timestamp(timestamp);
+    match hostname() {
+        Some(hostname) => suite.with_hostname(&hostname),
+        None => suite,
+    }
+}
+
+#[cfg(unix)]
+fn hostname() -> Option<String> {
+    let mut name = [0u8; 256];
+    if unsafe { libc::gethostname(name.as_mut_ptr() as *mut libc::c_char, name.len()) } != 0 {
+        return None;
+    }
+    let end = name.iter().position(|b| *b == 0).unwrap_or(name.len());
+    Some(String::from_utf8_lossy(&name[..end]).to_string()).filter(|h| !h.is_empty())
+}
+
+#[cfg(not(unix))]
+fn hostname() -> Option<String> {
+    env::var("COMPUTERNAME").ok().filter(|h| !h.is_empty())
+}
+
 fn output_dir(args: &ArgMatches) -> PathBuf {
     let cwd = env::current_dir()
         .map(|d| d.canonicalize().unwrap_or(d))
@@ -213,7 +995,23 @@ fn output_dir(args: &ArgMatches) -> PathBuf {
 }
 
 fn test(args: &ArgMatches) -> i32 {
-    let dir = output_dir(&args);
+    let dir = output_dir(args);
+    let timeout = match args.value_of("timeout").map(u64::from_str) {
+        None => None,
+        Some(Ok(0)) => {
+            error!("Invalid timeout 0: the timeout must be at least 1 second");
+            return 1;
+        }
+        Some(Ok(timeout)) => Some(timeout),
+        Some(Err(e)) => {
+            error!(
+                "Invalid timeout {}: {:?}",
+                args.value_of("timeout").unwrap_or_default(),
+                e
+            );
+            return 1;
+        }
+    };
     let test = suite::PlanTest {
         command: suite::PlanCommand::Exec(
             args.values_of("command")
@@ -233,10 +1031,12 @@ fn test(args: &ArgMatches) -> i32 {
             .values_of("failure")
             .map(|v| v.collect::<Vec<&str>>())
             .map(|v| v.iter().flat_map(|c| i32::from_str(c).ok()).collect()),
+        parallel: None,
+        timeout,
     };
     let name = args.value_of("test").expect("Name provided").to_string();
     let suite = args.value_of("name").expect("Name provided").to_string();
-    let mut suite_results = TestSuite::new(suite.as_ref());
+    let mut suite_results = started(TestSuite::new(suite.as_ref()), Utc::now());
     println!("{}", suite_results.as_start_str());
     if let Some(test_case) = test.run(&suite, &name) {
         suite_results = suite_results.push(test_case);
@@ -270,7 +1070,7 @@ fn exec(args: &ArgMatches) -> i32 {
     let command: Vec<&str> = command.collect();
     let mut child = Command::new(
         command
-            .get(0)
+            .first()
             .expect("A command to execute has been supplied"),
     );
     if command.len() > 1 {
@@ -368,7 +1168,7 @@ fn export(args: &ArgMatches) -> i32 {
 }
 
 fn import(args: &ArgMatches) -> i32 {
-    let dir = output_dir(&args);
+    let dir = output_dir(args);
     let processor = report_processor(args);
     let success = RefCell::new(Some(true));
     EmbeddedStreams::new(stdin().lock(), &mut stdout().lock()).for_each(|stream| {
@@ -439,9 +1239,10 @@ struct LocalizedArgs {
 
 impl LocalizedArgs {
     fn new() -> LocalizedArgs {
-        let prefix = env!("CARGO_PKG_NAME")
-            .to_uppercase()
-            .replace(|c| !((c >= 'A' && c <= 'Z') || (c >= '0' && c <= '9')), "_");
+        let prefix = env!("CARGO_PKG_NAME").to_uppercase().replace(
+            |c: char| !(c.is_ascii_uppercase() || c.is_ascii_digit()),
+            "_",
+        );
         LocalizedArgs {
             reports: format!("{}_REPORTS", prefix),
             secrets: format!("{}_SECRETS", prefix),
@@ -535,6 +1336,12 @@ impl LocalizedArgs {
                     .env(&self.secrets)
                     .help("A comma separated list of environment variable names with values that should be redacted from the report")
             )
+            .arg(
+                Arg::with_name("sanitize")
+                    .long("sanitize")
+                    .takes_value(false)
+                    .help("Replace characters that are illegal in XML 1.0 and invalid UTF-8 in the reports")
+            )
             .arg(
                 Arg::with_name("skip_export")
                     .long("skip-export")
@@ -552,7 +1359,7 @@ impl LocalizedArgs {
             )
     }
 
-    fn get_matches(&self) -> ArgMatches {
+    fn get_matches(&self) -> ArgMatches<'_> {
         App::new(env!("CARGO_PKG_NAME"))
             .version(env!("CARGO_PKG_VERSION"))
             .about(env!("CARGO_PKG_DESCRIPTION"))
@@ -575,6 +1382,12 @@ impl LocalizedArgs {
                             .long("output")
                             .default_value(".")
                             .help("Directory in which to write imported files"),
+                    )
+                    .arg(
+                        Arg::with_name("sanitize")
+                            .long("sanitize")
+                            .takes_value(false)
+                            .help("Replace characters that are illegal in XML 1.0 and invalid UTF-8 in the imported reports"),
                     ),
             )
             .subcommand(
@@ -616,6 +1429,16 @@ impl LocalizedArgs {
                             .required(true)
                             .help("YAML test suite to run and capture the results in JUnit XML format")
                     )
+                    .arg(
+                        Arg::with_name("jobs")
+                            .short("j")
+                            .long("jobs")
+                            .takes_value(true)
+                            .value_name("N")
+                            .default_value("1")
+                            .help("The number of tests to run at the same time, tests marked with \
+                            `parallel: false` run on their own")
+                    )
                     .arg(
                         Arg::with_name("ignore_failures")
                             .long("ignore-failures")
@@ -656,6 +1479,14 @@ impl LocalizedArgs {
                             .value_delimiter(",")
                             .help("A comma separated list of exit codes of the command indicating skipped test")
                     )
+                    .arg(
+                        Arg::with_name("timeout")
+                            .long("timeout")
+                            .takes_value(true)
+                            .value_name("SECONDS")
+                            .help("Kill the command, and on unix any processes it started, if it has \
+                            not finished after this many seconds and record the test as an error")
+                    )
                     .arg(
                         Arg::with_name("test")
                             .short("t")
@@ -710,6 +1541,65 @@ impl LocalizedArgs {
                         .required(true)
                         .help("The name of the test suite")
                 )
+                .arg(
+                    Arg::with_name("timing")
+                        .long("timing")
+                        .takes_value(true)
+                        .possible_values(&["wall-clock", "recorded"])
+                        .default_value("wall-clock")
+                        .help("Measure the time between tests as they are read, or only use the \
+                        times recorded in the stream when replaying a saved TAP file")
+                )
+                .arg(
+                    Arg::with_name("tee")
+                        .long("tee")
+                        .help("Echo the output of the command to the console while it is parsed")
+                )
+                .arg(
+                    Arg::with_name("progress")
+                        .long("progress")
+                        .help("Print the name, result and time of each test as it is read")
+                )
+                .arg(
+                    Arg::with_name("flush_interval")
+                        .long("flush-interval")
+                        .takes_value(true)
+                        .value_name("SECONDS")
+                        .default_value("10")
+                        .help("How often to rewrite the partial report while the tests run, so \
+                        that a run that is killed still leaves a report of the completed tests")
+                )
+                .arg(
+                    Arg::with_name("todo_failed")
+                        .long("todo-failed")
+                        .takes_value(true)
+                        .possible_values(&["success", "failure", "skipped"])
+                        .default_value("success")
+                        .help("How to record a `not ok` test with a TODO directive")
+                )
+                .arg(
+                    Arg::with_name("todo_passed")
+                        .long("todo-passed")
+                        .takes_value(true)
+                        .possible_values(&["success", "failure", "skipped"])
+                        .default_value("failure")
+                        .help("How to record an `ok` test with a TODO directive, a success records \
+                        the reason as a `todo` property")
+                )
+                .arg(
+                    Arg::with_name("skip")
+                        .long("skip")
+                        .takes_value(true)
+                        .possible_values(&["success", "failure", "skipped"])
+                        .default_value("skipped")
+                        .help("How to record a test with a SKIP directive")
+                )
+                .arg(
+                    Arg::with_name("strict")
+                        .long("strict")
+                        .help("Report problems with the TAP stream, such as duplicate test \
+                        numbers or a plan that does not match the tests, as an error")
+                )
                 .arg(
                     Arg::with_name("command")
                         .last(true)
@@ -722,6 +1612,300 @@ impl LocalizedArgs {
                         .help("Test failures/errors will not affect the exit code")
                 )
             )
+            .subcommand(SubCommand::with_name("gotest")
+                .about("Parses the events of `go test -json` into JUnit XML Report format, with a \
+                report for each package. If no command is specified then STDIN will be parsed for \
+                the events otherwise the supplied command will be run and its output parsed")
+                .arg(
+                    Arg::with_name("directory")
+                        .takes_value(true)
+                        .short("o")
+                        .long("output")
+                        .default_value(".")
+                        .help("Directory in which to write the test results")
+                )
+                .arg(
+                    Arg::with_name("command")
+                        .last(true)
+                        .multiple(true)
+                        .help("The command to execute, e.g. go test -json ./..., otherwise input \
+                        will be read from STDIN"),
+                )
+                .arg(
+                    Arg::with_name("ignore_failures")
+                        .long("ignore-failures")
+                        .help("Test failures/errors will not affect the exit code")
+                )
+            )
+            .subcommand(SubCommand::with_name("cargo")
+                .about("Parses the output of `cargo test` into JUnit XML Report format, with a \
+                report for each test binary. Both the human readable and the JSON output of the \
+                test harness are supported. If no command is specified then STDIN will be parsed \
+                otherwise the supplied command will be run and its output parsed")
+                .arg(
+                    Arg::with_name("directory")
+                        .takes_value(true)
+                        .short("o")
+                        .long("output")
+                        .default_value(".")
+                        .help("Directory in which to write the test results")
+                )
+                .arg(
+                    Arg::with_name("name")
+                        .short("n")
+                        .long("name")
+                        .takes_value(true)
+                        .value_name("NAME")
+                        .default_value("cargo")
+                        .help("The name of the test suites when the input does not name the test \
+                        binaries, suffixed with the number of the binary")
+                )
+                .arg(
+                    Arg::with_name("command")
+                        .last(true)
+                        .multiple(true)
+                        .help("The command to execute, e.g. cargo test, otherwise input will be \
+                        read from STDIN"),
+                )
+                .arg(
+                    Arg::with_name("ignore_failures")
+                        .long("ignore-failures")
+                        .help("Test failures/errors will not affect the exit code")
+                )
+            )
+            .subcommand(SubCommand::with_name("python")
+                .about("Parses the verbose output of pytest or unittest into JUnit XML Report \
+                format. If no command is specified then STDIN will be parsed otherwise the \
+                supplied command will be run and its output parsed")
+                .arg(
+                    Arg::with_name("directory")
+                        .takes_value(true)
+                        .short("o")
+                        .long("output")
+                        .default_value(".")
+                        .help("Directory in which to write the test result")
+                )
+                .arg(
+                    Arg::with_name("name")
+                        .short("n")
+                        .long("name")
+                        .takes_value(true)
+                        .value_name("NAME")
+                        .required(true)
+                        .help("The name of the test suite")
+                )
+                .arg(
+                    Arg::with_name("command")
+                        .last(true)
+                        .multiple(true)
+                        .help("The command to execute, e.g. pytest -v -rA, otherwise input will \
+                        be read from STDIN"),
+                )
+                .arg(
+                    Arg::with_name("ignore_failures")
+                        .long("ignore-failures")
+                        .help("Test failures/errors will not affect the exit code")
+                )
+            )
+            .subcommand(SubCommand::with_name("flaky")
+                .about("Analyses multiple runs of the same JUnit XML Reports to identify tests \
+                whose results vary between runs. Optionally the failures of flaky tests in the \
+                reports of the latest run can be marked")
+                .arg(
+                    Arg::with_name("runs")
+                        .multiple(true)
+                        .required(true)
+                        .help("The JUnit XML report files of each run, one glob per run with the \
+                        latest run last, supports * and ** style globs")
+                )
+                .arg(
+                    Arg::with_name("min_runs")
+                        .long("min-runs")
+                        .takes_value(true)
+                        .value_name("COUNT")
+                        .default_value("2")
+                        .help("The minimum number of runs a test must have executed in to be considered flaky")
+                )
+                .arg(
+                    Arg::with_name("min_rate")
+                        .long("min-rate")
+                        .takes_value(true)
+                        .value_name("RATE")
+                        .default_value("0")
+                        .help("The minimum flake rate, between 0 and 1, for a test to be considered flaky")
+                )
+                .arg(
+                    Arg::with_name("mark")
+                        .long("mark")
+                        .takes_value(true)
+                        .possible_values(&["property", "skipped"])
+                        .help("Rewrite the reports of the latest run marking failures of flaky \
+                        tests with a `flaky` property or as skipped")
+                )
+                .arg(
+                    Arg::with_name("directory")
+                        .takes_value(true)
+                        .short("o")
+                        .long("output")
+                        .default_value(".")
+                        .help("Directory in which to write the marked reports")
+                )
+            )
+            .subcommand(SubCommand::with_name("merge")
+                .about("Merges the JUnit XML Reports of an original run and its retries into a \
+                single result per test, recording the failed attempts using surefire's \
+                flakyFailure, flakyError, rerunFailure and rerunError elements")
+                .arg(
+                    Arg::with_name("runs")
+                        .multiple(true)
+                        .required(true)
+                        .help("The JUnit XML report files of each run, one glob per run with the \
+                        original run first, supports * and ** style globs")
+                )
+                .arg(
+                    Arg::with_name("directory")
+                        .takes_value(true)
+                        .short("o")
+                        .long("output")
+                        .default_value(".")
+                        .help("Directory in which to write the merged reports")
+                )
+                .arg(
+                    Arg::with_name("ignore_failures")
+                        .long("ignore-failures")
+                        .help("Test failures/errors will not affect the exit code")
+                )
+            )
+            .subcommand(SubCommand::with_name("validate")
+                .about("Validates JUnit XML Reports against the surefire XSD and the expectations \
+                of common consumers. Exits with a non-zero exit code if any errors are found")
+                .arg(
+                    Arg::with_name("reports")
+                        .multiple(true)
+                        .required(true)
+                        .help("The JUnit XML report file(s) to validate, supports * and ** style globs")
+                )
+                .arg(
+                    Arg::with_name("strict")
+                        .long("strict")
+                        .help("Warnings will also result in a non-zero exit code")
+                )
+            )
+            .subcommand(SubCommand::with_name("html")
+                .about("Renders JUnit XML Reports as a self-contained static HTML report")
+                .arg(
+                    Arg::with_name("directory")
+                        .takes_value(true)
+                        .short("o")
+                        .long("output")
+                        .default_value(".")
+                        .help("Directory in which to write the index.html")
+                )
+                .arg(
+                    Arg::with_name("title")
+                        .long("title")
+                        .takes_value(true)
+                        .help("The title of the HTML report")
+                )
+                .arg(
+                    Arg::with_name("reports")
+                        .multiple(true)
+                        .required(true)
+                        .help("The JUnit XML report file(s) to render, supports * and ** style globs")
+                )
+            )
+            .subcommand(SubCommand::with_name("convert")
+                .about("Converts test reports from JUnit XML or JSON in the Common Test Report Format \
+                (CTRF) to JUnit XML, CTRF JSON or TAP")
+                .arg(
+                    Arg::with_name("directory")
+                        .takes_value(true)
+                        .short("o")
+                        .long("output")
+                        .default_value(".")
+                        .help("Directory in which to write the converted reports")
+                )
+                .arg(
+                    Arg::with_name("to")
+                        .long("to")
+                        .takes_value(true)
+                        .required(true)
+                        .possible_values(&["json", "junit", "tap"])
+                        .help("The format to convert to, JSON is written to ctrf-report.json, \
+                        TAP to results.tap and JUnit XML to a TEST-{suite}.xml file per suite")
+                )
+                .arg(
+                    Arg::with_name("from")
+                        .long("from")
+                        .takes_value(true)
+                        .possible_values(&["json", "junit"])
+                        .help("The format to convert from, by default files with a .json \
+                        extension are read as JSON and all others as JUnit XML")
+                )
+                .arg(
+                    Arg::with_name("reports")
+                        .multiple(true)
+                        .required(true)
+                        .help("The report file(s) to convert, supports * and ** style globs")
+                )
+            )
+            .subcommand(SubCommand::with_name("summary")
+                .about("Renders JUnit XML Reports as a GitHub flavoured Markdown summary for pull \
+                request comments or $GITHUB_STEP_SUMMARY")
+                .arg(
+                    Arg::with_name("append")
+                        .long("append")
+                        .takes_value(true)
+                        .help("A file to append the summary to instead of writing it to STDOUT, \
+                        e.g. $GITHUB_STEP_SUMMARY")
+                )
+                .arg(
+                    Arg::with_name("title")
+                        .long("title")
+                        .takes_value(true)
+                        .help("The heading of the summary")
+                )
+                .arg(
+                    Arg::with_name("max_output_lines")
+                        .long("max-output-lines")
+                        .takes_value(true)
+                        .default_value("50")
+                        .help("The number of lines of output and stack trace to include for each \
+                        failing test")
+                )
+                .arg(
+                    Arg::with_name("reports")
+                        .multiple(true)
+                        .required(true)
+                        .help("The JUnit XML report file(s) to summarize, supports * and ** style globs")
+                )
+            )
+            .subcommand(SubCommand::with_name("annotate")
+                .about("Reports failed and flaky tests as CI annotations, so that they are shown \
+                inline in the diff view")
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .takes_value(true)
+                        .possible_values(&["github", "gitlab"])
+                        .default_value("github")
+                        .help("GitHub Actions workflow commands or a GitLab code quality report")
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .takes_value(true)
+                        .help("A file to write the annotations to instead of STDOUT, e.g. \
+                        gl-code-quality-report.json")
+                )
+                .arg(
+                    Arg::with_name("reports")
+                        .multiple(true)
+                        .required(true)
+                        .help("The JUnit XML report file(s) to annotate, supports * and ** style globs")
+                )
+            )
             .get_matches()
     }
 }
@@ -766,6 +1950,9 @@ fn report_processor(args: &ArgMatches) -> ReportProcessor {
             processor = processor.secret(&value.to_string_lossy());
         }
     }
+    if args.is_present("sanitize") {
+        processor = processor.sanitize(true);
+    }
     processor
 }
 
@@ -791,82 +1978,47 @@ fn export_reports<W: Write>(args: &ArgMatches, mut out: &mut W) -> anyhow::Resul
     out.flush()?;
     let processor = report_processor(args);
     for report_glob in args.values_of("reports").unwrap_or_default() {
-        for report in globwalk::glob(report_glob).unwrap() {
-            if let Ok(report) = report {
-                let file = report.path().canonicalize().unwrap();
-                let file = if let Ok(f) = file
-                    .clone()
-                    .strip_prefix(env::current_dir().unwrap_or_default())
-                {
-                    f.to_path_buf()
-                } else {
-                    file
+        for report in globwalk::glob(report_glob).unwrap().flatten() {
+            let file = report.path().canonicalize().unwrap();
+            let file = if let Ok(f) = file
+                .clone()
+                .strip_prefix(env::current_dir().unwrap_or_default())
+            {
+                f.to_path_buf()
+            } else {
+                file
+            };
+            let mut processor = processor.reset();
+            debug!("Exporting report: {}", report.path().to_string_lossy());
+            {
+                let needle =
+                    Needle::new_with_kind(&file.to_string_lossy(), "junit-test-report").to_string();
+                out.flush()?;
+                out.write_all(needle.as_bytes())?;
+                let mut reader = BufReader::new(File::open(file).unwrap());
+                let result = {
+                    let mut writer = BufWriter::new(&mut out);
+                    let mut encoder = EncoderWriter::new(&mut writer, base64::STANDARD);
+                    let result = processor.process(&mut reader, &mut encoder);
+                    encoder.finish()?;
+                    result
                 };
-                let mut processor = processor.reset();
-                debug!("Exporting report: {}", report.path().to_string_lossy());
-                {
-                    let needle =
-                        Needle::new_with_kind(&file.to_string_lossy(), "junit-test-report")
-                            .to_string();
+                out.write_all(needle.as_bytes())?;
+                if padding > 0 {
+                    writeln!(out)?;
                     out.flush()?;
-                    out.write_all(needle.as_bytes())?;
-                    let mut reader = BufReader::new(File::open(file).unwrap());
-                    let result = {
-                        let mut writer = BufWriter::new(&mut out);
-                        let mut encoder = EncoderWriter::new(&mut writer, base64::STANDARD);
-                        let result = processor.process(&mut reader, &mut encoder);
-                        encoder.finish()?;
-                        result
-                    };
-                    out.write_all(needle.as_bytes())?;
-                    if padding > 0 {
-                        writeln!(out)?;
-                        out.flush()?;
-                    }
-                    if let Err(e) = result {
-                        error!(
-                            "Could not complete parsing report {}: {:?}",
-                            report.path().to_string_lossy(),
-                            e
-                        );
-                    }
                 }
-                for attachment in processor.attachments() {
-                    if let Ok(file) = File::open(attachment) {
-                        let needle = Needle::new(&attachment).to_string();
-                        out.flush()?;
-                        out.write_all(needle.as_bytes())?;
-                        let mut reader = BufReader::new(file);
-                        {
-                            let mut writer = EncoderWriter::new(&mut out, base64::STANDARD);
-                            copy(&mut reader, &mut writer)?;
-                        }
-                        out.write_all(needle.as_bytes())?;
-                        if padding > 0 {
-                            writeln!(out)?;
-                            out.flush()?;
-                        }
-                    }
+                if let Err(e) = result {
+                    error!(
+                        "Could not complete parsing report {}: {:?}",
+                        report.path().to_string_lossy(),
+                        e
+                    );
                 }
             }
-        }
-    }
-    for file_glob in args.values_of("files").unwrap_or_default() {
-        for file in globwalk::glob(file_glob).unwrap() {
-            if let Ok(file) = file {
-                let path = file.path().canonicalize().unwrap();
-                let path = if let Ok(f) = path
-                    .clone()
-                    .strip_prefix(env::current_dir().unwrap_or_default())
-                {
-                    f.to_path_buf()
-                } else {
-                    path
-                };
-                let file_name = &path.to_string_lossy();
-                debug!("Exporting file: {}", file_name);
-                if let Ok(file) = File::open(path.clone()) {
-                    let needle = Needle::new(&file_name).to_string();
+            for attachment in processor.attachments() {
+                if let Ok(file) = File::open(attachment) {
+                    let needle = Needle::new(attachment).to_string();
                     out.flush()?;
                     out.write_all(needle.as_bytes())?;
                     let mut reader = BufReader::new(file);
@@ -883,6 +2035,36 @@ fn export_reports<W: Write>(args: &ArgMatches, mut out: &mut W) -> anyhow::Resul
             }
         }
     }
+    for file_glob in args.values_of("files").unwrap_or_default() {
+        for file in globwalk::glob(file_glob).unwrap().flatten() {
+            let path = file.path().canonicalize().unwrap();
+            let path = if let Ok(f) = path
+                .clone()
+                .strip_prefix(env::current_dir().unwrap_or_default())
+            {
+                f.to_path_buf()
+            } else {
+                path
+            };
+            let file_name = &path.to_string_lossy();
+            debug!("Exporting file: {}", file_name);
+            if let Ok(file) = File::open(path.clone()) {
+                let needle = Needle::new(file_name).to_string();
+                out.flush()?;
+                out.write_all(needle.as_bytes())?;
+                let mut reader = BufReader::new(file);
+                {
+                    let mut writer = EncoderWriter::new(&mut out, base64::STANDARD);
+                    copy(&mut reader, &mut writer)?;
+                }
+                out.write_all(needle.as_bytes())?;
+                if padding > 0 {
+                    writeln!(out)?;
+                    out.flush()?;
+                }
+            }
+        }
+    }
     for _ in 0..padding {
         writeln!(out)?;
     }
@@ -0,0 +1,576 @@
+/*
+ * Copyright (c) 2020 Stephen Connolly and CloudBees, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use crate::reports::{TestCase, TestResult, TestSuite};
+use chrono::Duration;
+use regex::Regex;
+use std::borrow::Cow;
+use std::io::BufRead;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Status {
+    Passed,
+    Failed,
+    Error,
+    Skipped,
+}
+
+struct PyTest {
+    class: String,
+    name: String,
+    /// how pytest heads the sections of the test, e.g. `TestDivide.test_by_zero`
+    head: String,
+    status: Status,
+    message: Option<String>,
+    traceback: String,
+    stdout: String,
+    stderr: String,
+    file: Option<String>,
+    time: Duration,
+}
+
+/// where the lines of a test's section go
+enum Capture {
+    Traceback(usize),
+    Stdout(usize),
+    Stderr(usize),
+    /// the heading of a unittest failure, up to the line of dashes
+    Heading(usize),
+}
+
+/// The patterns that are matched against the traceback of each test
+struct CasePatterns {
+    pytest_location: Regex,
+    pytest_message: Regex,
+    unittest_location: Regex,
+    unittest_exception: Regex,
+}
+
+/// Parses the output of pytest, with `-v` and/or `-rA`, or of the unittest verbose runner,
+/// `python -m unittest -v`, into a test suite. The traceback of each failed test is recorded as
+/// the failure body.
+pub fn read_python<'a, R: BufRead>(
+    input: &'_ mut R,
+    name: &'_ str,
+) -> anyhow::Result<TestSuite<'a>> {
+    let pytest_result = Regex::new(
+        r"^(?P<nodeid>\S+::\S+) (?P<status>PASSED|FAILED|ERROR|SKIPPED|XFAIL|XPASS)( \((?P<reason>.*)\))?(\s+\[\s*\d+%\])?$",
+    )
+    .unwrap();
+    let pytest_section = Regex::new(r"^=+ (?P<title>.+?) =+$").unwrap();
+    let pytest_heading = Regex::new(r"^_+ (?P<head>.+?) _+$").unwrap();
+    let pytest_captured = Regex::new(r"^-+ Captured (?P<stream>\w+) \w+ -+$").unwrap();
+    let pytest_summary = Regex::new(
+        r"^(?P<status>PASSED|FAILED|ERROR|XFAIL|XPASS) (?P<nodeid>\S+)( - (?P<message>.*))?$",
+    )
+    .unwrap();
+    let pytest_skipped =
+        Regex::new(r"^SKIPPED \[(?P<count>\d+)\] (?P<file>[^:]+):(?P<line>\d+): (?P<reason>.*)$")
+            .unwrap();
+    let pytest_duration =
+        Regex::new(r"^(?P<seconds>\d+\.\d+)s (setup|call|teardown)\s+(?P<nodeid>\S+)$").unwrap();
+    let unittest_test = Regex::new(r"^(?P<name>\w+) \((?P<class>[\w.]+)\)").unwrap();
+    let unittest_status = Regex::new(
+        r" \.\.\. (?P<status>ok|FAIL|ERROR|skipped '?(?P<reason>.*?)'?|expected failure|unexpected success)$",
+    )
+    .unwrap();
+    let unittest_heading =
+        Regex::new(r"^(?P<status>FAIL|ERROR): (?P<name>\w+) \((?P<class>[\w.]+)\)").unwrap();
+    let patterns = CasePatterns {
+        // pytest ends the traceback with `file:line: ExceptionType`
+        pytest_location: Regex::new(r"^(?P<file>[^\s:][^:]*):(?P<line>\d+): (?P<type>[\w.]+)$")
+            .unwrap(),
+        pytest_message: Regex::new(r"^E\s+(?P<message>.*)$").unwrap(),
+        // unittest ends it with `ExceptionType: message`
+        unittest_location: Regex::new(r#"^\s*File "(?P<file>[^"]+)", line (?P<line>\d+)"#).unwrap(),
+        unittest_exception: Regex::new(r"^(?P<type>[A-Za-z_][\w.]*)(: (?P<message>.*))?$").unwrap(),
+    };
+    let equals = Regex::new(r"^={20,}$").unwrap();
+    let dashes = Regex::new(r"^-{20,}$").unwrap();
+
+    let mut tests: Vec<PyTest> = Vec::new();
+    let mut section = String::new();
+    let mut capture: Option<Capture> = None;
+    // a unittest test with a docstring has its status on the following line
+    let mut pending: Option<usize> = None;
+    for line in input.lines() {
+        let line = line?;
+        if let Some(cap) = pytest_section.captures(&line) {
+            section = cap.name("title").unwrap().as_str().to_string();
+            capture = None;
+        } else if equals.is_match(&line) {
+            section.clear();
+            capture = None;
+        } else if let Some(cap) = pytest_result.captures(&line) {
+            let index = pytest_test(&mut tests, cap.name("nodeid").unwrap().as_str());
+            let (status, message) = match cap.name("status").unwrap().as_str() {
+                "PASSED" | "XPASS" => (Status::Passed, None),
+                "FAILED" => (Status::Failed, None),
+                "ERROR" => (Status::Error, None),
+                "XFAIL" => (
+                    Status::Skipped,
+                    Some(format!(
+                        "expected failure{}",
+                        cap.name("reason")
+                            .map(|m| format!(": {}", m.as_str()))
+                            .unwrap_or_default()
+                    )),
+                ),
+                _ => (
+                    Status::Skipped,
+                    cap.name("reason").map(|m| m.as_str().to_string()),
+                ),
+            };
+            update(&mut tests[index], status, message);
+        } else if section == "FAILURES" || section == "ERRORS" || section == "PASSES" {
+            if let Some(cap) = pytest_heading.captures(&line) {
+                let head = cap.name("head").unwrap().as_str();
+                let head = head
+                    .trim_start_matches("ERROR at setup of ")
+                    .trim_start_matches("ERROR at teardown of ");
+                let index = match tests.iter().position(|t| t.head == head) {
+                    Some(index) => index,
+                    None if head.starts_with("ERROR collecting ") => {
+                        let file = head.trim_start_matches("ERROR collecting ");
+                        pytest_test(&mut tests, &format!("{}::[collection]", file))
+                    }
+                    None => {
+                        // without -v the tests are only named in the sections and the summary
+                        tests.push(PyTest::new(name, head, head));
+                        tests.len() - 1
+                    }
+                };
+                match section.as_str() {
+                    "FAILURES" => update(&mut tests[index], Status::Failed, None),
+                    "ERRORS" => update(&mut tests[index], Status::Error, None),
+                    _ => {}
+                }
+                capture = Some(Capture::Traceback(index));
+            } else if let Some(cap) = pytest_captured.captures(&line) {
+                capture = match (cap.name("stream").unwrap().as_str(), &capture) {
+                    (_, None) => None,
+                    ("stderr", Some(c)) => Some(Capture::Stderr(c.index())),
+                    (_, Some(c)) => Some(Capture::Stdout(c.index())),
+                };
+            } else {
+                append(&mut tests, &capture, &line);
+            }
+        } else if section == "short test summary info" {
+            if let Some(cap) = pytest_summary.captures(&line) {
+                let index = pytest_test(&mut tests, cap.name("nodeid").unwrap().as_str());
+                let message = cap.name("message").map(|m| m.as_str().to_string());
+                match cap.name("status").unwrap().as_str() {
+                    "PASSED" | "XPASS" => update(&mut tests[index], Status::Passed, message),
+                    "FAILED" => update(&mut tests[index], Status::Failed, message),
+                    "ERROR" => update(&mut tests[index], Status::Error, message),
+                    _ => update(
+                        &mut tests[index],
+                        Status::Skipped,
+                        Some(format!(
+                            "expected failure{}",
+                            message.map(|m| format!(": {}", m)).unwrap_or_default()
+                        )),
+                    ),
+                }
+            } else if let Some(cap) = pytest_skipped.captures(&line) {
+                // skips are only reported by location, so fill in the reasons in order
+                let file = cap.name("file").unwrap().as_str();
+                let reason = cap.name("reason").unwrap().as_str();
+                let count: usize = cap.name("count").unwrap().as_str().parse().unwrap_or(1);
+                // with -v pytest 7 has already given the reasons
+                let known = tests
+                    .iter()
+                    .filter(|t| {
+                        t.status == Status::Skipped
+                            && t.message.as_deref() == Some(reason)
+                            && t.file.as_deref() == Some(file)
+                    })
+                    .count();
+                let mut missing = count.saturating_sub(known);
+                for test in tests.iter_mut().filter(|t| {
+                    t.status == Status::Skipped
+                        && t.message.is_none()
+                        && t.file.as_deref() == Some(file)
+                }) {
+                    if missing == 0 {
+                        break;
+                    }
+                    test.message = Some(reason.to_string());
+                    missing -= 1;
+                }
+                for _ in 0..missing {
+                    let index = pytest_test(
+                        &mut tests,
+                        &format!("{}::line {}", file, cap.name("line").unwrap().as_str()),
+                    );
+                    update(&mut tests[index], Status::Skipped, Some(reason.to_string()));
+                }
+            }
+        } else if section.ends_with("durations") {
+            if let Some(cap) = pytest_duration.captures(&line) {
+                if let Ok(seconds) = cap.name("seconds").unwrap().as_str().parse::<f64>() {
+                    let index = pytest_test(&mut tests, cap.name("nodeid").unwrap().as_str());
+                    tests[index].time =
+                        tests[index].time + Duration::milliseconds((seconds * 1000.0) as i64);
+                }
+            }
+        } else if let Some(Capture::Heading(index)) = capture {
+            if dashes.is_match(&line) {
+                capture = Some(Capture::Traceback(index));
+            }
+        } else if dashes.is_match(&line) {
+            capture = None;
+        } else if let Some(cap) = unittest_heading.captures(&line) {
+            let index = unittest_test_index(
+                &mut tests,
+                cap.name("class").unwrap().as_str(),
+                cap.name("name").unwrap().as_str(),
+            );
+            let status = match cap.name("status").unwrap().as_str() {
+                "FAIL" => Status::Failed,
+                _ => Status::Error,
+            };
+            update(&mut tests[index], status, None);
+            capture = Some(Capture::Heading(index));
+        } else if capture.is_some() {
+            append(&mut tests, &capture, &line);
+        } else {
+            let index = match unittest_test.captures(&line) {
+                Some(cap) => Some(unittest_test_index(
+                    &mut tests,
+                    cap.name("class").unwrap().as_str(),
+                    cap.name("name").unwrap().as_str(),
+                )),
+                None => pending.take(),
+            };
+            if let Some(index) = index {
+                match unittest_status.captures(&line) {
+                    Some(cap) => {
+                        let (status, message) = match cap.name("status").unwrap().as_str() {
+                            "ok" => (Status::Passed, None),
+                            "FAIL" => (Status::Failed, None),
+                            "ERROR" => (Status::Error, None),
+                            "expected failure" => {
+                                (Status::Skipped, Some("expected failure".to_string()))
+                            }
+                            "unexpected success" => {
+                                (Status::Failed, Some("unexpected success".to_string()))
+                            }
+                            _ => (
+                                Status::Skipped,
+                                cap.name("reason").map(|m| m.as_str().to_string()),
+                            ),
+                        };
+                        update(&mut tests[index], status, message);
+                        pending = None;
+                    }
+                    None => pending = Some(index),
+                }
+            }
+        }
+    }
+
+    let mut suite = TestSuite::new(name);
+    for test in tests {
+        suite = suite.push(test.into_test_case(&patterns));
+    }
+    Ok(suite)
+}
+
+impl Capture {
+    fn index(&self) -> usize {
+        match self {
+            Capture::Traceback(index)
+            | Capture::Stdout(index)
+            | Capture::Stderr(index)
+            | Capture::Heading(index) => *index,
+        }
+    }
+}
+
+impl PyTest {
+    fn new(class: &str, name: &str, head: &str) -> PyTest {
+        PyTest {
+            class: class.to_string(),
+            name: name.to_string(),
+            head: head.to_string(),
+            status: Status::Passed,
+            message: None,
+            traceback: String::new(),
+            stdout: String::new(),
+            stderr: String::new(),
+            file: None,
+            time: Duration::zero(),
+        }
+    }
+
+    fn into_test_case<'a>(self, patterns: &CasePatterns) -> TestCase<'a> {
+        let traceback = self
+            .traceback
+            .trim_start_matches('\n')
+            .trim_end()
+            .to_string();
+        let mut type_ = None;
+        let mut message = self.message;
+        let mut location = self.file.clone().map(|f| (f, None));
+        if let Some(cap) = traceback
+            .lines()
+            .filter_map(|l| patterns.pytest_location.captures(l))
+            .next_back()
+        {
+            type_ = Some(cap.name("type").unwrap().as_str().to_string());
+            location = Some((
+                cap.name("file").unwrap().as_str().to_string(),
+                cap.name("line").unwrap().as_str().parse().ok(),
+            ));
+            if message.is_none() {
+                message = traceback
+                    .lines()
+                    .find_map(|l| patterns.pytest_message.captures(l))
+                    .map(|cap| cap.name("message").unwrap().as_str().to_string());
+            }
+        } else if let Some(cap) = traceback
+            .lines()
+            .filter_map(|l| patterns.unittest_location.captures(l))
+            .next_back()
+        {
+            location = Some((
+                cap.name("file").unwrap().as_str().to_string(),
+                cap.name("line").unwrap().as_str().parse().ok(),
+            ));
+            if let Some(cap) = traceback
+                .lines()
+                .filter(|l| !l.starts_with(char::is_whitespace))
+                .filter_map(|l| patterns.unittest_exception.captures(l))
+                .next_back()
+            {
+                type_ = Some(cap.name("type").unwrap().as_str().to_string());
+                if message.is_none() {
+                    message = cap.name("message").map(|m| m.as_str().to_string());
+                }
+            }
+        }
+        let message = message.unwrap_or_default();
+        let result = match self.status {
+            Status::Passed => TestResult::success(),
+            Status::Skipped => TestResult::skipped(&message),
+            Status::Failed => TestResult::Failure {
+                type_: Cow::Owned(type_.unwrap_or_else(|| "assertion".to_string())),
+                message: Cow::Owned(message),
+                detail: Cow::Owned(traceback),
+            },
+            Status::Error => TestResult::Error {
+                type_: Cow::Owned(type_.unwrap_or_else(|| "error".to_string())),
+                message: Cow::Owned(message),
+                detail: Cow::Owned(traceback),
+            },
+        };
+        let case = TestCase::new_with_output(
+            &self.name,
+            &self.class,
+            &result,
+            Cow::Owned(self.stdout),
+            Cow::Owned(self.stderr),
+            self.time,
+        );
+        match location {
+            Some((file, line)) => case.with_location(&file, line),
+            None => case,
+        }
+    }
+}
+
+/// finds or adds the test of a pytest node id, e.g. `tests/test_calc.py::TestDivide::test_add`
+fn pytest_test(tests: &mut Vec<PyTest>, nodeid: &str) -> usize {
+    let parts: Vec<&str> = nodeid.split("::").collect();
+    let file = parts[0];
+    let module = file.trim_end_matches(".py").replace(['/', '\\'], ".");
+    let name = parts[parts.len() - 1];
+    let class = std::iter::once(module.as_str())
+        .chain(parts[1..parts.len() - 1].iter().copied())
+        .collect::<Vec<&str>>()
+        .join(".");
+    let head = parts[1..].join(".");
+    if let Some(index) = tests
+        .iter()
+        .position(|t| t.class == class && t.name == name)
+    {
+        return index;
+    }
+    let mut test = PyTest::new(&class, name, &head);
+    test.file = Some(file.to_string());
+    // a test that was only known by its section heading
+    if let Some(index) = tests
+        .iter()
+        .position(|t| t.file.is_none() && t.head == head && t.name == head)
+    {
+        tests[index].class = test.class;
+        tests[index].name = test.name;
+        tests[index].file = test.file;
+        return index;
+    }
+    tests.push(test);
+    tests.len() - 1
+}
+
+/// finds or adds a unittest test, since Python 3.11 the class includes the test method
+fn unittest_test_index(tests: &mut Vec<PyTest>, class: &str, name: &str) -> usize {
+    let suffix = format!(".{}", name);
+    let class = class.strip_suffix(&suffix).unwrap_or(class);
+    match tests
+        .iter()
+        .position(|t| t.class == class && t.name == name)
+    {
+        Some(index) => index,
+        None => {
+            tests.push(PyTest::new(class, name, name));
+            tests.len() - 1
+        }
+    }
+}
+
+/// records a status, a teardown error does not hide a failure
+fn update(test: &mut PyTest, status: Status, message: Option<String>) {
+    if !(test.status == Status::Failed && status == Status::Error) {
+        test.status = status;
+    }
+    if message.is_some() {
+        test.message = message;
+    }
+}
+
+fn append(tests: &mut [PyTest], capture: &Option<Capture>, line: &str) {
+    let buffer = match capture {
+        Some(Capture::Traceback(index)) => &mut tests[*index].traceback,
+        Some(Capture::Stdout(index)) => &mut tests[*index].stdout,
+        Some(Capture::Stderr(index)) => &mut tests[*index].stderr,
+        _ => return,
+    };
+    buffer.push_str(line);
+    buffer.push('\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::python::read_python;
+    use crate::reports::TestResult;
+    use chrono::Duration;
+    use std::io::{BufReader, Cursor};
+
+    #[test]
+    fn pytest() {
+        let input = include_str!("../../test/python/pytest.txt");
+        let mut reader = BufReader::new(Cursor::new(input.as_bytes()));
+        let suite = read_python(&mut reader, "pytest").unwrap();
+        assert_eq!(suite.name(), "pytest");
+        assert_eq!(suite.test_count(), 7);
+        assert_eq!(suite.failure_count(), 1);
+        assert_eq!(suite.error_count(), 1);
+        assert_eq!(suite.skipped_count(), 2);
+
+        let add = &suite.cases()[0];
+        assert_eq!(add.class(), "tests.test_calc");
+        assert_eq!(add.name(), "test_add");
+        assert_eq!(add.stdout(), "adding 1 and 2\n");
+        assert_eq!(add.time(), Duration::milliseconds(510));
+
+        let by_zero = &suite.cases()[1];
+        assert_eq!(by_zero.class(), "tests.test_calc.TestDivide");
+        assert_eq!(by_zero.name(), "test_by_zero");
+        match by_zero.result() {
+            TestResult::Failure {
+                type_,
+                message,
+                detail,
+            } => {
+                assert_eq!(type_, "ZeroDivisionError");
+                assert_eq!(message, "ZeroDivisionError: division by zero");
+                assert!(detail.starts_with("self = <test_calc.TestDivide object"));
+                assert!(detail.ends_with("tests/test_calc.py:21: ZeroDivisionError"));
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert_eq!(by_zero.file(), Some("tests/test_calc.py"));
+        assert_eq!(by_zero.line(), Some(21));
+        assert_eq!(by_zero.stdout(), "dividing 1 by 0\n");
+
+        assert_eq!(
+            suite.cases()[3].result(),
+            &TestResult::skipped("needs network")
+        );
+        assert_eq!(
+            suite.cases()[4].result().message(),
+            Some("RuntimeError: no database")
+        );
+        assert_eq!(
+            suite.cases()[5].result(),
+            &TestResult::skipped("expected failure: not implemented")
+        );
+        assert_eq!(suite.cases()[6].name(), "test_params[1-2]");
+    }
+
+    #[test]
+    fn unittest() {
+        let input = include_str!("../../test/python/unittest.txt");
+        let mut reader = BufReader::new(Cursor::new(input.as_bytes()));
+        let suite = read_python(&mut reader, "unittest").unwrap();
+        assert_eq!(suite.test_count(), 7);
+        assert_eq!(suite.failure_count(), 2);
+        assert_eq!(suite.error_count(), 1);
+        assert_eq!(suite.skipped_count(), 2);
+
+        let names: Vec<(&str, &str)> = suite
+            .cases()
+            .iter()
+            .map(|c| (c.class(), c.name()))
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                ("test_calc.TestCalc", "test_add"),
+                ("test_calc.TestCalc", "test_broken"),
+                ("test_calc.TestCalc", "test_documented"),
+                ("test_calc.TestCalc", "test_lucky"),
+                ("test_calc.TestCalc", "test_network"),
+                ("test_calc.TestCalc", "test_todo"),
+                ("test_calc.TestDivide", "test_by_zero"),
+            ]
+        );
+        let broken = &suite.cases()[1];
+        match broken.result() {
+            TestResult::Error {
+                type_,
+                message,
+                detail,
+            } => {
+                assert_eq!(type_, "RuntimeError");
+                assert_eq!(message, "no database");
+                assert!(detail.starts_with("Traceback (most recent call last):"));
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert_eq!(broken.file(), Some("/home/dev/calc/test_calc.py"));
+        assert_eq!(broken.line(), Some(12));
+        assert_eq!(suite.cases()[2].result(), &TestResult::success());
+        assert_eq!(
+            suite.cases()[3].result(),
+            &TestResult::failure("unexpected success")
+        );
+        assert_eq!(
+            suite.cases()[4].result(),
+            &TestResult::skipped("needs network")
+        );
+        assert_eq!(suite.cases()[6].result().message(), Some("2 != 3"));
+    }
+}
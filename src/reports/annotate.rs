@@ -0,0 +1,226 @@
+/*
+ * Copyright (c) 2020 Stephen Connolly and CloudBees, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use crate::reports::{TestCase, TestResult, TestSuite};
+use serde::Serialize;
+use std::io::Write;
+
+/// The CI system that the annotations are for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AnnotationFormat {
+    /// GitHub Actions `::error` and `::warning` workflow commands
+    GitHub,
+    /// a GitLab code quality report
+    GitLab,
+}
+
+/// A GitLab code quality issue, see
+/// https://docs.gitlab.com/ee/user/project/merge_requests/code_quality.html#implementing-a-custom-tool
+#[derive(Serialize)]
+struct CodeQualityIssue {
+    description: String,
+    check_name: String,
+    fingerprint: String,
+    severity: String,
+    location: CodeQualityLocation,
+}
+
+#[derive(Serialize)]
+struct CodeQualityLocation {
+    path: String,
+    lines: CodeQualityLines,
+}
+
+#[derive(Serialize)]
+struct CodeQualityLines {
+    begin: u32,
+}
+
+/// Writes an annotation for each failed, errored or flaky test, using the file and line of the
+/// test when the report records them. Returns the number of annotations written.
+pub fn write_annotations<W: Write>(
+    suites: &[TestSuite<'_>],
+    format: AnnotationFormat,
+    writer: &mut W,
+) -> anyhow::Result<usize> {
+    let cases: Vec<&TestCase<'_>> = suites
+        .iter()
+        .flat_map(|s| s.cases())
+        .filter(|c| {
+            c.is_flaky()
+                || matches!(
+                    c.result(),
+                    TestResult::Failure { .. } | TestResult::Error { .. }
+                )
+        })
+        .collect();
+    match format {
+        AnnotationFormat::GitHub => {
+            for case in &cases {
+                let mut properties = Vec::new();
+                if let Some(file) = case.file() {
+                    properties.push(format!("file={}", escape_property(file)));
+                    if let Some(line) = case.line() {
+                        properties.push(format!("line={}", line));
+                    }
+                }
+                properties.push(format!("title={}", escape_property(&title(case))));
+                writeln!(
+                    writer,
+                    "::{} {}::{}",
+                    if case.is_flaky() { "warning" } else { "error" },
+                    properties.join(","),
+                    escape_data(&description(case))
+                )?;
+            }
+        }
+        AnnotationFormat::GitLab => {
+            let issues: Vec<CodeQualityIssue> = cases
+                .iter()
+                .map(|case| CodeQualityIssue {
+                    description: format!("{}: {}", title(case), description(case)),
+                    check_name: if case.is_flaky() {
+                        "flaky-test".to_string()
+                    } else {
+                        "failed-test".to_string()
+                    },
+                    fingerprint: fingerprint(&format!("{}\u{0}{}", case.class(), case.name())),
+                    severity: if case.is_flaky() {
+                        "minor".to_string()
+                    } else {
+                        "major".to_string()
+                    },
+                    location: CodeQualityLocation {
+                        // GitLab requires a path, the class is the best we have without a file
+                        path: case
+                            .file()
+                            .map(|f| f.to_string())
+                            .unwrap_or_else(|| case.class().to_string()),
+                        lines: CodeQualityLines {
+                            begin: case.line().unwrap_or(1),
+                        },
+                    },
+                })
+                .collect();
+            serde_json::to_writer_pretty(&mut *writer, &issues)?;
+            writeln!(writer)?;
+        }
+    }
+    Ok(cases.len())
+}
+
+fn title(case: &TestCase<'_>) -> String {
+    if case.class().is_empty() {
+        case.name().to_string()
+    } else {
+        format!("{}({})", case.name(), case.class())
+    }
+}
+
+fn description(case: &TestCase<'_>) -> String {
+    match case.result() {
+        TestResult::Success => match case.reruns().len() {
+            1 => "flaky, passed after 1 failed attempt".to_string(),
+            n => format!("flaky, passed after {} failed attempts", n),
+        },
+        TestResult::Failure { type_, message, .. } | TestResult::Error { type_, message, .. } => {
+            if message.is_empty() {
+                type_.to_string()
+            } else {
+                format!("{}: {}", type_, message)
+            }
+        }
+        TestResult::Skipped { message } => message.to_string(),
+    }
+}
+
+/// escapes the message of a workflow command
+fn escape_data(text: &str) -> String {
+    text.replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+/// escapes the value of a workflow command property
+fn escape_property(text: &str) -> String {
+    escape_data(text).replace(':', "%3A").replace(',', "%2C")
+}
+
+/// a stable FNV-1a hash, GitLab uses the fingerprint to track issues between pipelines
+fn fingerprint(text: &str) -> String {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in text.bytes() {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    format!("{:016x}", hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::reports::{
+        write_annotations, AnnotationFormat, TestCase, TestRerun, TestResult, TestSuite,
+    };
+    use chrono::Duration;
+
+    fn suites() -> Vec<TestSuite<'static>> {
+        vec![TestSuite::new("suite")
+            .push(TestCase::new(
+                "passes",
+                "foo",
+                &TestResult::success(),
+                Duration::zero(),
+            ))
+            .push(
+                TestCase::new(
+                    "fails",
+                    "foo",
+                    &TestResult::failure("expected: 1\nactual: 2"),
+                    Duration::zero(),
+                )
+                .with_location("src/foo,bar.rs", Some(42)),
+            )
+            .push(
+                TestCase::new("flakes", "foo", &TestResult::success(), Duration::zero())
+                    .rerun(TestRerun::new(&TestResult::error("timeout"))),
+            )]
+    }
+
+    #[test]
+    fn github() {
+        let mut out = Vec::new();
+        let count = write_annotations(&suites(), AnnotationFormat::GitHub, &mut out).unwrap();
+        assert_eq!(count, 2);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "::error file=src/foo%2Cbar.rs,line=42,title=fails(foo)::assertion: expected: 1%0Aactual: 2\n\
+            ::warning title=flakes(foo)::flaky, passed after 1 failed attempt\n"
+        );
+    }
+
+    #[test]
+    fn gitlab() {
+        let mut out = Vec::new();
+        let count = write_annotations(&suites(), AnnotationFormat::GitLab, &mut out).unwrap();
+        assert_eq!(count, 2);
+        let json = String::from_utf8(out).unwrap();
+        assert!(json.contains("\"path\": \"src/foo,bar.rs\""));
+        assert!(json.contains("\"begin\": 42"));
+        assert!(json.contains("\"check_name\": \"flaky-test\""));
+        assert!(json.contains("\"path\": \"foo\""));
+        let mut again = Vec::new();
+        write_annotations(&suites(), AnnotationFormat::GitLab, &mut again).unwrap();
+        assert_eq!(json, String::from_utf8(again).unwrap());
+    }
+}
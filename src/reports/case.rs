@@ -12,7 +12,8 @@
  * limitations under the License.
  */
 
-use crate::reports::TestResult;
+use crate::reports::xml_util::{sanitize_xml, write_cdata};
+use crate::reports::{TestRerun, TestResult};
 use chrono::Duration;
 use std::borrow::Cow;
 use std::io::Write;
@@ -34,6 +35,14 @@ pub struct TestCase<'a> {
     result: TestResult<'a>,
     /// The duration of the test execution
     time: Duration,
+    /// The failed attempts of the test when it has been re-run
+    reruns: Vec<TestRerun<'a>>,
+    /// The source file that defines the test
+    file: Option<Cow<'a, str>>,
+    /// The line in the source file that defines the test
+    line: Option<u32>,
+    /// The properties of the test execution
+    properties: Vec<(Cow<'a, str>, Cow<'a, str>)>,
 }
 
 impl<'a> TestCase<'a> {
@@ -50,6 +59,10 @@ impl<'a> TestCase<'a> {
             stderr: Default::default(),
             result: result.clone(),
             time,
+            reruns: Vec::new(),
+            file: None,
+            line: None,
+            properties: Vec::new(),
         }
     }
 
@@ -68,79 +81,237 @@ impl<'a> TestCase<'a> {
             stderr: stderr.clone(),
             result: result.clone(),
             time,
+            reruns: Vec::new(),
+            file: None,
+            line: None,
+            properties: Vec::new(),
         }
     }
 
     /// the name of the test case
-    pub fn name(&'a self) -> &'a str {
+    pub fn name(&self) -> &str {
         self.name.as_ref()
     }
 
     /// the test group name
-    pub fn class(&'a self) -> &'a str {
+    pub fn class(&self) -> &str {
         self.class.as_ref()
     }
 
     /// the test stdout
-    pub fn stdout(&'a self) -> &'a str {
+    pub fn stdout(&self) -> &str {
         self.stdout.as_ref()
     }
 
     /// the test stderr
-    pub fn stderr(&'a self) -> &'a str {
+    pub fn stderr(&self) -> &str {
         self.stderr.as_ref()
     }
 
     /// the test result
-    pub fn result(&'a self) -> &'a TestResult<'a> {
+    pub fn result(&self) -> &TestResult<'a> {
         &self.result
     }
 
     /// the test duration
-    pub fn time(&'a self) -> Duration {
+    pub fn time(&self) -> Duration {
         self.time
     }
 
+    /// the failed attempts of the test when it has been re-run
+    pub fn reruns(&self) -> &[TestRerun<'a>] {
+        &self.reruns
+    }
+
+    /// the source file that defines the test
+    pub fn file(&self) -> Option<&str> {
+        self.file.as_deref()
+    }
+
+    /// the line in the source file that defines the test
+    pub fn line(&self) -> Option<u32> {
+        self.line
+    }
+
+    /// the properties of the test execution, in the order they were added
+    pub fn properties(&self) -> &[(Cow<'a, str>, Cow<'a, str>)] {
+        &self.properties
+    }
+
+    /// a test is flaky if it eventually succeeded after failed attempts
+    pub fn is_flaky(&self) -> bool {
+        self.result == TestResult::Success && !self.reruns.is_empty()
+    }
+
+    /// records a failed attempt of the test
+    pub fn rerun(self, rerun: TestRerun<'a>) -> TestCase<'a> {
+        TestCase {
+            reruns: {
+                let mut reruns = self.reruns;
+                reruns.push(rerun);
+                reruns
+            },
+            ..self
+        }
+    }
+
+    /// records where the test is defined
+    pub fn with_location(self, file: &'_ str, line: Option<u32>) -> TestCase<'a> {
+        TestCase {
+            file: Some(Cow::Owned(file.to_string())),
+            line,
+            ..self
+        }
+    }
+
+    /// records a property of the test execution
+    pub fn with_property(self, name: &'_ str, value: &'_ str) -> TestCase<'a> {
+        TestCase {
+            properties: {
+                let mut properties = self.properties;
+                properties.push((Cow::Owned(name.to_string()), Cow::Owned(value.to_string())));
+                properties
+            },
+            ..self
+        }
+    }
+
+    /// a one line summary of the test case suitable for console progress output
+    pub fn as_progress_str(&self) -> String {
+        format!(
+            "{}({}) Time elapsed: {}{}",
+            self.name,
+            self.class,
+            (self.time.num_milliseconds() as f64) / 1000.0,
+            match &self.result {
+                TestResult::Success if self.is_flaky() => " <<< FLAKE!",
+                TestResult::Success => "",
+                TestResult::Skipped { .. } => " <<< SKIPPED!",
+                TestResult::Failure { .. } => " <<< FAILURE!",
+                TestResult::Error { .. } => " <<< ERROR!",
+            }
+        )
+    }
+
     pub fn write<W: Write>(&self, writer: &mut EventWriter<W>) -> anyhow::Result<()> {
         let time = format!("{}", (self.time.num_milliseconds() as f64) / 1000.0);
-        writer.write(
-            XmlEvent::start_element("testcase")
-                .attr("name", self.name.as_ref())
-                .attr("classname", self.class.as_ref())
-                .attr("time", &time),
-        )?;
+        let name = sanitize_xml(&self.name);
+        let class = sanitize_xml(&self.class);
+        let file = self.file.as_deref().map(sanitize_xml);
+        let line = self.line.map(|l| format!("{}", l));
+        let mut start = XmlEvent::start_element("testcase")
+            .attr("name", name.as_ref())
+            .attr("classname", class.as_ref())
+            .attr("time", &time);
+        if let Some(file) = &file {
+            start = start.attr("file", file.as_ref());
+        }
+        if let Some(line) = &line {
+            start = start.attr("line", line);
+        }
+        writer.write(start)?;
+        if !self.properties.is_empty() {
+            writer.write(XmlEvent::start_element("properties"))?;
+            for (name, value) in &self.properties {
+                writer.write(
+                    XmlEvent::start_element("property")
+                        .attr("name", sanitize_xml(name).as_ref())
+                        .attr("value", sanitize_xml(value).as_ref()),
+                )?;
+                writer.write(XmlEvent::end_element())?;
+            }
+            writer.write(XmlEvent::end_element())?;
+        }
         match &self.result {
             TestResult::Success => (),
-            TestResult::Failure { type_, message } => {
+            TestResult::Failure {
+                type_,
+                message,
+                detail,
+            } => {
                 writer.write(
                     XmlEvent::start_element("failure")
-                        .attr("message", message.as_ref())
+                        .attr("message", sanitize_xml(message).as_ref())
                         .attr("type", type_.as_ref()),
                 )?;
+                if !detail.is_empty() {
+                    writer.write(XmlEvent::characters(sanitize_xml(detail).as_ref()))?;
+                }
                 writer.write(XmlEvent::end_element())?;
             }
-            TestResult::Error { type_, message } => {
+            TestResult::Error {
+                type_,
+                message,
+                detail,
+            } => {
                 writer.write(
                     XmlEvent::start_element("error")
-                        .attr("message", message.as_ref())
+                        .attr("message", sanitize_xml(message).as_ref())
                         .attr("type", type_.as_ref()),
                 )?;
+                if !detail.is_empty() {
+                    writer.write(XmlEvent::characters(sanitize_xml(detail).as_ref()))?;
+                }
                 writer.write(XmlEvent::end_element())?;
             }
             TestResult::Skipped { message } => {
-                writer
-                    .write(XmlEvent::start_element("skipped").attr("message", message.as_ref()))?;
+                writer.write(
+                    XmlEvent::start_element("skipped")
+                        .attr("message", sanitize_xml(message).as_ref()),
+                )?;
+                writer.write(XmlEvent::end_element())?;
+            }
+        }
+        // surefire uses `flaky*` when the test eventually passed and `rerun*` when it did not
+        let prefix = if self.result == TestResult::Success {
+            "flaky"
+        } else {
+            "rerun"
+        };
+        for rerun in &self.reruns {
+            let (kind, type_, message, detail) = match rerun.result() {
+                TestResult::Failure {
+                    type_,
+                    message,
+                    detail,
+                } => ("Failure", type_, message, detail),
+                TestResult::Error {
+                    type_,
+                    message,
+                    detail,
+                } => ("Error", type_, message, detail),
+                _ => continue,
+            };
+            writer.write(
+                XmlEvent::start_element(format!("{}{}", prefix, kind).as_str())
+                    .attr("message", sanitize_xml(message).as_ref())
+                    .attr("type", type_.as_ref()),
+            )?;
+            if !detail.is_empty() {
+                writer.write(XmlEvent::start_element("stackTrace"))?;
+                writer.write(XmlEvent::characters(sanitize_xml(detail).as_ref()))?;
+                writer.write(XmlEvent::end_element())?;
+            }
+            if !rerun.stdout().is_empty() {
+                writer.write(XmlEvent::start_element("system-out"))?;
+                write_cdata(writer, rerun.stdout())?;
+                writer.write(XmlEvent::end_element())?;
+            }
+            if !rerun.stderr().is_empty() {
+                writer.write(XmlEvent::start_element("system-err"))?;
+                write_cdata(writer, rerun.stderr())?;
                 writer.write(XmlEvent::end_element())?;
             }
+            writer.write(XmlEvent::end_element())?;
         }
         if !self.stdout.is_empty() {
             writer.write(XmlEvent::start_element("system-out"))?;
-            writer.write(XmlEvent::cdata(self.stdout.as_ref()))?;
+            write_cdata(writer, &self.stdout)?;
             writer.write(XmlEvent::end_element())?;
         }
         if !self.stderr.is_empty() {
             writer.write(XmlEvent::start_element("system-err"))?;
-            writer.write(XmlEvent::cdata(self.stderr.as_ref()))?;
+            write_cdata(writer, &self.stderr)?;
             writer.write(XmlEvent::end_element())?;
         }
         writer.write(XmlEvent::end_element())?;
@@ -152,7 +323,7 @@ impl<'a> TestCase<'a> {
 mod tests {
     use crate::reports::xml_util::round_trip_xml_output;
     use crate::reports::TestCase;
-    use crate::reports::TestResult;
+    use crate::reports::{TestRerun, TestResult};
     use chrono::Duration;
     use std::borrow::Cow;
     use xml::EventWriter;
@@ -250,4 +421,79 @@ mod tests {
         .unwrap();
         assert_eq!(String::from_utf8_lossy(&out).as_ref(), "<?xml version=\"1.0\" encoding=\"utf-8\"?><testcase name=\"foo\" classname=\"bar\" time=\"123456.789\"><error message=\"reason\" type=\"error\"/></testcase>");
     }
+
+    #[test]
+    fn write_flaky_as_xml() {
+        let mut out = Vec::<u8>::new();
+        let mut sink = EventWriter::new_with_config(&mut out, round_trip_xml_output());
+        let instance = TestCase::new(
+            "foo",
+            "bar",
+            &TestResult::success(),
+            Duration::milliseconds(123456789),
+        )
+        .rerun(TestRerun::new_with_output(
+            &TestResult::failure("reason"),
+            Cow::Borrowed("first attempt"),
+            Cow::Borrowed(""),
+        ));
+        assert!(instance.is_flaky());
+        instance.write(&mut sink).unwrap();
+        assert_eq!(String::from_utf8_lossy(&out).as_ref(), "<?xml version=\"1.0\" encoding=\"utf-8\"?><testcase name=\"foo\" classname=\"bar\" time=\"123456.789\"><flakyFailure message=\"reason\" type=\"assertion\"><system-out><![CDATA[first attempt]]></system-out></flakyFailure></testcase>");
+    }
+
+    #[test]
+    fn write_rerun_as_xml() {
+        let mut out = Vec::<u8>::new();
+        let mut sink = EventWriter::new_with_config(&mut out, round_trip_xml_output());
+        let instance = TestCase::new(
+            "foo",
+            "bar",
+            &TestResult::failure("reason"),
+            Duration::milliseconds(123456789),
+        )
+        .rerun(TestRerun::new(&TestResult::error("oops")));
+        assert!(!instance.is_flaky());
+        instance.write(&mut sink).unwrap();
+        assert_eq!(String::from_utf8_lossy(&out).as_ref(), "<?xml version=\"1.0\" encoding=\"utf-8\"?><testcase name=\"foo\" classname=\"bar\" time=\"123456.789\"><failure message=\"reason\" type=\"assertion\"/><rerunError message=\"oops\" type=\"error\"/></testcase>");
+    }
+
+    #[test]
+    fn write_sanitized_as_xml() {
+        let mut out = Vec::<u8>::new();
+        let mut sink = EventWriter::new_with_config(&mut out, round_trip_xml_output());
+        TestCase::new_with_output(
+            "foo",
+            "bar",
+            &TestResult::failure("bell\u{7}"),
+            Cow::Borrowed("\u{1b}[1mbold\u{1b}[0m ]]> done"),
+            Cow::Borrowed("nul\u{0}"),
+            Duration::milliseconds(123456789),
+        )
+        .write(&mut sink)
+        .unwrap();
+        assert_eq!(String::from_utf8_lossy(&out).as_ref(), "<?xml version=\"1.0\" encoding=\"utf-8\"?><testcase name=\"foo\" classname=\"bar\" time=\"123456.789\"><failure message=\"bell\\u0007\" type=\"assertion\"/><system-out><![CDATA[\\u001B[1mbold\\u001B[0m ]]]]><![CDATA[> done]]></system-out><system-err><![CDATA[nul\\u0000]]></system-err></testcase>");
+    }
+
+    #[test]
+    fn write_details_as_xml() {
+        let mut out = Vec::<u8>::new();
+        let mut sink = EventWriter::new_with_config(&mut out, round_trip_xml_output());
+        let instance = TestCase::new(
+            "foo",
+            "bar",
+            &TestResult::failure("reason").with_detail("expected: <1> but was: <2>"),
+            Duration::milliseconds(123456789),
+        )
+        .with_location("src/foo.rs", Some(42))
+        .with_property("owner", "team-a")
+        .rerun(TestRerun::new(
+            &TestResult::error("oops").with_detail("at foo.rs:42"),
+        ));
+        assert_eq!(instance.file(), Some("src/foo.rs"));
+        assert_eq!(instance.line(), Some(42));
+        assert_eq!(instance.properties().len(), 1);
+        instance.write(&mut sink).unwrap();
+        assert_eq!(String::from_utf8_lossy(&out).as_ref(), "<?xml version=\"1.0\" encoding=\"utf-8\"?><testcase name=\"foo\" classname=\"bar\" time=\"123456.789\" file=\"src/foo.rs\" line=\"42\"><properties><property name=\"owner\" value=\"team-a\"/></properties><failure message=\"reason\" type=\"assertion\">expected: &lt;1> but was: &lt;2></failure><rerunError message=\"oops\" type=\"error\"><stackTrace>at foo.rs:42</stackTrace></rerunError></testcase>");
+    }
 }
@@ -0,0 +1,488 @@
+/*
+ * Copyright (c) 2020 Stephen Connolly and CloudBees, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use crate::reports::xml_util::{round_trip_xml_input, round_trip_xml_output};
+use crate::reports::{TestResult, TestSuite, ToWrite};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Read, Write};
+use xml::attribute::OwnedAttribute;
+use xml::name::OwnedName;
+use xml::reader::XmlEvent;
+use xml::{EventReader, EventWriter};
+
+/// How the failures of flaky tests should be marked when rewriting a report
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FlakyMark {
+    /// adds a `flaky` property to the test case and leaves the failure in place
+    Property,
+    /// replaces the failure with a skipped result giving the flake rate as the reason
+    Skipped,
+}
+
+/// The results of a single test across multiple runs
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlakyTest {
+    class: String,
+    name: String,
+    runs: usize,
+    failures: usize,
+}
+
+impl FlakyTest {
+    /// the test group name
+    pub fn class(&self) -> &str {
+        self.class.as_str()
+    }
+
+    /// the name of the test case
+    pub fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    /// the number of runs where the test was executed (i.e. not skipped)
+    pub fn runs(&self) -> usize {
+        self.runs
+    }
+
+    /// the number of runs where the test failed or errored
+    pub fn failures(&self) -> usize {
+        self.failures
+    }
+
+    /// the fraction of runs where the test failed or errored
+    pub fn flake_rate(&self) -> f64 {
+        if self.runs == 0 {
+            0.0
+        } else {
+            self.failures as f64 / self.runs as f64
+        }
+    }
+
+    /// a test is flaky if it has both passed and failed
+    pub fn is_flaky(&self) -> bool {
+        self.failures > 0 && self.failures < self.runs
+    }
+
+    pub fn as_str(&self) -> String {
+        format!(
+            "{}({}) failed {} of {} runs ({:.0}% flake rate)",
+            self.name,
+            self.class,
+            self.failures,
+            self.runs,
+            self.flake_rate() * 100.0
+        )
+    }
+}
+
+/// Detects tests whose results vary across multiple runs of the same JUnit XML Reports.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlakyDetector {
+    min_runs: usize,
+    min_rate: f64,
+    runs: usize,
+    tests: BTreeMap<(String, String), FlakyTest>,
+}
+
+impl Default for FlakyDetector {
+    fn default() -> Self {
+        FlakyDetector {
+            min_runs: 2,
+            min_rate: 0.0,
+            runs: 0,
+            tests: BTreeMap::new(),
+        }
+    }
+}
+
+impl FlakyDetector {
+    pub fn new() -> FlakyDetector {
+        FlakyDetector {
+            ..Default::default()
+        }
+    }
+
+    /// the minimum number of runs a test must have been executed in to be considered flaky
+    pub fn min_runs(self, min_runs: usize) -> FlakyDetector {
+        FlakyDetector { min_runs, ..self }
+    }
+
+    /// the minimum flake rate for a test to be considered flaky
+    pub fn min_rate(self, min_rate: f64) -> FlakyDetector {
+        FlakyDetector { min_rate, ..self }
+    }
+
+    /// records the results of one run of the test suites
+    pub fn add_run(&mut self, suites: &[TestSuite<'_>]) {
+        self.runs += 1;
+        for suite in suites {
+            for case in suite.cases() {
+                let failed = match case.result() {
+                    TestResult::Success => false,
+                    TestResult::Failure { .. } | TestResult::Error { .. } => true,
+                    TestResult::Skipped { .. } => continue,
+                };
+                let test = self
+                    .tests
+                    .entry((case.class().to_string(), case.name().to_string()))
+                    .or_insert_with(|| FlakyTest {
+                        class: case.class().to_string(),
+                        name: case.name().to_string(),
+                        runs: 0,
+                        failures: 0,
+                    });
+                test.runs += 1;
+                if failed {
+                    test.failures += 1;
+                }
+            }
+        }
+    }
+
+    /// the number of runs recorded
+    pub fn runs(&self) -> usize {
+        self.runs
+    }
+
+    /// the results of a specific test
+    pub fn get(&self, class: &str, name: &str) -> Option<&FlakyTest> {
+        self.tests.get(&(class.to_string(), name.to_string()))
+    }
+
+    /// the tests that meet the flakiness criteria, in class and name order
+    pub fn flaky(&self) -> Vec<&FlakyTest> {
+        self.tests.values().filter(|t| self.is_flaky(t)).collect()
+    }
+
+    fn is_flaky(&self, test: &FlakyTest) -> bool {
+        test.is_flaky() && test.runs >= self.min_runs && test.flake_rate() >= self.min_rate
+    }
+
+    /// Rewrites a JUnit XML Report marking the failures and errors of flaky tests. Returns the
+    /// number of test cases that were marked.
+    pub fn mark<R: Read, W: Write>(
+        &self,
+        reader: R,
+        writer: &mut W,
+        mark: FlakyMark,
+    ) -> anyhow::Result<usize> {
+        let events = EventReader::new_with_config(reader, round_trip_xml_input())
+            .into_iter()
+            .collect::<Result<Vec<XmlEvent>, _>>()?;
+
+        // first pass finds the failures to mark so that the totals of every enclosing
+        // `testsuite` and `testsuites` can be corrected
+        let mut marked: HashMap<usize, String> = HashMap::new();
+        let mut replaced: HashMap<usize, String> = HashMap::new();
+        let mut adjustments: HashMap<usize, (i32, i32)> = HashMap::new();
+        let mut properties: HashMap<usize, usize> = HashMap::new();
+        let mut suites: Vec<usize> = Vec::new();
+        let mut case: Option<(usize, usize, Option<String>)> = None;
+        let mut depth = 0;
+        for (index, event) in events.iter().enumerate() {
+            match event {
+                XmlEvent::StartElement {
+                    name, attributes, ..
+                } => {
+                    depth += 1;
+                    match name.local_name.as_str() {
+                        "testsuite" | "testsuites" => suites.push(index),
+                        "testcase" => {
+                            let flaky = self
+                                .get(
+                                    &attr(attributes, "classname").unwrap_or_default(),
+                                    &attr(attributes, "name").unwrap_or_default(),
+                                )
+                                .filter(|t| self.is_flaky(t))
+                                .map(|t| t.as_str());
+                            case = Some((index, depth, flaky));
+                        }
+                        "properties" => {
+                            if let Some((case_index, case_depth, Some(_))) = &case {
+                                if depth == case_depth + 1 {
+                                    properties.entry(*case_index).or_insert(index);
+                                }
+                            }
+                        }
+                        "failure" | "error" => {
+                            if let Some((case_index, case_depth, Some(reason))) = &case {
+                                if depth == case_depth + 1 {
+                                    marked.insert(*case_index, reason.clone());
+                                    replaced.insert(index, reason.clone());
+                                    for suite in &suites {
+                                        let totals = adjustments.entry(*suite).or_default();
+                                        if name.local_name == "failure" {
+                                            totals.0 += 1;
+                                        } else {
+                                            totals.1 += 1;
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                        _ => (),
+                    }
+                }
+                XmlEvent::EndElement { name } => {
+                    depth -= 1;
+                    match name.local_name.as_str() {
+                        "testsuite" | "testsuites" => {
+                            suites.pop();
+                        }
+                        "testcase" => case = None,
+                        _ => (),
+                    }
+                }
+                _ => (),
+            }
+        }
+        // the flaky property joins any properties the test case already has
+        let extended: HashMap<usize, usize> = properties
+            .iter()
+            .filter(|(case_index, _)| marked.contains_key(case_index))
+            .map(|(case_index, index)| (*index, *case_index))
+            .collect();
+
+        let mut sink = EventWriter::new_with_config(writer, round_trip_xml_output());
+        let mut dropping = 0;
+        for (index, event) in events.iter().enumerate() {
+            if dropping > 0 {
+                match event {
+                    XmlEvent::StartElement { .. } => dropping += 1,
+                    XmlEvent::EndElement { .. } => {
+                        dropping -= 1;
+                        if dropping == 0 {
+                            sink.write(xml::writer::XmlEvent::end_element())?;
+                        }
+                    }
+                    _ => (),
+                }
+                continue;
+            }
+            match event {
+                XmlEvent::StartElement {
+                    name,
+                    attributes,
+                    namespace,
+                } if mark == FlakyMark::Skipped && adjustments.contains_key(&index) => {
+                    let (failures, errors) = adjustments[&index];
+                    let mut attributes: Vec<OwnedAttribute> = attributes
+                        .iter()
+                        .map(|a| {
+                            let delta = match a.name.local_name.as_str() {
+                                "failures" => -failures,
+                                "errors" => -errors,
+                                "skipped" => failures + errors,
+                                _ => return a.clone(),
+                            };
+                            let value = a.value.trim().parse::<i32>().unwrap_or(0) + delta;
+                            OwnedAttribute::new(a.name.clone(), format!("{}", value))
+                        })
+                        .collect();
+                    if attr(&attributes, "skipped").is_none() {
+                        attributes.push(OwnedAttribute::new(
+                            OwnedName::local("skipped"),
+                            format!("{}", failures + errors),
+                        ));
+                    }
+                    let event = XmlEvent::StartElement {
+                        name: name.clone(),
+                        attributes,
+                        namespace: namespace.clone(),
+                    };
+                    for e in event.to_write() {
+                        sink.write(e)?;
+                    }
+                }
+                _ if mark == FlakyMark::Skipped && replaced.contains_key(&index) => {
+                    let reason = format!("flaky: {}", replaced[&index]);
+                    sink.write(
+                        xml::writer::XmlEvent::start_element("skipped").attr("message", &reason),
+                    )?;
+                    dropping = 1;
+                }
+                _ if mark == FlakyMark::Property && extended.contains_key(&index) => {
+                    for e in event.to_write() {
+                        sink.write(e)?;
+                    }
+                    sink.write(
+                        xml::writer::XmlEvent::start_element("property")
+                            .attr("name", "flaky")
+                            .attr("value", &marked[&extended[&index]]),
+                    )?;
+                    sink.write(xml::writer::XmlEvent::end_element())?;
+                }
+                _ if mark == FlakyMark::Property && marked.contains_key(&index) => {
+                    for e in event.to_write() {
+                        sink.write(e)?;
+                    }
+                    if !properties.contains_key(&index) {
+                        sink.write(xml::writer::XmlEvent::start_element("properties"))?;
+                        sink.write(
+                            xml::writer::XmlEvent::start_element("property")
+                                .attr("name", "flaky")
+                                .attr("value", &marked[&index]),
+                        )?;
+                        sink.write(xml::writer::XmlEvent::end_element())?;
+                        sink.write(xml::writer::XmlEvent::end_element())?;
+                    }
+                }
+                _ => {
+                    for e in event.to_write() {
+                        sink.write(e)?;
+                    }
+                }
+            }
+        }
+        Ok(marked.len())
+    }
+}
+
+fn attr(attributes: &[OwnedAttribute], name: &str) -> Option<String> {
+    attributes
+        .iter()
+        .find(|a| a.name.local_name == name)
+        .map(|a| a.value.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::reports::{FlakyDetector, FlakyMark, TestCase, TestResult, TestSuite};
+    use chrono::Duration;
+    use std::io::Cursor;
+
+    fn run(a: TestResult<'static>, b: TestResult<'static>) -> Vec<TestSuite<'static>> {
+        vec![TestSuite::new("suite")
+            .push(TestCase::new("a", "foo", &a, Duration::milliseconds(1)))
+            .push(TestCase::new("b", "foo", &b, Duration::milliseconds(1)))]
+    }
+
+    fn detector() -> FlakyDetector {
+        let mut detector = FlakyDetector::new();
+        detector.add_run(&run(TestResult::success(), TestResult::failure("x")));
+        detector.add_run(&run(TestResult::failure("x"), TestResult::failure("x")));
+        detector.add_run(&run(TestResult::success(), TestResult::failure("x")));
+        detector.add_run(&run(TestResult::skipped("x"), TestResult::failure("x")));
+        detector
+    }
+
+    #[test]
+    fn detect() {
+        let detector = detector();
+        assert_eq!(detector.runs(), 4);
+        let flaky = detector.flaky();
+        assert_eq!(flaky.len(), 1);
+        assert_eq!(flaky[0].name(), "a");
+        assert_eq!(flaky[0].class(), "foo");
+        assert_eq!(flaky[0].runs(), 3);
+        assert_eq!(flaky[0].failures(), 1);
+        assert_eq!(
+            flaky[0].as_str(),
+            "a(foo) failed 1 of 3 runs (33% flake rate)"
+        );
+        assert!(!detector.get("foo", "b").unwrap().is_flaky());
+    }
+
+    #[test]
+    fn thresholds() {
+        assert_eq!(detector().min_rate(0.5).flaky().len(), 0);
+        assert_eq!(detector().min_runs(4).flaky().len(), 0);
+        assert_eq!(detector().min_runs(3).min_rate(0.3).flaky().len(), 1);
+    }
+
+    #[test]
+    fn mark_property() {
+        let xml = include_str!("../../test/flaky/latest.xml");
+        let mut out = Vec::new();
+        let count = detector()
+            .mark(Cursor::new(xml.as_bytes()), &mut out, FlakyMark::Property)
+            .unwrap();
+        assert_eq!(count, 1);
+        assert_eq!(
+            String::from_utf8_lossy(&out).replace(" />", "/>").trim(),
+            include_str!("../../test/flaky/latest-property.xml")
+                .to_string()
+                .replace(" />", "/>")
+                .trim()
+        );
+    }
+
+    #[test]
+    fn mark_existing_property() {
+        let xml = include_str!("../../test/flaky/latest-existing.xml");
+        let mut out = Vec::new();
+        let count = detector()
+            .mark(Cursor::new(xml.as_bytes()), &mut out, FlakyMark::Property)
+            .unwrap();
+        assert_eq!(count, 1);
+        assert_eq!(
+            String::from_utf8_lossy(&out).replace(" />", "/>").trim(),
+            include_str!("../../test/flaky/latest-existing-property.xml")
+                .to_string()
+                .replace(" />", "/>")
+                .trim()
+        );
+    }
+
+    #[test]
+    fn mark_skipped_without_count() {
+        let xml = include_str!("../../test/flaky/latest-existing.xml");
+        let mut out = Vec::new();
+        let count = detector()
+            .mark(Cursor::new(xml.as_bytes()), &mut out, FlakyMark::Skipped)
+            .unwrap();
+        assert_eq!(count, 1);
+        assert_eq!(
+            String::from_utf8_lossy(&out).replace(" />", "/>").trim(),
+            include_str!("../../test/flaky/latest-existing-skipped.xml")
+                .to_string()
+                .replace(" />", "/>")
+                .trim()
+        );
+    }
+
+    #[test]
+    fn mark_skipped_suites() {
+        let xml = include_str!("../../test/flaky/latest-suites.xml");
+        let mut out = Vec::new();
+        let count = detector()
+            .mark(Cursor::new(xml.as_bytes()), &mut out, FlakyMark::Skipped)
+            .unwrap();
+        assert_eq!(count, 1);
+        assert_eq!(
+            String::from_utf8_lossy(&out).replace(" />", "/>").trim(),
+            include_str!("../../test/flaky/latest-suites-skipped.xml")
+                .to_string()
+                .replace(" />", "/>")
+                .trim()
+        );
+    }
+
+    #[test]
+    fn mark_skipped() {
+        let xml = include_str!("../../test/flaky/latest.xml");
+        let mut out = Vec::new();
+        let count = detector()
+            .mark(Cursor::new(xml.as_bytes()), &mut out, FlakyMark::Skipped)
+            .unwrap();
+        assert_eq!(count, 1);
+        assert_eq!(
+            String::from_utf8_lossy(&out).replace(" />", "/>").trim(),
+            include_str!("../../test/flaky/latest-skipped.xml")
+                .to_string()
+                .replace(" />", "/>")
+                .trim()
+        );
+    }
+}
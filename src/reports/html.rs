@@ -0,0 +1,366 @@
+/*
+ * Copyright (c) 2020 Stephen Connolly and CloudBees, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use crate::reports::{TestCase, TestResult, TestSuite};
+use regex::{Captures, Regex};
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+
+const STYLE: &str = r#"
+body { font-family: sans-serif; margin: 2em; color: #222; }
+table { border-collapse: collapse; width: 100%; margin-bottom: 2em; }
+th, td { text-align: left; padding: 0.3em 0.6em; border-bottom: 1px solid #ddd; vertical-align: top; }
+th { background: #f4f4f4; }
+pre { background: #f8f8f8; padding: 0.6em; overflow-x: auto; white-space: pre-wrap; }
+.summary span { margin-right: 1.5em; }
+.success { color: #2a7a2a; }
+.failure, .error { color: #b00020; }
+.skipped { color: #8a6d00; }
+.flaky { color: #b35c00; }
+#filter { margin-bottom: 1em; }
+"#;
+
+const SCRIPT: &str = r#"
+function filterTests() {
+  var text = document.getElementById('filter-text').value.toLowerCase();
+  var status = document.getElementById('filter-status').value;
+  var rows = document.querySelectorAll('#tests tbody tr');
+  for (var i = 0; i < rows.length; i++) {
+    var row = rows[i];
+    var matches = row.textContent.toLowerCase().indexOf(text) >= 0
+      && (status === '' || row.getAttribute('data-status') === status);
+    row.style.display = matches ? '' : 'none';
+  }
+}
+"#;
+
+/// Renders JUnit test suites as a single self-contained HTML page.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HtmlReport {
+    title: String,
+    output_dir: Option<PathBuf>,
+    base_dir: Option<PathBuf>,
+}
+
+impl Default for HtmlReport {
+    fn default() -> Self {
+        HtmlReport {
+            title: "Test Results".to_string(),
+            output_dir: None,
+            base_dir: None,
+        }
+    }
+}
+
+impl HtmlReport {
+    pub fn new() -> HtmlReport {
+        HtmlReport {
+            ..Default::default()
+        }
+    }
+
+    pub fn title(self, title: &str) -> HtmlReport {
+        HtmlReport {
+            title: title.to_string(),
+            ..self
+        }
+    }
+
+    /// the directory the HTML report will be written to, so that links to attachments can be
+    /// made relative to the report
+    pub fn output_dir(self, output_dir: &Path) -> HtmlReport {
+        HtmlReport {
+            output_dir: Some(output_dir.to_path_buf()),
+            ..self
+        }
+    }
+
+    /// the directory that relative attachment paths are resolved against
+    pub fn base_dir(self, base_dir: &Path) -> HtmlReport {
+        HtmlReport {
+            base_dir: Some(base_dir.to_path_buf()),
+            ..self
+        }
+    }
+
+    pub fn write<W: Write>(&self, suites: &[TestSuite<'_>], writer: &mut W) -> anyhow::Result<()> {
+        let title = escape_html(&self.title);
+        writeln!(writer, "<!DOCTYPE html>")?;
+        writeln!(writer, "<html lang=\"en\">")?;
+        writeln!(writer, "<head>")?;
+        writeln!(writer, "<meta charset=\"utf-8\">")?;
+        writeln!(writer, "<title>{}</title>", title)?;
+        writeln!(writer, "<style>{}</style>", STYLE)?;
+        writeln!(writer, "<script>{}</script>", SCRIPT)?;
+        writeln!(writer, "</head>")?;
+        writeln!(writer, "<body>")?;
+        writeln!(writer, "<h1>{}</h1>", title)?;
+
+        let mut totals = (0, 0, 0, 0, 0);
+        for suite in suites {
+            totals.0 += suite.test_count();
+            totals.1 += suite.failure_count();
+            totals.2 += suite.error_count();
+            totals.3 += suite.skipped_count();
+            totals.4 += suite.flaky_count();
+        }
+        writeln!(
+            writer,
+            "<p class=\"summary\"><span>Tests: {}</span><span class=\"failure\">Failures: {}</span>\
+            <span class=\"error\">Errors: {}</span><span class=\"skipped\">Skipped: {}</span>\
+            <span class=\"flaky\">Flakes: {}</span></p>",
+            totals.0, totals.1, totals.2, totals.3, totals.4
+        )?;
+
+        writeln!(writer, "<h2>Test suites</h2>")?;
+        writeln!(writer, "<table id=\"suites\">")?;
+        writeln!(
+            writer,
+            "<thead><tr><th>Suite</th><th>Tests</th><th>Failures</th><th>Errors</th>\
+            <th>Skipped</th><th>Time (s)</th></tr></thead>"
+        )?;
+        writeln!(writer, "<tbody>")?;
+        for suite in suites {
+            writeln!(
+                writer,
+                "<tr class=\"{}\"><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                if suite.as_exit_code() == 0 {
+                    "success"
+                } else {
+                    "failure"
+                },
+                escape_html(suite.name()),
+                suite.test_count(),
+                suite.failure_count(),
+                suite.error_count(),
+                suite.skipped_count(),
+                (suite.time().num_milliseconds() as f64) / 1000.0
+            )?;
+        }
+        writeln!(writer, "</tbody>")?;
+        writeln!(writer, "</table>")?;
+
+        writeln!(writer, "<h2>Tests</h2>")?;
+        writeln!(
+            writer,
+            "<div id=\"filter\"><input id=\"filter-text\" type=\"search\" placeholder=\"Filter tests\" \
+            oninput=\"filterTests()\"> <select id=\"filter-status\" onchange=\"filterTests()\">\
+            <option value=\"\">All</option><option value=\"success\">Passed</option>\
+            <option value=\"failure\">Failed</option><option value=\"error\">Errors</option>\
+            <option value=\"skipped\">Skipped</option><option value=\"flaky\">Flaky</option>\
+            </select></div>"
+        )?;
+        writeln!(writer, "<table id=\"tests\">")?;
+        writeln!(
+            writer,
+            "<thead><tr><th>Suite</th><th>Class</th><th>Test</th><th>Status</th>\
+            <th>Time (s)</th></tr></thead>"
+        )?;
+        writeln!(writer, "<tbody>")?;
+        // see https://github.com/jenkinsci/junit-attachments-plugin/blob/3db4f1724bddf0380ad24858d50fe551afb55e4c/src/main/java/hudson/plugins/junitattachments/GetTestDataMethodObject.java#L171-L206
+        let attachment = Regex::new(r"\[\[ATTACHMENT\|([^]]+)]]").unwrap();
+        for suite in suites {
+            for case in suite.cases() {
+                self.write_case(suite, case, &attachment, writer)?;
+            }
+        }
+        writeln!(writer, "</tbody>")?;
+        writeln!(writer, "</table>")?;
+        writeln!(writer, "</body>")?;
+        writeln!(writer, "</html>")?;
+        Ok(())
+    }
+
+    fn write_case<W: Write>(
+        &self,
+        suite: &TestSuite<'_>,
+        case: &TestCase<'_>,
+        attachment: &Regex,
+        writer: &mut W,
+    ) -> anyhow::Result<()> {
+        let status = match case.result() {
+            TestResult::Success if case.is_flaky() => "flaky",
+            TestResult::Success => "success",
+            TestResult::Failure { .. } => "failure",
+            TestResult::Error { .. } => "error",
+            TestResult::Skipped { .. } => "skipped",
+        };
+        write!(
+            writer,
+            "<tr data-status=\"{}\"><td>{}</td><td>{}</td><td>",
+            status,
+            escape_html(suite.name()),
+            escape_html(case.class())
+        )?;
+        let mut details = String::new();
+        if let Some(message) = case.result().message() {
+            if !message.is_empty() {
+                details.push_str(&format!("<p>{}</p>", escape_html(message)));
+            }
+        }
+        if let Some(detail) = case.result().detail() {
+            details.push_str(&format!("<pre>{}</pre>", escape_html(detail)));
+        }
+        for (index, rerun) in case.reruns().iter().enumerate() {
+            details.push_str(&format!(
+                "<p>Attempt {}: {}</p>",
+                index + 1,
+                escape_html(rerun.result().message().unwrap_or_default())
+            ));
+            if let Some(detail) = rerun.result().detail() {
+                details.push_str(&format!("<pre>{}</pre>", escape_html(detail)));
+            }
+        }
+        if !case.stdout().is_empty() {
+            details.push_str(&format!(
+                "<h4>Standard output</h4><pre>{}</pre>",
+                self.with_attachments(case.stdout(), attachment)
+            ));
+        }
+        if !case.stderr().is_empty() {
+            details.push_str(&format!(
+                "<h4>Standard error</h4><pre>{}</pre>",
+                self.with_attachments(case.stderr(), attachment)
+            ));
+        }
+        if details.is_empty() {
+            write!(writer, "{}", escape_html(case.name()))?;
+        } else {
+            write!(
+                writer,
+                "<details><summary>{}</summary>{}</details>",
+                escape_html(case.name()),
+                details
+            )?;
+        }
+        writeln!(
+            writer,
+            "</td><td class=\"{}\">{}</td><td>{}</td></tr>",
+            status,
+            status,
+            (case.time().num_milliseconds() as f64) / 1000.0
+        )?;
+        Ok(())
+    }
+
+    /// escapes captured output, turning any Jenkins JUnit attachment markers into links
+    fn with_attachments(&self, text: &str, attachment: &Regex) -> String {
+        let text = escape_html(text);
+        attachment
+            .replace_all(&text, |caps: &Captures| {
+                let file_name = caps.get(1).unwrap().as_str().replace('\\', "/");
+                format!("<a href=\"{}\">{}</a>", self.link(&file_name), file_name)
+            })
+            .to_string()
+    }
+
+    /// the link to an attachment, relative to the output directory where possible
+    fn link(&self, file_name: &str) -> String {
+        let path = Path::new(file_name);
+        let path = match &self.base_dir {
+            Some(base_dir) if path.is_relative() => base_dir.join(path),
+            _ => path.to_path_buf(),
+        };
+        match &self.output_dir {
+            Some(output_dir) if path.is_absolute() && output_dir.is_absolute() => {
+                relative_path(output_dir, &path)
+            }
+            _ => file_name.to_string(),
+        }
+    }
+}
+
+/// the path to the target from the directory, both of which must be absolute
+fn relative_path(dir: &Path, target: &Path) -> String {
+    let dir: Vec<Component> = dir.components().collect();
+    let target: Vec<Component> = target.components().collect();
+    let common = dir
+        .iter()
+        .zip(target.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut parts: Vec<String> = dir[common..].iter().map(|_| "..".to_string()).collect();
+    parts.extend(
+        target[common..]
+            .iter()
+            .map(|c| c.as_os_str().to_string_lossy().to_string()),
+    );
+    parts.join("/")
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::reports::{HtmlReport, TestCase, TestRerun, TestResult, TestSuite};
+    use chrono::Duration;
+    use std::borrow::Cow;
+    use std::path::Path;
+
+    #[test]
+    fn render() {
+        let suites = vec![TestSuite::new("a <suite>")
+            .push(TestCase::new(
+                "passes",
+                "foo",
+                &TestResult::success(),
+                Duration::milliseconds(1500),
+            ))
+            .push(TestCase::new_with_output(
+                "fails",
+                "foo",
+                &TestResult::failure("expected 1 & 2").with_detail("at foo.rs:42"),
+                Cow::Borrowed("see [[ATTACHMENT|logs/foo.log]]"),
+                Cow::Borrowed(""),
+                Duration::milliseconds(10),
+            ))
+            .push(
+                TestCase::new("flakes", "foo", &TestResult::success(), Duration::zero())
+                    .rerun(TestRerun::new(&TestResult::error("timeout"))),
+            )];
+        let mut out = Vec::new();
+        HtmlReport::new()
+            .title("Nightly")
+            .output_dir(Path::new("/work/report"))
+            .base_dir(Path::new("/work"))
+            .write(&suites, &mut out)
+            .unwrap();
+        let html = String::from_utf8(out).unwrap();
+        assert!(html.contains("<title>Nightly</title>"));
+        assert!(html.contains(
+            "<td>a &lt;suite&gt;</td><td>3</td><td>1</td><td>0</td><td>0</td><td>1.51</td>"
+        ));
+        assert!(html.contains("<span>Tests: 3</span><span class=\"failure\">Failures: 1</span>"));
+        assert!(html.contains(
+            "<tr data-status=\"success\"><td>a &lt;suite&gt;</td><td>foo</td><td>passes</td>"
+        ));
+        assert!(html.contains("<p>expected 1 &amp; 2</p><pre>at foo.rs:42</pre>"));
+        assert!(html.contains("see <a href=\"../logs/foo.log\">logs/foo.log</a>"));
+        assert!(html.contains("<tr data-status=\"flaky\">"));
+        assert!(html.contains("<p>Attempt 1: timeout</p>"));
+    }
+}
@@ -0,0 +1,220 @@
+/*
+ * Copyright (c) 2020 Stephen Connolly and CloudBees, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use crate::reports::{pretty_xml_output, TestCase, TestSuite};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use xml::EventWriter;
+
+/// a JUnit XML report on disk that is rewritten at intervals while its test cases are produced
+#[derive(Debug)]
+pub struct IncrementalReport {
+    path: PathBuf,
+    name: String,
+    interval: Duration,
+    state: Arc<Mutex<ReportState>>,
+    timer: Option<Timer>,
+}
+
+/// the thread that writes the report at intervals, which stops when dropped
+#[derive(Debug)]
+struct Timer {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+/// the test suite as recorded so far and whether it has been written
+#[derive(Debug)]
+struct ReportState {
+    suite: TestSuite<'static>,
+    written: Option<Instant>,
+    dirty: bool,
+}
+
+impl IncrementalReport {
+    pub fn new(path: &'_ Path, suite: TestSuite<'static>) -> IncrementalReport {
+        IncrementalReport {
+            path: path.to_path_buf(),
+            name: suite.name().to_string(),
+            interval: Duration::from_secs(10),
+            state: Arc::new(Mutex::new(ReportState {
+                suite,
+                written: None,
+                dirty: true,
+            })),
+            timer: None,
+        }
+    }
+
+    /// sets the minimum time between writes of the report
+    pub fn interval(self, interval: Duration) -> IncrementalReport {
+        IncrementalReport { interval, ..self }
+    }
+
+    /// the file the report is written to
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// the name of the test suite
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// adds a completed test case, writing the report if the interval has elapsed. The report is
+    /// also written once the interval elapses without another test case.
+    pub fn push(&mut self, case: TestCase<'static>) -> anyhow::Result<()> {
+        if self.timer.is_none() {
+            self.timer = Some(self.start_timer());
+        }
+        let mut state = self.state.lock().unwrap();
+        let suite = std::mem::replace(&mut state.suite, TestSuite::new(""));
+        state.suite = suite.push(case);
+        state.dirty = true;
+        match state.written {
+            Some(written) if written.elapsed() < self.interval => Ok(()),
+            _ => state.write(&self.path),
+        }
+    }
+
+    /// writes the report if anything changed since the last write
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        self.state.lock().unwrap().write(&self.path)
+    }
+
+    fn start_timer(&self) -> Timer {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let state = Arc::clone(&self.state);
+        let path = self.path.clone();
+        let interval = self.interval;
+        let thread = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                if let Err(e) = state.lock().unwrap().write(&path) {
+                    warn!("Could not write partial test results: {:?}", e);
+                }
+            }
+        });
+        Timer {
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        // the report is not written again once the thread has stopped
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl ReportState {
+    /// writes the report if anything changed since the last write, via a temporary file so that
+    /// readers never see a partially written report
+    fn write(&mut self, path: &Path) -> anyhow::Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        let mut temp = path.to_path_buf().into_os_string();
+        temp.push(".tmp");
+        let temp = PathBuf::from(temp);
+        let file = File::create(&temp)?;
+        self.suite
+            .write(&mut EventWriter::new_with_config(file, pretty_xml_output()))?;
+        std::fs::rename(&temp, path)?;
+        self.written = Some(Instant::now());
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::reports::{read_junit, IncrementalReport, TestCase, TestResult, TestSuite};
+    use chrono::Duration;
+    use std::fs::File;
+    use std::io::BufReader;
+
+    #[test]
+    fn writes_partial_reports() {
+        let path =
+            std::env::temp_dir().join(format!("juxr-incremental-{}.xml", std::process::id()));
+        let read = || {
+            read_junit(BufReader::new(File::open(&path).unwrap()))
+                .unwrap()
+                .remove(0)
+        };
+        let mut report = IncrementalReport::new(&path, TestSuite::new("suite"))
+            .interval(std::time::Duration::from_secs(3600));
+        report
+            .push(TestCase::new(
+                "a",
+                "foo",
+                &TestResult::Success,
+                Duration::milliseconds(1),
+            ))
+            .unwrap();
+        assert_eq!(read().test_count(), 1);
+
+        report
+            .push(TestCase::new(
+                "b",
+                "foo",
+                &TestResult::Success,
+                Duration::milliseconds(1),
+            ))
+            .unwrap();
+        assert_eq!(read().test_count(), 1);
+
+        report.flush().unwrap();
+        let suite = read();
+        assert_eq!(suite.test_count(), 2);
+        assert_eq!(suite.name(), "suite");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn writes_on_a_timer() {
+        let path = std::env::temp_dir().join(format!("juxr-timer-{}.xml", std::process::id()));
+        let read = || {
+            read_junit(BufReader::new(File::open(&path).unwrap()))
+                .unwrap()
+                .remove(0)
+        };
+        let mut report = IncrementalReport::new(&path, TestSuite::new("suite"))
+            .interval(std::time::Duration::from_millis(50));
+        for name in &["a", "b"] {
+            report
+                .push(TestCase::new(
+                    name,
+                    "foo",
+                    &TestResult::Success,
+                    Duration::milliseconds(1),
+                ))
+                .unwrap();
+        }
+        // the second test case is written without another test case or a flush
+        std::thread::sleep(std::time::Duration::from_millis(500));
+        assert_eq!(read().test_count(), 2);
+        drop(report);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
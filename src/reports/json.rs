@@ -0,0 +1,527 @@
+/*
+ * Copyright (c) 2020 Stephen Connolly and CloudBees, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use crate::reports::{TestCase, TestRerun, TestResult, TestSuite};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::borrow::Cow;
+use std::io::{Read, Write};
+
+/// The JSON schema of the reports produced by [`write_json`] and consumed by [`read_json`].
+pub const JSON_SCHEMA: &str = include_str!("../../schema/ctrf-juxr.schema.json");
+
+// The document follows the Common Test Report Format (https://ctrf.io), with the JUnit specific
+// details that CTRF has no place for kept in the `extra` objects so that reports round-trip.
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Document {
+    #[serde(default = "report_format")]
+    report_format: String,
+    #[serde(default = "spec_version")]
+    spec_version: String,
+    results: Results,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Results {
+    tool: Tool,
+    summary: Summary,
+    tests: Vec<Test>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    extra: Option<ResultsExtra>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Tool {
+    name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    version: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Summary {
+    tests: usize,
+    passed: usize,
+    failed: usize,
+    pending: usize,
+    skipped: usize,
+    other: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    flaky: Option<usize>,
+    start: i64,
+    stop: i64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct ResultsExtra {
+    #[serde(default)]
+    suites: Vec<SuiteExtra>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SuiteExtra {
+    name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    timestamp: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    hostname: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Test {
+    name: String,
+    status: String,
+    #[serde(deserialize_with = "millis")]
+    duration: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    suite: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    raw_status: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    trace: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    file_path: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    line: Option<u32>,
+    #[serde(default, skip_serializing_if = "is_false")]
+    flaky: bool,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    retries: usize,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    retry_attempts: Vec<RetryAttempt>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    stdout: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    stderr: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    extra: Option<TestExtra>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RetryAttempt {
+    attempt: usize,
+    status: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    raw_status: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    trace: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    stdout: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    stderr: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    extra: Option<TestExtra>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct TestExtra {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    classname: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    r#type: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    properties: Vec<Property>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Property {
+    name: String,
+    value: String,
+}
+
+fn report_format() -> String {
+    "CTRF".to_string()
+}
+
+fn spec_version() -> String {
+    "0.0.0".to_string()
+}
+
+/// CTRF durations are numbers, but some tools report fractions of a millisecond
+fn millis<'de, D: Deserializer<'de>>(deserializer: D) -> Result<i64, D::Error> {
+    f64::deserialize(deserializer).map(|m| m.round() as i64)
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+fn is_zero(value: &usize) -> bool {
+    *value == 0
+}
+
+/// Writes the test suites as a CTRF JSON report.
+pub fn write_json<W: Write>(suites: &[TestSuite<'_>], writer: W) -> anyhow::Result<()> {
+    let mut tests = Vec::new();
+    let mut summary = Summary {
+        tests: 0,
+        passed: 0,
+        failed: 0,
+        pending: 0,
+        skipped: 0,
+        other: 0,
+        flaky: None,
+        start: 0,
+        stop: 0,
+    };
+    let mut flaky = 0;
+    let mut duration = Duration::zero();
+    for suite in suites {
+        for case in suite.cases() {
+            let test = to_test(suite, case);
+            summary.tests += 1;
+            // errors are failed tests in CTRF
+            match test.status.as_str() {
+                "passed" => summary.passed += 1,
+                "failed" => summary.failed += 1,
+                "skipped" => summary.skipped += 1,
+                "pending" => summary.pending += 1,
+                _ => summary.other += 1,
+            }
+            if test.flaky {
+                flaky += 1;
+            }
+            tests.push(test);
+        }
+        duration = duration + suite.time();
+    }
+    if flaky > 0 {
+        summary.flaky = Some(flaky);
+    }
+    // CTRF times are milliseconds since the epoch
+    summary.start = suites
+        .iter()
+        .filter_map(|s| s.timestamp())
+        .min()
+        .map(|t| t.timestamp_millis())
+        .unwrap_or(0);
+    summary.stop = summary.start + duration.num_milliseconds();
+    let document = Document {
+        report_format: report_format(),
+        spec_version: spec_version(),
+        results: Results {
+            tool: Tool {
+                name: env!("CARGO_PKG_NAME").to_string(),
+                version: Some(env!("CARGO_PKG_VERSION").to_string()),
+            },
+            summary,
+            tests,
+            extra: Some(ResultsExtra {
+                suites: suites
+                    .iter()
+                    .map(|s| SuiteExtra {
+                        name: s.name().to_string(),
+                        timestamp: s.timestamp().map(|t| t.to_rfc3339()),
+                        hostname: s.hostname().map(|h| h.to_string()),
+                    })
+                    .collect(),
+            }),
+        },
+    };
+    serde_json::to_writer_pretty(writer, &document)?;
+    Ok(())
+}
+
+/// Reads a CTRF JSON report into test suites. Tests are grouped into suites by their `suite` and
+/// tests without a suite are grouped into a suite named after the tool.
+pub fn read_json<'a, R: Read>(input: R) -> anyhow::Result<Vec<TestSuite<'a>>> {
+    let document: Document = serde_json::from_reader(input)?;
+    let results = document.results;
+    let mut suites: Vec<(TestSuite<'a>, Vec<TestCase<'a>>)> = Vec::new();
+    for extra in results.extra.unwrap_or_default().suites {
+        let mut suite = TestSuite::new(&extra.name);
+        if let Some(timestamp) = extra
+            .timestamp
+            .and_then(|t| DateTime::parse_from_rfc3339(&t).ok())
+        {
+            suite = suite.with_timestamp(timestamp.with_timezone(&Utc));
+        }
+        if let Some(hostname) = &extra.hostname {
+            suite = suite.with_hostname(hostname);
+        }
+        suites.push((suite, Vec::new()));
+    }
+    let tool = results.tool.name;
+    for test in results.tests {
+        let name = test.suite.clone().unwrap_or_else(|| tool.clone());
+        let position = match suites.iter().position(|(s, _)| s.name() == name) {
+            Some(position) => position,
+            None => {
+                suites.push((TestSuite::new(&name), Vec::new()));
+                suites.len() - 1
+            }
+        };
+        suites[position].1.push(to_case(test));
+    }
+    // without per-suite timestamps the best we have is when the tests started
+    let start = if results.summary.start > 0 && suites.iter().all(|(s, _)| s.timestamp().is_none())
+    {
+        Utc.timestamp_millis_opt(results.summary.start).single()
+    } else {
+        None
+    };
+    Ok(suites
+        .into_iter()
+        .map(|(suite, cases)| {
+            let suite = match start {
+                Some(start) => suite.with_timestamp(start),
+                None => suite,
+            };
+            cases.into_iter().fold(suite, |s, c| s.push(c))
+        })
+        .collect())
+}
+
+fn to_test(suite: &TestSuite<'_>, case: &TestCase<'_>) -> Test {
+    let (status, raw_status, type_) = to_status(case.result());
+    let extra = TestExtra {
+        classname: Some(case.class().to_string()),
+        r#type: type_,
+        properties: case
+            .properties()
+            .iter()
+            .map(|(name, value)| Property {
+                name: name.to_string(),
+                value: value.to_string(),
+            })
+            .collect(),
+    };
+    Test {
+        name: case.name().to_string(),
+        status,
+        duration: case.time().num_milliseconds(),
+        suite: Some(suite.name().to_string()),
+        raw_status,
+        message: case.result().message().map(|m| m.to_string()),
+        trace: case.result().detail().map(|d| d.to_string()),
+        file_path: case.file().map(|f| f.to_string()),
+        line: case.line(),
+        flaky: case.is_flaky(),
+        retries: case.reruns().len(),
+        retry_attempts: case
+            .reruns()
+            .iter()
+            .enumerate()
+            .map(|(index, rerun)| {
+                let (status, raw_status, type_) = to_status(rerun.result());
+                RetryAttempt {
+                    attempt: index + 1,
+                    status,
+                    raw_status,
+                    message: rerun.result().message().map(|m| m.to_string()),
+                    trace: rerun.result().detail().map(|d| d.to_string()),
+                    stdout: lines(rerun.stdout()),
+                    stderr: lines(rerun.stderr()),
+                    extra: type_.map(|t| TestExtra {
+                        r#type: Some(t),
+                        ..Default::default()
+                    }),
+                }
+            })
+            .collect(),
+        stdout: lines(case.stdout()),
+        stderr: lines(case.stderr()),
+        extra: Some(extra),
+    }
+}
+
+fn to_case<'a>(test: Test) -> TestCase<'a> {
+    let extra = test.extra.unwrap_or_default();
+    let result = to_result(
+        &test.status,
+        test.raw_status.as_deref(),
+        extra.r#type.as_deref(),
+        test.message.as_deref(),
+        test.trace.as_deref(),
+    );
+    let mut case = TestCase::new_with_output(
+        &test.name,
+        &extra.classname.unwrap_or_default(),
+        &result,
+        Cow::Owned(test.stdout.join("\n")),
+        Cow::Owned(test.stderr.join("\n")),
+        Duration::milliseconds(test.duration),
+    );
+    if let Some(file) = &test.file_path {
+        case = case.with_location(file, test.line);
+    }
+    for property in &extra.properties {
+        case = case.with_property(&property.name, &property.value);
+    }
+    for attempt in test.retry_attempts {
+        let type_ = attempt.extra.and_then(|e| e.r#type);
+        let result = to_result(
+            &attempt.status,
+            attempt.raw_status.as_deref(),
+            type_.as_deref(),
+            attempt.message.as_deref(),
+            attempt.trace.as_deref(),
+        );
+        if let TestResult::Failure { .. } | TestResult::Error { .. } = result {
+            case = case.rerun(TestRerun::new_with_output(
+                &result,
+                Cow::Owned(attempt.stdout.join("\n")),
+                Cow::Owned(attempt.stderr.join("\n")),
+            ));
+        }
+    }
+    case
+}
+
+/// the CTRF status, the JUnit result element and the failure type of a result
+fn to_status(result: &TestResult<'_>) -> (String, Option<String>, Option<String>) {
+    match result {
+        TestResult::Success => ("passed".to_string(), None, None),
+        TestResult::Failure { type_, .. } => (
+            "failed".to_string(),
+            Some("failure".to_string()),
+            Some(type_.to_string()),
+        ),
+        TestResult::Error { type_, .. } => (
+            "failed".to_string(),
+            Some("error".to_string()),
+            Some(type_.to_string()),
+        ),
+        TestResult::Skipped { .. } => ("skipped".to_string(), None, None),
+    }
+}
+
+fn to_result<'a>(
+    status: &str,
+    raw_status: Option<&str>,
+    type_: Option<&str>,
+    message: Option<&str>,
+    trace: Option<&str>,
+) -> TestResult<'a> {
+    let message = message.unwrap_or_default();
+    let result = match (status, raw_status) {
+        ("passed", _) => TestResult::success(),
+        ("failed", Some("error")) => TestResult::Error {
+            type_: Cow::Owned(type_.unwrap_or("error").to_string()),
+            message: Cow::Owned(message.to_string()),
+            detail: Default::default(),
+        },
+        ("failed", _) => TestResult::Failure {
+            type_: Cow::Owned(type_.unwrap_or("assertion").to_string()),
+            message: Cow::Owned(message.to_string()),
+            detail: Default::default(),
+        },
+        // CTRF's `pending` and `other` have no JUnit equivalent so are treated as skipped
+        _ => TestResult::skipped(message),
+    };
+    match trace {
+        Some(trace) => result.with_detail(trace),
+        None => result,
+    }
+}
+
+/// splits output into lines, CTRF's representation of captured output
+fn lines(text: &str) -> Vec<String> {
+    if text.is_empty() {
+        Vec::new()
+    } else {
+        text.split('\n').map(|l| l.to_string()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::reports::{read_json, read_junit, write_json, TestResult};
+    use std::io::Cursor;
+
+    #[test]
+    fn round_trip() {
+        let xml = include_str!("../../test/report/results.xml");
+        let suites = read_junit(Cursor::new(xml.as_bytes())).unwrap();
+        let mut out = Vec::new();
+        write_json(&suites, &mut out).unwrap();
+        let json = String::from_utf8(out).unwrap();
+        assert!(json.contains("\"reportFormat\": \"CTRF\""));
+        assert!(json.contains("\"status\": \"failed\""));
+        let actual = read_json(Cursor::new(json.as_bytes())).unwrap();
+        assert_eq!(actual.len(), 2);
+        for (expected, actual) in suites.iter().zip(actual.iter()) {
+            assert_eq!(expected.name(), actual.name());
+            assert_eq!(expected.timestamp(), actual.timestamp());
+            assert_eq!(expected.hostname(), actual.hostname());
+            assert_eq!(expected.cases(), actual.cases());
+        }
+    }
+
+    #[test]
+    fn summary() {
+        let xml = include_str!("../../test/report/results.xml");
+        let suites = read_junit(Cursor::new(xml.as_bytes())).unwrap();
+        let mut out = Vec::new();
+        write_json(&suites, &mut out).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        let summary = &json["results"]["summary"];
+        assert_eq!(summary["tests"], 3);
+        assert_eq!(summary["passed"], 0);
+        // the error is a failed test rather than a skipped one
+        assert_eq!(summary["failed"], 2);
+        assert_eq!(summary["skipped"], 1);
+        assert_eq!(summary["other"], 0);
+    }
+
+    #[test]
+    fn round_trip_reruns() {
+        let xml = include_str!("../../test/report/reruns.xml");
+        let suites = read_junit(Cursor::new(xml.as_bytes())).unwrap();
+        let mut out = Vec::new();
+        write_json(&suites, &mut out).unwrap();
+        let actual = read_json(Cursor::new(&out)).unwrap();
+        assert_eq!(suites[0].cases(), actual[0].cases());
+        assert_eq!(actual[0].flaky_count(), 1);
+    }
+
+    #[test]
+    fn read_ctrf() {
+        let json = include_str!("../../test/json/ctrf.json");
+        let suites = read_json(Cursor::new(json.as_bytes())).unwrap();
+        assert_eq!(suites.len(), 2);
+        assert_eq!(suites[0].name(), "login");
+        assert_eq!(
+            suites[0].timestamp().map(|t| t.to_rfc3339()),
+            Some("2020-11-05T10:15:30+00:00".to_string())
+        );
+        assert_eq!(suites[0].test_count(), 2);
+        assert_eq!(suites[0].failure_count(), 1);
+        assert_eq!(
+            suites[0].cases()[1].result(),
+            &TestResult::failure("expected 200").with_detail("at login.spec.js:12")
+        );
+        assert_eq!(suites[0].cases()[1].stdout(), "line 1\nline 2");
+        assert_eq!(suites[1].name(), "jest");
+        assert_eq!(suites[1].skipped_count(), 1);
+    }
+
+    #[test]
+    fn read_start_out_of_range() {
+        let json = r#"{"results": {"tool": {"name": "jest"}, "summary": {"tests": 1, "passed": 1,
+            "failed": 0, "pending": 0, "skipped": 0, "other": 0, "start": 9223372036854775807,
+            "stop": 9223372036854775807},
+            "tests": [{"name": "works", "status": "passed", "duration": 1}]}}"#;
+        let suites = read_json(Cursor::new(json.as_bytes())).unwrap();
+        assert_eq!(suites.len(), 1);
+        assert_eq!(suites[0].timestamp(), None);
+    }
+}
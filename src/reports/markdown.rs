@@ -0,0 +1,302 @@
+/*
+ * Copyright (c) 2020 Stephen Connolly and CloudBees, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use crate::reports::{TestCase, TestResult, TestSuite};
+use chrono::Duration;
+use std::io::Write;
+
+/// Renders JUnit test suites as a GitHub flavoured Markdown summary, suitable for a pull request
+/// comment or `$GITHUB_STEP_SUMMARY`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MarkdownSummary {
+    title: String,
+    max_output_lines: usize,
+}
+
+impl Default for MarkdownSummary {
+    fn default() -> Self {
+        MarkdownSummary {
+            title: "Test Results".to_string(),
+            max_output_lines: 50,
+        }
+    }
+}
+
+impl MarkdownSummary {
+    pub fn new() -> MarkdownSummary {
+        MarkdownSummary {
+            ..Default::default()
+        }
+    }
+
+    pub fn title(self, title: &str) -> MarkdownSummary {
+        MarkdownSummary {
+            title: title.to_string(),
+            ..self
+        }
+    }
+
+    /// the number of lines of captured output to include for each failing test, the last lines
+    /// are kept as they are usually the most relevant
+    pub fn max_output_lines(self, max_output_lines: usize) -> MarkdownSummary {
+        MarkdownSummary {
+            max_output_lines,
+            ..self
+        }
+    }
+
+    pub fn write<W: Write>(&self, suites: &[TestSuite<'_>], writer: &mut W) -> anyhow::Result<()> {
+        writeln!(writer, "## {}", self.title)?;
+        writeln!(writer)?;
+        writeln!(
+            writer,
+            "| | Suite | Tests | Passed | Failures | Errors | Skipped | Flakes | Time |"
+        )?;
+        writeln!(writer, "|---|---|--:|--:|--:|--:|--:|--:|--:|")?;
+        let mut totals = (0, 0, 0, 0, 0, Duration::zero());
+        for suite in suites {
+            let tests = suite.test_count();
+            let failures = suite.failure_count();
+            let errors = suite.error_count();
+            let skipped = suite.skipped_count();
+            let flakes = suite.flaky_count();
+            totals.0 += tests;
+            totals.1 += failures;
+            totals.2 += errors;
+            totals.3 += skipped;
+            totals.4 += flakes;
+            totals.5 = totals.5 + suite.time();
+            writeln!(
+                writer,
+                "| {} | {} | {} | {} | {} | {} | {} | {} | {}s |",
+                if failures + errors > 0 { "❌" } else { "✅" },
+                escape_cell(suite.name()),
+                tests,
+                tests - failures - errors - skipped,
+                failures,
+                errors,
+                skipped,
+                flakes,
+                seconds(suite.time())
+            )?;
+        }
+        writeln!(
+            writer,
+            "| {} | **Total** | **{}** | **{}** | **{}** | **{}** | **{}** | **{}** | **{}s** |",
+            if totals.1 + totals.2 > 0 {
+                "❌"
+            } else {
+                "✅"
+            },
+            totals.0,
+            totals.0 - totals.1 - totals.2 - totals.3,
+            totals.1,
+            totals.2,
+            totals.3,
+            totals.4,
+            seconds(totals.5)
+        )?;
+
+        let failed: Vec<&TestCase<'_>> = suites
+            .iter()
+            .flat_map(|s| s.cases())
+            .filter(|c| {
+                matches!(
+                    c.result(),
+                    TestResult::Failure { .. } | TestResult::Error { .. }
+                )
+            })
+            .collect();
+        if !failed.is_empty() {
+            writeln!(writer)?;
+            writeln!(writer, "### Failures")?;
+            for case in failed {
+                self.write_failure(case, writer)?;
+            }
+        }
+
+        let skipped: Vec<&TestCase<'_>> = suites
+            .iter()
+            .flat_map(|s| s.cases())
+            .filter(|c| matches!(c.result(), TestResult::Skipped { .. }))
+            .collect();
+        if !skipped.is_empty() {
+            writeln!(writer)?;
+            writeln!(writer, "### Skipped")?;
+            writeln!(writer)?;
+            for case in skipped {
+                match case.result().message().filter(|m| !m.is_empty()) {
+                    Some(message) => writeln!(
+                        writer,
+                        "- `{}` ({}): {}",
+                        case.name().replace('`', "'"),
+                        escape_text(case.class()),
+                        escape_text(message)
+                    )?,
+                    None => writeln!(
+                        writer,
+                        "- `{}` ({})",
+                        case.name().replace('`', "'"),
+                        escape_text(case.class())
+                    )?,
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn write_failure<W: Write>(&self, case: &TestCase<'_>, writer: &mut W) -> anyhow::Result<()> {
+        writeln!(writer)?;
+        writeln!(writer, "<details>")?;
+        writeln!(
+            writer,
+            "<summary>{} <code>{}</code> ({})</summary>",
+            if let TestResult::Error { .. } = case.result() {
+                "💥"
+            } else {
+                "❌"
+            },
+            escape_html(case.name()),
+            escape_html(case.class())
+        )?;
+        writeln!(writer)?;
+        if let Some(message) = case.result().message().filter(|m| !m.is_empty()) {
+            for line in message.lines() {
+                writeln!(writer, "> {}", escape_text(line))?;
+            }
+            writeln!(writer)?;
+        }
+        if let Some(detail) = case.result().detail() {
+            self.write_block(detail, writer)?;
+        }
+        if !case.stdout().is_empty() {
+            writeln!(writer, "Standard output:")?;
+            writeln!(writer)?;
+            self.write_block(case.stdout(), writer)?;
+        }
+        if !case.stderr().is_empty() {
+            writeln!(writer, "Standard error:")?;
+            writeln!(writer)?;
+            self.write_block(case.stderr(), writer)?;
+        }
+        writeln!(writer, "</details>")?;
+        Ok(())
+    }
+
+    /// writes text as a fenced code block, truncated to the maximum number of lines
+    fn write_block<W: Write>(&self, text: &str, writer: &mut W) -> anyhow::Result<()> {
+        let lines: Vec<&str> = text.lines().collect();
+        let skip = lines.len().saturating_sub(self.max_output_lines);
+        // the fence must be longer than any run of backticks in the text
+        let mut longest = 0;
+        let mut run = 0;
+        for c in text.chars() {
+            if c == '`' {
+                run += 1;
+                longest = longest.max(run);
+            } else {
+                run = 0;
+            }
+        }
+        let fence = "`".repeat((longest + 1).max(3));
+        writeln!(writer, "{}", fence)?;
+        if skip > 0 {
+            writeln!(writer, "... {} lines truncated ...", skip)?;
+        }
+        for line in &lines[skip..] {
+            writeln!(writer, "{}", line)?;
+        }
+        writeln!(writer, "{}", fence)?;
+        writeln!(writer)?;
+        Ok(())
+    }
+}
+
+fn seconds(time: Duration) -> f64 {
+    (time.num_milliseconds() as f64) / 1000.0
+}
+
+/// escapes text for a table cell
+fn escape_cell(text: &str) -> String {
+    escape_text(text).replace('|', "\\|")
+}
+
+/// escapes the characters that GitHub would otherwise treat as markup
+fn escape_text(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '>' | '#' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '\n' => escaped.push(' '),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::reports::{MarkdownSummary, TestCase, TestRerun, TestResult, TestSuite};
+    use chrono::Duration;
+    use std::borrow::Cow;
+
+    #[test]
+    fn summary() {
+        let suites = vec![
+            TestSuite::new("first")
+                .push(TestCase::new(
+                    "passes",
+                    "foo",
+                    &TestResult::success(),
+                    Duration::milliseconds(1500),
+                ))
+                .push(
+                    TestCase::new("flakes", "foo", &TestResult::success(), Duration::zero())
+                        .rerun(TestRerun::new(&TestResult::failure("timeout"))),
+                )
+                .push(TestCase::new_with_output(
+                    "fails",
+                    "foo",
+                    &TestResult::failure("expected <1>").with_detail("at foo.rs:42"),
+                    Cow::Borrowed("line 1\nline 2\nline 3\n```"),
+                    Cow::Borrowed(""),
+                    Duration::milliseconds(250),
+                )),
+            TestSuite::new("second|other").push(TestCase::new(
+                "skips",
+                "bar",
+                &TestResult::skipped("not_today"),
+                Duration::zero(),
+            )),
+        ];
+        let mut out = Vec::new();
+        MarkdownSummary::new()
+            .max_output_lines(2)
+            .write(&suites, &mut out)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap().trim(),
+            include_str!("../../test/report/summary.md").trim()
+        );
+    }
+}
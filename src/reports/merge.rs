@@ -0,0 +1,217 @@
+/*
+ * Copyright (c) 2020 Stephen Connolly and CloudBees, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use crate::reports::{TestCase, TestRerun, TestResult, TestSuite};
+use chrono::{DateTime, Utc};
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+/// Merges the reports of an original run and its retries into a single result per test, using
+/// surefire's re-run conventions to record the failed attempts.
+#[derive(Debug, Clone, Default)]
+pub struct RerunMerger<'a> {
+    suites: Vec<MergedSuite<'a>>,
+}
+
+#[derive(Debug, Clone)]
+struct MergedSuite<'a> {
+    name: String,
+    timestamp: Option<DateTime<Utc>>,
+    hostname: Option<String>,
+    index: HashMap<(String, String), usize>,
+    attempts: Vec<Vec<TestCase<'a>>>,
+}
+
+impl<'a> RerunMerger<'a> {
+    pub fn new() -> RerunMerger<'a> {
+        RerunMerger {
+            ..Default::default()
+        }
+    }
+
+    /// records the results of one run, runs must be added in the order they were executed
+    pub fn add_run(&mut self, suites: &[TestSuite<'a>]) {
+        for suite in suites {
+            let position = match self.suites.iter().position(|s| s.name == suite.name()) {
+                Some(position) => position,
+                None => {
+                    self.suites.push(MergedSuite {
+                        name: suite.name().to_string(),
+                        timestamp: suite.timestamp(),
+                        hostname: suite.hostname().map(|h| h.to_string()),
+                        index: HashMap::new(),
+                        attempts: Vec::new(),
+                    });
+                    self.suites.len() - 1
+                }
+            };
+            let merged = &mut self.suites[position];
+            for case in suite.cases() {
+                let key = (case.class().to_string(), case.name().to_string());
+                match merged.index.get(&key) {
+                    Some(&i) => merged.attempts[i].push(case.clone()),
+                    None => {
+                        merged.index.insert(key, merged.attempts.len());
+                        merged.attempts.push(vec![case.clone()]);
+                    }
+                }
+            }
+        }
+    }
+
+    /// the merged test suites, in the order they were first seen
+    pub fn suites(&self) -> Vec<TestSuite<'a>> {
+        self.suites
+            .iter()
+            .map(|suite| {
+                let mut merged = TestSuite::new(&suite.name);
+                if let Some(timestamp) = suite.timestamp {
+                    merged = merged.with_timestamp(timestamp);
+                }
+                if let Some(hostname) = &suite.hostname {
+                    merged = merged.with_hostname(hostname);
+                }
+                suite
+                    .attempts
+                    .iter()
+                    .fold(merged, |s, attempts| s.push(merge(attempts)))
+            })
+            .collect()
+    }
+}
+
+/// Merges the attempts of a single test. The first passing attempt wins and any earlier failed
+/// attempts become flaky failures, otherwise the first failed attempt wins and the subsequent
+/// attempts become re-run failures. Skipped attempts are only used if every attempt was skipped.
+fn merge<'a>(attempts: &[TestCase<'a>]) -> TestCase<'a> {
+    let executed: Vec<&TestCase<'a>> = attempts
+        .iter()
+        .filter(|a| !matches!(a.result(), TestResult::Skipped { .. }))
+        .collect();
+    let (chosen, failed) = match executed
+        .iter()
+        .position(|a| a.result() == &TestResult::Success)
+    {
+        Some(pass) => (executed[pass], &executed[..pass]),
+        None => match executed.first() {
+            Some(first) => (*first, &executed[1..]),
+            None => return attempts[attempts.len() - 1].clone(),
+        },
+    };
+    let mut reruns: Vec<TestRerun<'a>> = Vec::new();
+    if chosen.result() == &TestResult::Success {
+        failed.iter().for_each(|a| reruns.extend(as_reruns(a)));
+        reruns.extend(chosen.reruns().iter().cloned());
+    } else {
+        reruns.extend(chosen.reruns().iter().cloned());
+        failed.iter().for_each(|a| reruns.extend(as_reruns(a)));
+    }
+    let mut case = TestCase::new_with_output(
+        chosen.name(),
+        chosen.class(),
+        chosen.result(),
+        Cow::Owned(chosen.stdout().to_string()),
+        Cow::Owned(chosen.stderr().to_string()),
+        chosen.time(),
+    );
+    if let Some(file) = chosen.file() {
+        case = case.with_location(file, chosen.line());
+    }
+    for (name, value) in chosen.properties() {
+        case = case.with_property(name, value);
+    }
+    reruns
+        .into_iter()
+        .fold(case, |case, rerun| case.rerun(rerun))
+}
+
+/// converts a failed attempt, along with any attempts it records, into re-runs
+fn as_reruns<'a>(attempt: &TestCase<'a>) -> Vec<TestRerun<'a>> {
+    let mut reruns = attempt.reruns().to_vec();
+    reruns.push(TestRerun::new_with_output(
+        attempt.result(),
+        Cow::Owned(attempt.stdout().to_string()),
+        Cow::Owned(attempt.stderr().to_string()),
+    ));
+    reruns
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::reports::{RerunMerger, TestCase, TestResult, TestSuite};
+    use chrono::Duration;
+
+    fn run(
+        a: TestResult<'static>,
+        b: TestResult<'static>,
+        c: TestResult<'static>,
+    ) -> Vec<TestSuite<'static>> {
+        vec![TestSuite::new("suite")
+            .push(TestCase::new("a", "foo", &a, Duration::milliseconds(1)))
+            .push(TestCase::new("b", "foo", &b, Duration::milliseconds(1)))
+            .push(TestCase::new("c", "foo", &c, Duration::milliseconds(1)))]
+    }
+
+    #[test]
+    fn merge_retries() {
+        let mut merger = RerunMerger::new();
+        merger.add_run(&run(
+            TestResult::failure("first"),
+            TestResult::failure("first"),
+            TestResult::success(),
+        ));
+        merger.add_run(&run(
+            TestResult::success(),
+            TestResult::error("second"),
+            TestResult::skipped("retry not needed"),
+        ));
+        let suites = merger.suites();
+        assert_eq!(suites.len(), 1);
+        let suite = &suites[0];
+        assert_eq!(suite.test_count(), 3);
+        assert_eq!(suite.failure_count(), 1);
+        assert_eq!(suite.error_count(), 0);
+        assert_eq!(suite.flaky_count(), 1);
+
+        let a = &suite.cases()[0];
+        assert_eq!(a.result(), &TestResult::success());
+        assert!(a.is_flaky());
+        assert_eq!(a.reruns()[0].result(), &TestResult::failure("first"));
+
+        let b = &suite.cases()[1];
+        assert_eq!(b.result(), &TestResult::failure("first"));
+        assert_eq!(b.reruns().len(), 1);
+        assert_eq!(b.reruns()[0].result(), &TestResult::error("second"));
+
+        let c = &suite.cases()[2];
+        assert_eq!(c.result(), &TestResult::success());
+        assert!(c.reruns().is_empty());
+    }
+
+    #[test]
+    fn merge_skipped() {
+        let mut merger = RerunMerger::new();
+        merger.add_run(&[TestSuite::new("suite").push(TestCase::new(
+            "a",
+            "foo",
+            &TestResult::skipped("not today"),
+            Duration::milliseconds(0),
+        ))]);
+        merger.add_run(&[TestSuite::new("other")]);
+        let suites = merger.suites();
+        assert_eq!(suites.len(), 2);
+        assert_eq!(suites[0].skipped_count(), 1);
+        assert_eq!(suites[1].name(), "other");
+    }
+}
@@ -12,15 +12,34 @@
  * limitations under the License.
  */
 
+mod annotate;
 mod case;
+mod flaky;
+mod html;
+mod incremental;
+mod json;
+mod markdown;
+mod merge;
+mod read;
 mod result;
 mod suite;
 mod transform;
+mod validate;
 mod xml_util;
 
+pub use annotate::{write_annotations, AnnotationFormat};
 pub use case::TestCase;
-pub use result::TestResult;
+pub use flaky::{FlakyDetector, FlakyMark, FlakyTest};
+pub use html::HtmlReport;
+pub use incremental::IncrementalReport;
+pub use json::{read_json, write_json, JSON_SCHEMA};
+pub use markdown::MarkdownSummary;
+pub use merge::RerunMerger;
+pub use read::read_junit;
+pub use result::{TestRerun, TestResult};
 pub use suite::TestSuite;
 pub use transform::ReportProcessor;
+pub use validate::{validate_junit, Diagnostic, Severity};
 pub use xml_util::pretty_xml_output;
+pub use xml_util::sanitize_xml;
 pub use xml_util::ToWrite;
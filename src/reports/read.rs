@@ -0,0 +1,383 @@
+/*
+ * Copyright (c) 2020 Stephen Connolly and CloudBees, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use crate::reports::{TestCase, TestRerun, TestResult, TestSuite};
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use std::borrow::Cow;
+use std::io::Read;
+use xml::attribute::OwnedAttribute;
+use xml::reader::XmlEvent;
+use xml::{EventReader, ParserConfig};
+
+/// Reads a JUnit XML Report into the test suites it contains. Both a `<testsuite>` root element
+/// and a `<testsuites>` aggregate root element are supported.
+pub fn read_junit<'a, R: Read>(input: R) -> anyhow::Result<Vec<TestSuite<'a>>> {
+    let source = EventReader::new_with_config(
+        input,
+        ParserConfig::new()
+            .cdata_to_characters(true)
+            .coalesce_characters(true),
+    );
+    let mut suites: Vec<TestSuite<'a>> = Vec::new();
+    let mut suite_stack: Vec<TestSuite<'a>> = Vec::new();
+    let mut elements: Vec<String> = Vec::new();
+    let mut case: Option<PartialCase> = None;
+    let mut rerun: Option<PartialRerun> = None;
+    let mut text: Option<String> = None;
+    for event in source {
+        match event? {
+            XmlEvent::StartElement {
+                name, attributes, ..
+            } => {
+                let parent = elements.last().map(|e| e.as_str()).unwrap_or_default();
+                match (parent, name.local_name.as_str()) {
+                    (_, "testsuite") => {
+                        let mut suite =
+                            TestSuite::new(&attr(&attributes, "name").unwrap_or_default());
+                        if let Some(timestamp) =
+                            attr(&attributes, "timestamp").and_then(|t| parse_timestamp(&t))
+                        {
+                            suite = suite.with_timestamp(timestamp);
+                        }
+                        if let Some(hostname) = attr(&attributes, "hostname") {
+                            suite = suite.with_hostname(&hostname);
+                        }
+                        suite_stack.push(suite)
+                    }
+                    ("testsuite", "testcase") => {
+                        case = Some(PartialCase {
+                            name: attr(&attributes, "name").unwrap_or_default(),
+                            class: attr(&attributes, "classname").unwrap_or_default(),
+                            time: attr(&attributes, "time")
+                                .map(|t| parse_time(&t))
+                                .unwrap_or_else(|| Duration::milliseconds(0)),
+                            result: None,
+                            stdout: String::new(),
+                            stderr: String::new(),
+                            reruns: Vec::new(),
+                            file: attr(&attributes, "file"),
+                            line: attr(&attributes, "line").and_then(|l| l.trim().parse().ok()),
+                            properties: Vec::new(),
+                        })
+                    }
+                    ("testcase", "failure") | ("testcase", "error") | ("testcase", "skipped") => {
+                        if let Some(case) = case.as_mut() {
+                            case.result = Some(to_result(&name.local_name, &attributes));
+                        }
+                        text = Some(String::new());
+                    }
+                    ("properties", "property")
+                        if elements.len() > 1 && elements[elements.len() - 2] == "testcase" =>
+                    {
+                        if let Some(case) = case.as_mut() {
+                            case.properties.push((
+                                attr(&attributes, "name").unwrap_or_default(),
+                                attr(&attributes, "value").unwrap_or_default(),
+                            ));
+                        }
+                    }
+                    ("testcase", "flakyFailure")
+                    | ("testcase", "rerunFailure")
+                    | ("testcase", "flakyError")
+                    | ("testcase", "rerunError") => {
+                        let kind = if name.local_name.ends_with("Failure") {
+                            "failure"
+                        } else {
+                            "error"
+                        };
+                        rerun = Some(PartialRerun {
+                            result: to_result(kind, &attributes),
+                            stdout: String::new(),
+                            stderr: String::new(),
+                        })
+                    }
+                    ("testsuite", "system-out")
+                    | ("testsuite", "system-err")
+                    | ("testcase", "system-out")
+                    | ("testcase", "system-err")
+                    | ("flakyFailure", "system-out")
+                    | ("flakyFailure", "system-err")
+                    | ("rerunFailure", "system-out")
+                    | ("rerunFailure", "system-err")
+                    | ("flakyError", "system-out")
+                    | ("flakyError", "system-err")
+                    | ("rerunError", "system-out")
+                    | ("rerunError", "system-err")
+                    | ("flakyFailure", "stackTrace")
+                    | ("rerunFailure", "stackTrace")
+                    | ("flakyError", "stackTrace")
+                    | ("rerunError", "stackTrace") => text = Some(String::new()),
+                    _ => (),
+                }
+                elements.push(name.local_name);
+            }
+            XmlEvent::Characters(data) | XmlEvent::CData(data) => {
+                if let Some(text) = text.as_mut() {
+                    text.push_str(&data);
+                }
+            }
+            XmlEvent::EndElement { name } => {
+                elements.pop();
+                let parent = elements.last().map(|e| e.as_str()).unwrap_or_default();
+                match (parent, name.local_name.as_str()) {
+                    (_, "testsuite") => {
+                        if let Some(suite) = suite_stack.pop() {
+                            suites.push(suite);
+                        }
+                    }
+                    ("testsuite", "testcase") => {
+                        if let (Some(case), Some(suite)) = (case.take(), suite_stack.pop()) {
+                            let mut test_case = TestCase::new_with_output(
+                                &case.name,
+                                &case.class,
+                                &case.result.unwrap_or(TestResult::Success),
+                                Cow::Owned(case.stdout),
+                                Cow::Owned(case.stderr),
+                                case.time,
+                            );
+                            if let Some(file) = &case.file {
+                                test_case = test_case.with_location(file, case.line);
+                            }
+                            for (name, value) in &case.properties {
+                                test_case = test_case.with_property(name, value);
+                            }
+                            for rerun in case.reruns {
+                                test_case = test_case.rerun(TestRerun::new_with_output(
+                                    &rerun.result,
+                                    Cow::Owned(rerun.stdout),
+                                    Cow::Owned(rerun.stderr),
+                                ));
+                            }
+                            suite_stack.push(suite.push(test_case));
+                        }
+                    }
+                    ("testcase", "flakyFailure")
+                    | ("testcase", "rerunFailure")
+                    | ("testcase", "flakyError")
+                    | ("testcase", "rerunError") => {
+                        if let (Some(case), Some(rerun)) = (case.as_mut(), rerun.take()) {
+                            case.reruns.push(rerun);
+                        }
+                    }
+                    ("testcase", "failure") | ("testcase", "error") => {
+                        if let (Some(case), Some(text)) = (case.as_mut(), text.take()) {
+                            case.result = case.result.take().map(|r| r.with_detail(&text));
+                        }
+                    }
+                    ("testcase", "skipped") => text = None,
+                    (_, "stackTrace") => {
+                        if let (Some(rerun), Some(text)) = (rerun.as_mut(), text.take()) {
+                            rerun.result = rerun.result.clone().with_detail(&text);
+                        }
+                    }
+                    ("testcase", "system-out") => {
+                        if let (Some(case), Some(text)) = (case.as_mut(), text.take()) {
+                            case.stdout.push_str(&text);
+                        }
+                    }
+                    ("testsuite", "system-out") => {
+                        if let (Some(suite), Some(text)) = (suite_stack.pop(), text.take()) {
+                            let stdout = format!("{}{}", suite.stdout(), text);
+                            suite_stack.push(suite.with_stdout(&stdout));
+                        }
+                    }
+                    ("testsuite", "system-err") => {
+                        if let (Some(suite), Some(text)) = (suite_stack.pop(), text.take()) {
+                            let stderr = format!("{}{}", suite.stderr(), text);
+                            suite_stack.push(suite.with_stderr(&stderr));
+                        }
+                    }
+                    ("testcase", "system-err") => {
+                        if let (Some(case), Some(text)) = (case.as_mut(), text.take()) {
+                            case.stderr.push_str(&text);
+                        }
+                    }
+                    (_, "system-out") => {
+                        if let (Some(rerun), Some(text)) = (rerun.as_mut(), text.take()) {
+                            rerun.stdout.push_str(&text);
+                        }
+                    }
+                    (_, "system-err") => {
+                        if let (Some(rerun), Some(text)) = (rerun.as_mut(), text.take()) {
+                            rerun.stderr.push_str(&text);
+                        }
+                    }
+                    _ => (),
+                }
+            }
+            _ => (),
+        }
+    }
+    Ok(suites)
+}
+
+struct PartialCase<'a> {
+    name: String,
+    class: String,
+    time: Duration,
+    result: Option<TestResult<'a>>,
+    stdout: String,
+    stderr: String,
+    reruns: Vec<PartialRerun<'a>>,
+    file: Option<String>,
+    line: Option<u32>,
+    properties: Vec<(String, String)>,
+}
+
+struct PartialRerun<'a> {
+    result: TestResult<'a>,
+    stdout: String,
+    stderr: String,
+}
+
+/// converts a `failure`, `error` or `skipped` element into the corresponding result
+fn to_result<'a>(kind: &str, attributes: &[OwnedAttribute]) -> TestResult<'a> {
+    let message = attr(attributes, "message").unwrap_or_default();
+    let type_ = attr(attributes, "type");
+    match kind {
+        "failure" => TestResult::Failure {
+            type_: Cow::Owned(type_.unwrap_or_else(|| "assertion".to_string())),
+            message: Cow::Owned(message),
+            detail: Default::default(),
+        },
+        "error" => TestResult::Error {
+            type_: Cow::Owned(type_.unwrap_or_else(|| "error".to_string())),
+            message: Cow::Owned(message),
+            detail: Default::default(),
+        },
+        _ => TestResult::Skipped {
+            message: Cow::Owned(message),
+        },
+    }
+}
+
+fn attr(attributes: &[OwnedAttribute], name: &str) -> Option<String> {
+    attributes
+        .iter()
+        .find(|a| a.name.local_name == name)
+        .map(|a| a.value.clone())
+}
+
+/// parses a time in seconds, allowing for the thousands separators that some tools emit
+fn parse_time(value: &str) -> Duration {
+    value
+        .replace(',', "")
+        .trim()
+        .parse::<f64>()
+        .map(|t| Duration::milliseconds((t * 1000.0).round() as i64))
+        .unwrap_or_else(|_| Duration::milliseconds(0))
+}
+
+/// parses an ISO 8601 timestamp, which the JUnit schema expects to be without a timezone
+fn parse_timestamp(value: &str) -> Option<DateTime<Utc>> {
+    let value = value.trim();
+    DateTime::parse_from_rfc3339(value)
+        .map(|t| t.with_timezone(&Utc))
+        .ok()
+        .or_else(|| {
+            NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S%.f")
+                .ok()
+                .map(|t| DateTime::from_utc(t, Utc))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::reports::{read_junit, TestResult};
+    use chrono::Duration;
+    use std::io::Cursor;
+
+    #[test]
+    fn read_empty() {
+        let xml = include_str!("../../test/report/empty.xml");
+        let suites = read_junit(Cursor::new(xml.as_bytes())).unwrap();
+        assert_eq!(suites.len(), 1);
+        assert_eq!(suites[0].name(), "empty");
+        assert_eq!(suites[0].test_count(), 0);
+    }
+
+    #[test]
+    fn read_output() {
+        let xml = include_str!("../../test/report/output.xml");
+        let suites = read_junit(Cursor::new(xml.as_bytes())).unwrap();
+        assert_eq!(suites.len(), 1);
+        let case = &suites[0].cases()[0];
+        assert_eq!(case.name(), "a test with output");
+        assert_eq!(case.class(), "a class");
+        assert_eq!(case.time(), Duration::milliseconds(46));
+        assert_eq!(case.result(), &TestResult::success());
+        assert_eq!(case.stdout(), "some text");
+        assert_eq!(case.stderr(), "more text");
+        assert_eq!(suites[0].stdout(), "");
+    }
+
+    #[test]
+    fn read_suite_output() {
+        let xml = include_str!("../../test/report/suite-output.xml");
+        let suites = read_junit(Cursor::new(xml.as_bytes())).unwrap();
+        assert_eq!(suites[0].cases()[0].stdout(), "test text");
+        assert_eq!(suites[0].stdout(), "suite text");
+        assert_eq!(suites[0].stderr(), "suite errors");
+    }
+
+    #[test]
+    fn read_results() {
+        let xml = include_str!("../../test/report/results.xml");
+        let suites = read_junit(Cursor::new(xml.as_bytes())).unwrap();
+        assert_eq!(suites.len(), 2);
+        assert_eq!(suites[0].name(), "first");
+        assert_eq!(suites[0].test_count(), 2);
+        assert_eq!(suites[0].failure_count(), 1);
+        assert_eq!(suites[0].skipped_count(), 1);
+        assert_eq!(suites[1].name(), "second");
+        assert_eq!(suites[1].error_count(), 1);
+        assert_eq!(suites[1].time(), Duration::milliseconds(1234500));
+        assert_eq!(
+            suites[0].timestamp().map(|t| t.to_rfc3339()),
+            Some("2020-11-05T10:15:30+00:00".to_string())
+        );
+        assert_eq!(suites[0].hostname(), Some("build-01"));
+        assert_eq!(suites[1].timestamp(), None);
+        let case = &suites[0].cases()[0];
+        assert_eq!(
+            case.result(),
+            &TestResult::failure("expected true").with_detail("stack trace")
+        );
+        assert_eq!(case.file(), Some("src/a_class.rs"));
+        assert_eq!(case.line(), Some(12));
+        assert_eq!(case.properties().len(), 1);
+        assert_eq!(case.properties()[0].0, "owner");
+        assert_eq!(case.properties()[0].1, "team-a");
+        assert_eq!(
+            suites[0].cases()[1].result(),
+            &TestResult::skipped("not today")
+        );
+    }
+
+    #[test]
+    fn read_reruns() {
+        let xml = include_str!("../../test/report/reruns.xml");
+        let suites = read_junit(Cursor::new(xml.as_bytes())).unwrap();
+        let cases = suites[0].cases();
+        assert_eq!(cases[0].result(), &TestResult::success());
+        assert!(cases[0].is_flaky());
+        assert_eq!(cases[0].reruns().len(), 1);
+        assert_eq!(cases[0].reruns()[0].result(), &TestResult::failure("first"));
+        assert_eq!(cases[0].reruns()[0].stdout(), "attempt 1");
+        assert_eq!(cases[0].stdout(), "attempt 2");
+        assert_eq!(cases[1].result(), &TestResult::failure("first"));
+        assert_eq!(cases[1].reruns().len(), 2);
+        assert_eq!(cases[1].reruns()[1].result(), &TestResult::error("third"));
+    }
+}
@@ -21,6 +21,8 @@ pub enum TestResult<'a> {
     Failure {
         type_: Cow<'a, str>,
         message: Cow<'a, str>,
+        /// the body of the failure, such as a stack trace or a diff
+        detail: Cow<'a, str>,
     },
     Skipped {
         message: Cow<'a, str>,
@@ -28,6 +30,8 @@ pub enum TestResult<'a> {
     Error {
         type_: Cow<'a, str>,
         message: Cow<'a, str>,
+        /// the body of the error, such as a stack trace
+        detail: Cow<'a, str>,
     },
 }
 
@@ -42,6 +46,7 @@ impl<'a> TestResult<'a> {
         TestResult::Failure {
             type_: Cow::Borrowed("assertion"),
             message: Cow::Owned(message.to_string()),
+            detail: Default::default(),
         }
     }
 
@@ -50,6 +55,7 @@ impl<'a> TestResult<'a> {
         TestResult::Error {
             type_: Cow::Borrowed("error"),
             message: Cow::Owned(message.to_string()),
+            detail: Default::default(),
         }
     }
 
@@ -61,7 +67,7 @@ impl<'a> TestResult<'a> {
     }
 
     /// extracts the message from the test result
-    pub fn message(&'a self) -> Option<&'a str> {
+    pub fn message(&self) -> Option<&str> {
         match &self {
             TestResult::Success => None,
             TestResult::Failure { message, .. }
@@ -69,6 +75,84 @@ impl<'a> TestResult<'a> {
             | TestResult::Error { message, .. } => Some(message.as_ref()),
         }
     }
+
+    /// attaches a body, such as a stack trace or a diff, to a failed or errored test result
+    pub fn with_detail(self, detail: &'_ str) -> TestResult<'a> {
+        match self {
+            TestResult::Failure { type_, message, .. } => TestResult::Failure {
+                type_,
+                message,
+                detail: Cow::Owned(detail.to_string()),
+            },
+            TestResult::Error { type_, message, .. } => TestResult::Error {
+                type_,
+                message,
+                detail: Cow::Owned(detail.to_string()),
+            },
+            _ => self,
+        }
+    }
+
+    /// extracts the body from a failed or errored test result
+    pub fn detail(&self) -> Option<&str> {
+        match &self {
+            TestResult::Failure { detail, .. } | TestResult::Error { detail, .. }
+                if !detail.is_empty() =>
+            {
+                Some(detail.as_ref())
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Represents a failed attempt of a test that was re-run, as recorded by surefire's
+/// `flakyFailure`, `flakyError`, `rerunFailure` and `rerunError` elements
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestRerun<'a> {
+    /// The result of the attempt, either a failure or an error
+    result: TestResult<'a>,
+    /// STDOUT of the attempt
+    stdout: Cow<'a, str>,
+    /// STDERR of the attempt
+    stderr: Cow<'a, str>,
+}
+
+impl<'a> TestRerun<'a> {
+    pub fn new(result: &'_ TestResult<'a>) -> TestRerun<'a> {
+        TestRerun {
+            result: result.clone(),
+            stdout: Default::default(),
+            stderr: Default::default(),
+        }
+    }
+
+    pub fn new_with_output(
+        result: &'_ TestResult<'a>,
+        stdout: Cow<'a, str>,
+        stderr: Cow<'a, str>,
+    ) -> TestRerun<'a> {
+        TestRerun {
+            result: result.clone(),
+            stdout,
+            stderr,
+        }
+    }
+
+    /// the result of the attempt
+    pub fn result(&self) -> &TestResult<'a> {
+        &self.result
+    }
+
+    /// the attempt stdout
+    pub fn stdout(&self) -> &str {
+        self.stdout.as_ref()
+    }
+
+    /// the attempt stderr
+    pub fn stderr(&self) -> &str {
+        self.stderr.as_ref()
+    }
 }
 
 #[cfg(test)]
@@ -86,4 +170,17 @@ mod tests {
         let r = TestResult::error("just because");
         assert_eq!(r.message(), Some("just because"));
     }
+
+    #[test]
+    fn extract_detail() {
+        let r = TestResult::failure("just because");
+        assert_eq!(r.detail(), None);
+        let r = r.with_detail("at line 1");
+        assert_eq!(r.message(), Some("just because"));
+        assert_eq!(r.detail(), Some("at line 1"));
+        let r = TestResult::error("just because").with_detail("at line 2");
+        assert_eq!(r.detail(), Some("at line 2"));
+        let r = TestResult::skipped("just because").with_detail("ignored");
+        assert_eq!(r.detail(), None);
+    }
 }
@@ -12,8 +12,9 @@
  * limitations under the License.
  */
 
+use crate::reports::xml_util::{sanitize_xml, write_cdata};
 use crate::reports::{TestCase, TestResult};
-use chrono::Duration;
+use chrono::{DateTime, Duration, Utc};
 use std::borrow::Cow;
 use std::io::Write;
 use std::ops::Add;
@@ -25,6 +26,14 @@ use xml::EventWriter;
 pub struct TestSuite<'a> {
     name: Cow<'a, str>,
     cases: Vec<TestCase<'a>>,
+    /// when the test suite started
+    timestamp: Option<DateTime<Utc>>,
+    /// the host that ran the test suite
+    hostname: Option<Cow<'a, str>>,
+    /// output that does not belong to any of the test cases
+    stdout: Cow<'a, str>,
+    /// error output that does not belong to any of the test cases
+    stderr: Cow<'a, str>,
 }
 
 impl<'a> TestSuite<'a> {
@@ -32,6 +41,42 @@ impl<'a> TestSuite<'a> {
         TestSuite {
             name: Cow::Owned(name.to_string()),
             cases: Vec::new(),
+            timestamp: None,
+            hostname: None,
+            stdout: Cow::Borrowed(""),
+            stderr: Cow::Borrowed(""),
+        }
+    }
+
+    /// records when the test suite started
+    pub fn with_timestamp(self, timestamp: DateTime<Utc>) -> TestSuite<'a> {
+        TestSuite {
+            timestamp: Some(timestamp),
+            ..self
+        }
+    }
+
+    /// records the host that ran the test suite
+    pub fn with_hostname(self, hostname: &'_ str) -> TestSuite<'a> {
+        TestSuite {
+            hostname: Some(Cow::Owned(hostname.to_string())),
+            ..self
+        }
+    }
+
+    /// records output that does not belong to any of the test cases
+    pub fn with_stdout(self, stdout: &'_ str) -> TestSuite<'a> {
+        TestSuite {
+            stdout: Cow::Owned(stdout.to_string()),
+            ..self
+        }
+    }
+
+    /// records error output that does not belong to any of the test cases
+    pub fn with_stderr(self, stderr: &'_ str) -> TestSuite<'a> {
+        TestSuite {
+            stderr: Cow::Owned(stderr.to_string()),
+            ..self
         }
     }
 
@@ -46,6 +91,36 @@ impl<'a> TestSuite<'a> {
         }
     }
 
+    /// the name of the test suite
+    pub fn name(&self) -> &str {
+        self.name.as_ref()
+    }
+
+    /// when the test suite started
+    pub fn timestamp(&self) -> Option<DateTime<Utc>> {
+        self.timestamp
+    }
+
+    /// the host that ran the test suite
+    pub fn hostname(&self) -> Option<&str> {
+        self.hostname.as_deref()
+    }
+
+    /// the output that does not belong to any of the test cases
+    pub fn stdout(&self) -> &str {
+        self.stdout.as_ref()
+    }
+
+    /// the error output that does not belong to any of the test cases
+    pub fn stderr(&self) -> &str {
+        self.stderr.as_ref()
+    }
+
+    /// the test cases in the test suite
+    pub fn cases(&self) -> &[TestCase<'a>] {
+        &self.cases
+    }
+
     fn totals(&self) -> (i32, i32, i32, i32, Duration) {
         let mut tests = 0;
         let mut failures = 0;
@@ -89,6 +164,11 @@ impl<'a> TestSuite<'a> {
         self.totals().4
     }
 
+    /// the number of tests that passed after failed attempts
+    pub fn flaky_count(&self) -> i32 {
+        self.cases.iter().filter(|c| c.is_flaky()).count() as i32
+    }
+
     pub fn write<W: Write>(&self, writer: &mut EventWriter<W>) -> anyhow::Result<()> {
         let (tests, failures, skipped, errors, time) = self.totals();
         let tests = format!("{}", tests);
@@ -96,20 +176,41 @@ impl<'a> TestSuite<'a> {
         let skipped = format!("{}", skipped);
         let errors = format!("{}", errors);
         let time = format!("{}", (time.num_milliseconds() as f64) / 1000.0);
-        writer.write(
-            XmlEvent::start_element("testsuite")
+        // the JUnit schema expects an ISO 8601 timestamp without a timezone
+        let timestamp = self
+            .timestamp
+            .map(|t| t.format("%Y-%m-%dT%H:%M:%S").to_string());
+        let name = sanitize_xml(&self.name);
+        let hostname = self.hostname.as_deref().map(sanitize_xml);
+        let mut start = XmlEvent::start_element("testsuite")
                 .attr("xsi:noNamespaceSchemaLocation", "https://maven.apache.org/surefire/maven-surefire-plugin/xsd/surefire-test-report.xsd")
                 .attr("xmlns:xsi", "http://www.w3.org/2001/XMLSchema-instance")
-                .attr("name", &self.name)
+                .attr("name", name.as_ref())
                 .attr("tests", &tests)
                 .attr("failures", &failures)
                 .attr("skipped", &skipped)
                 .attr("errors", &errors)
-                .attr("time", &time)
-        )?;
+                .attr("time", &time);
+        if let Some(timestamp) = &timestamp {
+            start = start.attr("timestamp", timestamp);
+        }
+        if let Some(hostname) = &hostname {
+            start = start.attr("hostname", hostname.as_ref());
+        }
+        writer.write(start)?;
         for case in &self.cases {
             case.write(writer)?
         }
+        if !self.stdout.is_empty() {
+            writer.write(XmlEvent::start_element("system-out"))?;
+            write_cdata(writer, &self.stdout)?;
+            writer.write(XmlEvent::end_element())?;
+        }
+        if !self.stderr.is_empty() {
+            writer.write(XmlEvent::start_element("system-err"))?;
+            write_cdata(writer, &self.stderr)?;
+            writer.write(XmlEvent::end_element())?;
+        }
         writer.write(XmlEvent::end_element())?;
         Ok(())
     }
@@ -129,12 +230,18 @@ impl<'a> TestSuite<'a> {
 
     pub fn as_end_str(&self) -> String {
         let (tests, failures, skipped, errors, time) = self.totals();
+        let flakes = self.flaky_count();
         let mut result = format!(
-            "Tests run: {}, Failures: {}, Errors: {}, Skipped: {}, Time elapsed: {} sec {} - in {}",
+            "Tests run: {}, Failures: {}, Errors: {}, Skipped: {}, {}Time elapsed: {} sec {} - in {}",
             tests,
             failures,
             errors,
             skipped,
+            if flakes > 0 {
+                format!("Flakes: {}, ", flakes)
+            } else {
+                "".to_string()
+            },
             (time.num_milliseconds() as f64) / 1000.0,
             if failures > 0 {
                 "<<< FAILURE".to_string()
@@ -147,7 +254,13 @@ impl<'a> TestSuite<'a> {
         );
         for case in &self.cases {
             match &case.result() {
-                TestResult::Failure { type_, message } => result.push_str(&format!(
+                TestResult::Success if case.is_flaky() => result.push_str(&format!(
+                    "\n{}({}) Time elapsed: {} <<< FLAKE!",
+                    case.name(),
+                    case.class(),
+                    (case.time().num_milliseconds() as f64) / 1000.0
+                )),
+                TestResult::Failure { type_, message, .. } => result.push_str(&format!(
                     "\n{}({}) Time elapsed: {} <<< FAILURE!\n\t{}: {}",
                     case.name(),
                     case.class(),
@@ -155,7 +268,7 @@ impl<'a> TestSuite<'a> {
                     type_,
                     message
                 )),
-                TestResult::Error { type_, message } => result.push_str(&format!(
+                TestResult::Error { type_, message, .. } => result.push_str(&format!(
                     "\n{}({}) Time elapsed: {} <<< ERROR!\n\t{}: {}",
                     case.name(),
                     case.class(),
@@ -172,8 +285,10 @@ impl<'a> TestSuite<'a> {
 
 #[cfg(test)]
 mod tests {
-    use crate::reports::{TestCase, TestResult, TestSuite};
-    use chrono::Duration;
+    use crate::reports::xml_util::round_trip_xml_output;
+    use crate::reports::{TestCase, TestRerun, TestResult, TestSuite};
+    use chrono::{Duration, TimeZone, Utc};
+    use xml::EventWriter;
 
     #[test]
     fn start_str() {
@@ -336,4 +451,51 @@ mod tests {
                 .to_string()
         )
     }
+
+    #[test]
+    fn flaky() {
+        let s = TestSuite::new("foo");
+        let s = s.push(
+            TestCase::new(
+                "a",
+                "foo",
+                &TestResult::success(),
+                Duration::milliseconds(1000),
+            )
+            .rerun(TestRerun::new(&TestResult::failure("because"))),
+        );
+        assert_eq!(s.test_count(), 1);
+        assert_eq!(s.failure_count(), 0);
+        assert_eq!(s.flaky_count(), 1);
+        assert_eq!(s.as_exit_code(), 0);
+        assert_eq!(
+            s.as_end_str(),
+            "Tests run: 1, Failures: 0, Errors: 0, Skipped: 0, Flakes: 1, Time elapsed: 1 sec  - in foo\na(foo) Time elapsed: 1 <<< FLAKE!"
+                .to_string()
+        )
+    }
+
+    #[test]
+    fn write_timestamp_and_hostname() {
+        let mut out = Vec::<u8>::new();
+        let mut sink = EventWriter::new_with_config(&mut out, round_trip_xml_output());
+        TestSuite::new("foo")
+            .with_timestamp(Utc.ymd(2020, 11, 5).and_hms(10, 15, 30))
+            .with_hostname("build-01")
+            .write(&mut sink)
+            .unwrap();
+        assert_eq!(String::from_utf8_lossy(&out).as_ref(), "<?xml version=\"1.0\" encoding=\"utf-8\"?><testsuite xsi:noNamespaceSchemaLocation=\"https://maven.apache.org/surefire/maven-surefire-plugin/xsd/surefire-test-report.xsd\" xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\" name=\"foo\" tests=\"0\" failures=\"0\" skipped=\"0\" errors=\"0\" time=\"0\" timestamp=\"2020-11-05T10:15:30\" hostname=\"build-01\"/>");
+    }
+
+    #[test]
+    fn write_stdout() {
+        let mut out = Vec::<u8>::new();
+        let mut sink = EventWriter::new_with_config(&mut out, round_trip_xml_output());
+        TestSuite::new("foo")
+            .with_stdout("not a test")
+            .with_stderr("not an error")
+            .write(&mut sink)
+            .unwrap();
+        assert_eq!(String::from_utf8_lossy(&out).as_ref(), "<?xml version=\"1.0\" encoding=\"utf-8\"?><testsuite xsi:noNamespaceSchemaLocation=\"https://maven.apache.org/surefire/maven-surefire-plugin/xsd/surefire-test-report.xsd\" xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\" name=\"foo\" tests=\"0\" failures=\"0\" skipped=\"0\" errors=\"0\" time=\"0\"><system-out><![CDATA[not a test]]></system-out><system-err><![CDATA[not an error]]></system-err></testsuite>");
+    }
 }
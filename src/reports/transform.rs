@@ -12,12 +12,14 @@
  * limitations under the License.
  */
 
-use crate::reports::xml_util::{round_trip_xml_input, round_trip_xml_output};
+use crate::reports::xml_util::{
+    illegal_references, round_trip_xml_input, round_trip_xml_output, sanitize_xml,
+};
 use crate::reports::ToWrite;
 use regex::{Captures, Regex};
 use std::borrow::Cow;
 use std::cmp::Ordering;
-use std::io::{Read, Write};
+use std::io::{Cursor, Read, Write};
 use xml::attribute::OwnedAttribute;
 use xml::{EventReader, EventWriter};
 
@@ -34,6 +36,7 @@ pub struct ReportProcessor {
     attachment_windows_paths: bool,
     attachments: Vec<String>,
     secrets: Vec<String>,
+    sanitize: bool,
 }
 
 impl ReportProcessor {
@@ -99,6 +102,12 @@ impl ReportProcessor {
         }
     }
 
+    /// replace any characters that are not allowed in XML 1.0 with a visible escape, as well as any
+    /// invalid UTF-8 sequences, so that reports from misbehaving tools can still be processed
+    pub fn sanitize(self, sanitize: bool) -> ReportProcessor {
+        ReportProcessor { sanitize, ..self }
+    }
+
     pub fn secret(self, secret: &str) -> ReportProcessor {
         ReportProcessor {
             secrets: {
@@ -150,7 +159,32 @@ impl ReportProcessor {
         self.attachments.iter().map(|s| s.as_str()).collect()
     }
 
-    pub fn process<R: Read, W: Write>(&mut self, reader: R, writer: &mut W) -> anyhow::Result<()> {
+    pub fn process<R: Read, W: Write>(
+        &mut self,
+        mut reader: R,
+        writer: &mut W,
+    ) -> anyhow::Result<()> {
+        if self.sanitize {
+            // illegal characters would stop the parser, so they need to be replaced up front
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes)?;
+            let text = String::from_utf8_lossy(&bytes);
+            let mut text = sanitize_xml(&text).into_owned();
+            // as would character references to them
+            for (range, code) in illegal_references(&text).into_iter().rev() {
+                let escape = match code {
+                    Some(code) => format!("\\u{:04X}", code),
+                    None => " ".to_string(),
+                };
+                text.replace_range(range, &escape);
+            }
+            self.transform(Cursor::new(text.into_bytes()), writer)
+        } else {
+            self.transform(reader, writer)
+        }
+    }
+
+    fn transform<R: Read, W: Write>(&mut self, reader: R, writer: &mut W) -> anyhow::Result<()> {
         let mut xpath_stack = Vec::new();
         let mut xpath = String::new();
         let source = EventReader::new_with_config(reader, round_trip_xml_input());
@@ -175,7 +209,7 @@ impl ReportProcessor {
                     xpath.push_str(&name.local_name);
                     if &xpath == "/testsuite/testcase" {
                         let mut new_attrs = Vec::new();
-                        for a in attributes.to_owned() {
+                        for a in attributes.iter().cloned() {
                             if a.name.local_name.as_str() == "name" {
                                 new_attrs.push(OwnedAttribute::new(
                                     a.name.clone(),
@@ -207,7 +241,7 @@ impl ReportProcessor {
                         }
                     } else if &xpath == "/testsuite" {
                         let mut new_attrs = Vec::new();
-                        for a in attributes.to_owned() {
+                        for a in attributes.iter().cloned() {
                             if a.name.local_name.as_str() == "name" {
                                 new_attrs.push(OwnedAttribute::new(
                                     a.name.clone(),
@@ -229,7 +263,7 @@ impl ReportProcessor {
                         }
                     } else if &xpath == "/testsuite/properties/property" {
                         let mut new_attrs = Vec::new();
-                        for a in attributes.to_owned() {
+                        for a in attributes.iter().cloned() {
                             if a.name.local_name.as_str() == "value" {
                                 let mut value = Cow::Borrowed(&a.value);
                                 for secret in &self.secrets {
@@ -611,4 +645,42 @@ mod tests {
                 .trim()
         );
     }
+
+    #[test]
+    fn sanitize_illegal() {
+        let xml = include_bytes!("../../test/report/illegal.xml");
+        let mut out = Vec::new();
+        assert!(ReportProcessor::new()
+            .process(Cursor::new(&xml[..]), &mut out)
+            .is_err());
+        let mut out = Vec::new();
+        ReportProcessor::new()
+            .sanitize(true)
+            .process(Cursor::new(&xml[..]), &mut out)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&out).replace(" />", "/>").trim(),
+            include_str!("../../test/report/illegal-sanitized.xml")
+                .to_string()
+                .replace(" />", "/>")
+                .trim()
+        );
+    }
+
+    #[test]
+    fn sanitize_illegal_references() {
+        let xml = include_bytes!("../../test/report/illegal-references.xml");
+        let mut out = Vec::new();
+        ReportProcessor::new()
+            .sanitize(true)
+            .process(Cursor::new(&xml[..]), &mut out)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&out).replace(" />", "/>").trim(),
+            include_str!("../../test/report/illegal-references-sanitized.xml")
+                .to_string()
+                .replace(" />", "/>")
+                .trim()
+        );
+    }
 }
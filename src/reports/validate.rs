@@ -0,0 +1,449 @@
+/*
+ * Copyright (c) 2020 Stephen Connolly and CloudBees, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use crate::reports::xml_util::{illegal_references, is_xml_char};
+use std::collections::HashSet;
+use std::fmt;
+use std::io::{Cursor, Read};
+use xml::attribute::OwnedAttribute;
+use xml::common::Position;
+use xml::reader::XmlEvent;
+use xml::{EventReader, ParserConfig};
+
+/// How serious a problem is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// the input deviates from what is expected, but consumers will generally cope
+    Warning,
+    /// the input is invalid and consumers will likely reject or misreport it
+    Error,
+}
+
+/// A problem found in an input, with the line it was found on when known
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    severity: Severity,
+    line: Option<usize>,
+    message: String,
+}
+
+impl Diagnostic {
+    pub fn error(line: Option<usize>, message: &str) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Error,
+            line,
+            message: message.to_string(),
+        }
+    }
+
+    pub fn warning(line: Option<usize>, message: &str) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Warning,
+            line,
+            message: message.to_string(),
+        }
+    }
+
+    /// the severity of the problem
+    pub fn severity(&self) -> Severity {
+        self.severity
+    }
+
+    /// the line number, starting from 1, where the problem was found
+    pub fn line(&self) -> Option<usize> {
+        self.line
+    }
+
+    /// a description of the problem
+    pub fn message(&self) -> &str {
+        self.message.as_str()
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        match self.line {
+            Some(line) => write!(f, "{}: line {}: {}", severity, line, self.message),
+            None => write!(f, "{}: {}", severity, self.message),
+        }
+    }
+}
+
+/// Validates a JUnit XML Report with rules derived from the surefire XSD and the expectations of
+/// common consumers such as Jenkins, rather than against the XSD itself.
+pub fn validate_junit<R: Read>(mut input: R) -> anyhow::Result<Vec<Diagnostic>> {
+    let mut bytes = Vec::new();
+    input.read_to_end(&mut bytes)?;
+    let mut diagnostics = Vec::new();
+
+    let text = match String::from_utf8(bytes) {
+        Ok(text) => text,
+        Err(e) => {
+            let valid = &e.as_bytes()[..e.utf8_error().valid_up_to()];
+            let line = valid.iter().filter(|b| **b == b'\n').count() + 1;
+            diagnostics.push(Diagnostic::error(Some(line), "invalid UTF-8 byte sequence"));
+            String::from_utf8_lossy(e.as_bytes()).to_string()
+        }
+    };
+
+    // the parser gives up on illegal characters, so report and replace them to keep validating
+    let mut sanitized = String::with_capacity(text.len());
+    for (index, line) in text.split('\n').enumerate() {
+        if index > 0 {
+            sanitized.push('\n');
+        }
+        for c in line.chars() {
+            if is_xml_char(c) {
+                sanitized.push(c);
+            } else {
+                diagnostics.push(Diagnostic::error(
+                    Some(index + 1),
+                    &format!("illegal XML 1.0 character U+{:04X}", c as u32),
+                ));
+                sanitized.push(' ');
+            }
+        }
+    }
+    let illegal = illegal_references(&sanitized);
+    for (range, _) in &illegal {
+        let line = sanitized[..range.start].matches('\n').count() + 1;
+        diagnostics.push(Diagnostic::error(
+            Some(line),
+            &format!(
+                "character reference {} is not a legal XML 1.0 character",
+                &sanitized[range.clone()]
+            ),
+        ));
+    }
+    // the parser gives up on them too
+    for (range, _) in illegal.into_iter().rev() {
+        sanitized.replace_range(range, " ");
+    }
+
+    let mut source = EventReader::new_with_config(
+        Cursor::new(sanitized.as_bytes()),
+        ParserConfig::new()
+            .cdata_to_characters(true)
+            .coalesce_characters(true),
+    );
+    let mut elements: Vec<String> = Vec::new();
+    let mut suites: Vec<SuiteTotals> = Vec::new();
+    let mut results: usize = 0;
+    loop {
+        let event = match source.next() {
+            Ok(event) => event,
+            Err(e) => {
+                diagnostics.push(Diagnostic::error(
+                    Some(e.position().row as usize + 1),
+                    &format!("not well-formed XML: {}", e.msg()),
+                ));
+                break;
+            }
+        };
+        let line = Some(source.position().row as usize + 1);
+        match event {
+            XmlEvent::StartElement {
+                name, attributes, ..
+            } => {
+                let parent = elements.last().map(|e| e.as_str());
+                match (parent, name.local_name.as_str()) {
+                    (None, "testsuite") | (None, "testsuites") => (),
+                    (None, other) => {
+                        diagnostics.push(Diagnostic::error(
+                            line,
+                            &format!(
+                                "root element is <{}>, expected <testsuite> or <testsuites>",
+                                other
+                            ),
+                        ));
+                        break;
+                    }
+                    (Some("testsuites"), "testsuite") | (Some("testsuite"), "testsuite") => (),
+                    (Some("testsuite"), "testcase") => {
+                        if let Some(suite) = suites.last_mut() {
+                            suite.tests += 1;
+                            let key = (
+                                attr(&attributes, "classname").unwrap_or_default(),
+                                attr(&attributes, "name").unwrap_or_default(),
+                            );
+                            if !suite.names.insert(key.clone()) {
+                                diagnostics.push(Diagnostic::warning(
+                                    line,
+                                    &format!(
+                                        "duplicate test case {}({}) in test suite {}",
+                                        key.1, key.0, suite.name
+                                    ),
+                                ));
+                            }
+                        }
+                        results = 0;
+                    }
+                    (Some("testsuite"), "properties")
+                    | (Some("testsuite"), "system-out")
+                    | (Some("testsuite"), "system-err")
+                    | (Some("properties"), "property")
+                    | (Some("testcase"), "properties")
+                    | (Some("testcase"), "system-out")
+                    | (Some("testcase"), "system-err")
+                    | (Some("testcase"), "flakyFailure")
+                    | (Some("testcase"), "flakyError")
+                    | (Some("testcase"), "rerunFailure")
+                    | (Some("testcase"), "rerunError") => (),
+                    (Some("testcase"), kind @ "failure")
+                    | (Some("testcase"), kind @ "error")
+                    | (Some("testcase"), kind @ "skipped") => {
+                        results += 1;
+                        if results > 1 {
+                            diagnostics.push(Diagnostic::warning(
+                                line,
+                                &format!("test case has more than one result, found <{}>", kind),
+                            ));
+                        }
+                        if let Some(suite) = suites.last_mut() {
+                            match kind {
+                                "failure" => suite.failures += 1,
+                                "error" => suite.errors += 1,
+                                _ => suite.skipped += 1,
+                            }
+                        }
+                    }
+                    (Some(p), "stackTrace") | (Some(p), "system-out") | (Some(p), "system-err")
+                        if p.starts_with("flaky") || p.starts_with("rerun") => {}
+                    (Some(parent), other) => diagnostics.push(Diagnostic::warning(
+                        line,
+                        &format!("unexpected element <{}> in <{}>", other, parent),
+                    )),
+                }
+                check_attributes(&name.local_name, &attributes, line, &mut diagnostics);
+                if name.local_name == "testsuite" {
+                    suites.push(SuiteTotals {
+                        name: attr(&attributes, "name").unwrap_or_default(),
+                        expected: [
+                            count(&attributes, "tests"),
+                            count(&attributes, "failures"),
+                            count(&attributes, "errors"),
+                            count(&attributes, "skipped"),
+                        ],
+                        ..Default::default()
+                    });
+                }
+                elements.push(name.local_name);
+            }
+            XmlEvent::EndElement { .. } => {
+                if elements.pop().as_deref() != Some("testsuite") {
+                    continue;
+                }
+                if let Some(suite) = suites.pop() {
+                    let actual = [suite.tests, suite.failures, suite.errors, suite.skipped];
+                    for (i, total) in ["tests", "failures", "errors", "skipped"]
+                        .iter()
+                        .enumerate()
+                    {
+                        if let Some(expected) = suite.expected[i] {
+                            if expected != actual[i] {
+                                diagnostics.push(Diagnostic::warning(
+                                    line,
+                                    &format!(
+                                        "test suite {} declares {}=\"{}\" but contains {}",
+                                        suite.name, total, expected, actual[i]
+                                    ),
+                                ));
+                            }
+                        }
+                    }
+                }
+            }
+            XmlEvent::EndDocument => break,
+            _ => (),
+        }
+    }
+    diagnostics.sort_by_key(|d| d.line);
+    Ok(diagnostics)
+}
+
+#[derive(Default)]
+struct SuiteTotals {
+    name: String,
+    expected: [Option<usize>; 4],
+    tests: usize,
+    failures: usize,
+    errors: usize,
+    skipped: usize,
+    names: HashSet<(String, String)>,
+}
+
+/// checks the attributes required by the surefire XSD and their values
+fn check_attributes(
+    element: &str,
+    attributes: &[OwnedAttribute],
+    line: Option<usize>,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    let (required, optional, counts): (&[&str], &[&str], &[&str]) = match element {
+        "testsuite" => (
+            &["name", "tests", "failures", "errors"],
+            &[],
+            &["tests", "failures", "errors", "skipped"],
+        ),
+        "testcase" => (&["name"], &["classname"], &[]),
+        "failure" | "error" => (&[], &["type"], &[]),
+        "property" => (&["name", "value"], &[], &[]),
+        _ => (&[], &[], &[]),
+    };
+    for name in required {
+        if attr(attributes, name).is_none() {
+            diagnostics.push(Diagnostic::error(
+                line,
+                &format!("<{}> is missing the required {} attribute", element, name),
+            ));
+        }
+    }
+    for name in optional {
+        if attr(attributes, name).is_none() {
+            diagnostics.push(Diagnostic::warning(
+                line,
+                &format!("<{}> is missing the {} attribute", element, name),
+            ));
+        }
+    }
+    for name in counts {
+        if let Some(value) = attr(attributes, name) {
+            if value.trim().parse::<usize>().is_err() {
+                diagnostics.push(Diagnostic::error(
+                    line,
+                    &format!(
+                        "<{}> has an invalid {} attribute \"{}\", expected a non-negative integer",
+                        element, name, value
+                    ),
+                ));
+            }
+        }
+    }
+    if let Some(time) = attr(attributes, "time") {
+        match time.trim().parse::<f64>() {
+            Ok(t) if t.is_finite() && t >= 0.0 => (),
+            Ok(_) => diagnostics.push(Diagnostic::error(
+                line,
+                &format!("<{}> has an invalid time \"{}\"", element, time),
+            )),
+            Err(_) => match time.replace(',', "").trim().parse::<f64>() {
+                Ok(t) if t.is_finite() && t >= 0.0 => diagnostics.push(Diagnostic::warning(
+                    line,
+                    &format!(
+                        "<{}> time \"{}\" uses thousands separators, which not all consumers accept",
+                        element, time
+                    ),
+                )),
+                _ => diagnostics.push(Diagnostic::error(
+                    line,
+                    &format!("<{}> has an invalid time \"{}\"", element, time),
+                )),
+            },
+        }
+    }
+}
+
+fn attr(attributes: &[OwnedAttribute], name: &str) -> Option<String> {
+    attributes
+        .iter()
+        .find(|a| a.name.local_name == name)
+        .map(|a| a.value.clone())
+}
+
+fn count(attributes: &[OwnedAttribute], name: &str) -> Option<usize> {
+    attr(attributes, name).and_then(|v| v.trim().parse::<usize>().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::reports::xml_util::round_trip_xml_output;
+    use crate::reports::{validate_junit, Diagnostic, TestCase, TestResult, TestSuite};
+    use chrono::Duration;
+    use std::io::Cursor;
+    use xml::EventWriter;
+
+    #[test]
+    fn valid() {
+        let xml = include_str!("../../test/report/reruns.xml");
+        let diagnostics = validate_junit(Cursor::new(xml.as_bytes())).unwrap();
+        assert_eq!(diagnostics, vec![]);
+    }
+
+    #[test]
+    fn written_report_is_valid() {
+        let mut out = Vec::<u8>::new();
+        TestSuite::new("foo")
+            .push(TestCase::new(
+                "a",
+                "foo",
+                &TestResult::failure("because"),
+                Duration::milliseconds(1000),
+            ))
+            .push(TestCase::new(
+                "b",
+                "foo",
+                &TestResult::skipped("because"),
+                Duration::milliseconds(500),
+            ))
+            .write(&mut EventWriter::new_with_config(
+                &mut out,
+                round_trip_xml_output(),
+            ))
+            .unwrap();
+        let diagnostics = validate_junit(Cursor::new(out)).unwrap();
+        assert_eq!(diagnostics, vec![]);
+    }
+
+    #[test]
+    fn not_well_formed() {
+        let xml = "<?xml version=\"1.0\"?>\n<testsuite name=\"a\" tests=\"0\" failures=\"0\" errors=\"0\">\n</testcase>";
+        let diagnostics = validate_junit(Cursor::new(xml.as_bytes())).unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0].is_error());
+        assert_eq!(diagnostics[0].line(), Some(3));
+    }
+
+    #[test]
+    fn invalid() {
+        let xml = include_str!("../../test/report/invalid.xml");
+        let diagnostics = validate_junit(Cursor::new(xml.as_bytes())).unwrap();
+        let actual: Vec<String> = diagnostics.iter().map(Diagnostic::to_string).collect();
+        assert_eq!(
+            actual,
+            vec![
+                "error: line 2: <testsuite> is missing the required errors attribute",
+                "error: line 2: <testsuite> has an invalid failures attribute \"-1\", expected a non-negative integer",
+                "warning: line 2: <testsuite> time \"1,234.5\" uses thousands separators, which not all consumers accept",
+                "error: line 3: <testcase> has an invalid time \"fast\"",
+                "error: line 4: character reference &#x1b; is not a legal XML 1.0 character",
+                "warning: line 4: <failure> is missing the type attribute",
+                "error: line 5: illegal XML 1.0 character U+0001",
+                "warning: line 7: duplicate test case a test(a class) in test suite invalid",
+                "warning: line 8: test case has more than one result, found <skipped>",
+                "warning: line 10: <testcase> is missing the classname attribute",
+                "warning: line 11: unexpected element <unknown> in <testcase>",
+                "warning: line 13: test suite invalid declares tests=\"2\" but contains 3",
+                "warning: line 13: test suite invalid declares skipped=\"0\" but contains 1",
+            ]
+        );
+    }
+}
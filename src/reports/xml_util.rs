@@ -12,10 +12,13 @@
  * limitations under the License.
  */
 
+use regex::Regex;
 use std::borrow::Cow;
+use std::io::Write;
+use std::ops::Range;
 use xml::attribute::Attribute;
 use xml::writer::XmlEvent;
-use xml::{EmitterConfig, ParserConfig};
+use xml::{EmitterConfig, EventWriter, ParserConfig};
 
 /// XML writer configuration to give pretty output
 pub fn pretty_xml_output() -> EmitterConfig {
@@ -64,12 +67,81 @@ pub(crate) fn round_trip_xml_input() -> ParserConfig {
     }
 }
 
+/// is the character allowed in an XML 1.0 document
+pub(crate) fn is_xml_char(c: char) -> bool {
+    matches!(c, '\t' | '\n' | '\r' | '\u{20}'..='\u{D7FF}' | '\u{E000}'..='\u{FFFD}' | '\u{10000}'..='\u{10FFFF}')
+}
+
+/// Replaces any characters that are not allowed in an XML 1.0 document with a visible `\uXXXX`
+/// escape so that the content of the output is not lost.
+pub fn sanitize_xml(text: &str) -> Cow<'_, str> {
+    if text.chars().all(is_xml_char) {
+        return Cow::Borrowed(text);
+    }
+    let mut sanitized = String::with_capacity(text.len() + 8);
+    for c in text.chars() {
+        if is_xml_char(c) {
+            sanitized.push(c);
+        } else {
+            sanitized.push_str(&format!("\\u{:04X}", c as u32));
+        }
+    }
+    Cow::Owned(sanitized)
+}
+
+/// Finds the `&#...;` character references that refer to characters which are not allowed in an
+/// XML 1.0 document, returning the range of each reference and the code point it refers to.
+pub(crate) fn illegal_references(text: &str) -> Vec<(Range<usize>, Option<u32>)> {
+    let reference = Regex::new(r"&#(x[0-9a-fA-F]+|[0-9]+);").unwrap();
+    // references are only parsed in text and attribute values, they are literal in CDATA
+    // sections, comments and processing instructions
+    let literal = Regex::new(r"(?s)<!\[CDATA\[.*?\]\]>|<!--.*?-->|<\?.*?\?>").unwrap();
+    let literals: Vec<Range<usize>> = literal.find_iter(text).map(|m| m.range()).collect();
+    reference
+        .captures_iter(text)
+        .filter_map(|cap| {
+            let found = cap.get(0).unwrap();
+            if literals
+                .iter()
+                .any(|literal| literal.start <= found.start() && found.end() <= literal.end)
+            {
+                return None;
+            }
+            let value = cap.get(1).unwrap().as_str();
+            let code = match value.strip_prefix('x') {
+                Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                None => value.parse::<u32>().ok(),
+            };
+            if code.and_then(std::char::from_u32).is_some_and(is_xml_char) {
+                None
+            } else {
+                Some((found.range(), code))
+            }
+        })
+        .collect()
+}
+
+/// Writes text as CDATA, sanitizing illegal characters and splitting any `]]>` across adjacent
+/// CDATA sections so that it cannot terminate the section early.
+pub(crate) fn write_cdata<W: Write>(
+    writer: &mut EventWriter<W>,
+    text: &str,
+) -> xml::writer::Result<()> {
+    let text = sanitize_xml(text);
+    let mut remaining = text.as_ref();
+    while let Some(index) = remaining.find("]]>") {
+        writer.write(XmlEvent::cdata(&remaining[..index + 2]))?;
+        remaining = &remaining[index + 2..];
+    }
+    writer.write(XmlEvent::cdata(remaining))
+}
+
 pub trait ToWrite {
     fn to_write<'a>(&'a self) -> Vec<XmlEvent<'a>>;
 }
 
 impl ToWrite for xml::reader::XmlEvent {
-    fn to_write(&self) -> Vec<XmlEvent> {
+    fn to_write(&self) -> Vec<XmlEvent<'_>> {
         match self {
             xml::reader::XmlEvent::StartDocument {
                 version,
@@ -118,12 +190,44 @@ impl ToWrite for xml::reader::XmlEvent {
 
 #[cfg(test)]
 mod tests {
-    use crate::reports::xml_util::{round_trip_xml_input, round_trip_xml_output};
+    use crate::reports::xml_util::{
+        round_trip_xml_input, round_trip_xml_output, sanitize_xml, write_cdata,
+    };
     use crate::reports::ToWrite;
     use std::io::Cursor;
     use xml::reader::XmlEvent;
     use xml::{EventReader, EventWriter};
 
+    #[test]
+    fn sanitize() {
+        assert_eq!(sanitize_xml("tab\tand\r\nnewline"), "tab\tand\r\nnewline");
+        assert_eq!(
+            sanitize_xml("\u{1b}[31mred\u{0}\u{FFFE}"),
+            "\\u001B[31mred\\u0000\\uFFFE"
+        );
+    }
+
+    #[test]
+    fn split_cdata() {
+        let mut out = Vec::new();
+        let mut sink = EventWriter::new_with_config(&mut out, round_trip_xml_output());
+        write_cdata(&mut sink, "a]]>b]]>").unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&out).as_ref(),
+            "<![CDATA[a]]]]><![CDATA[>b]]]]><![CDATA[>]]>"
+        );
+        let text: String = EventReader::new(Cursor::new(
+            format!("<a>{}</a>", String::from_utf8_lossy(&out)).as_bytes(),
+        ))
+        .into_iter()
+        .filter_map(|e| match e.unwrap() {
+            XmlEvent::CData(t) | XmlEvent::Characters(t) => Some(t),
+            _ => None,
+        })
+        .collect();
+        assert_eq!(text, "a]]>b]]>");
+    }
+
     #[test]
     fn round_trip() {
         let xml = include_str!("../../test/xml/sample.xml");
@@ -183,10 +183,7 @@ impl<'a, R: Read, W: Write> EmbeddedStreams<'a, R, W> {
 impl<R> EmbeddedReader<R> {
     fn with_capacity(capacity: usize, inner: R) -> EmbeddedReader<R> {
         // the needle always starts with a newline which we will emit
-        let mut buffer = Vec::<u8>::with_capacity(capacity);
-        unsafe {
-            buffer.set_len(capacity);
-        }
+        let buffer = vec![0u8; capacity];
         EmbeddedReader {
             inner,
             buffer,
@@ -316,10 +313,10 @@ mod tests {
 
     #[test]
     fn given_empty_input_then_returns_empty() {
-        let input = concat!("").as_bytes();
+        let input = "".as_bytes();
         let mut state = EmbeddedReader::with_capacity(40, Cursor::new(input));
         let mut instance = EmbeddedStream::new(b"foo.txt", b"cafebabe", &mut state).unwrap();
-        let mut buf = vec![0; 300 as usize];
+        let mut buf = vec![0; 300];
         let mut total = 0;
         loop {
             let count = instance.read(&mut buf[total..]).unwrap();
@@ -337,7 +334,7 @@ mod tests {
             concat!("\n", "[[juxr::stream::cafebabe::foo.txt]]\n", "More text\n").as_bytes();
         let mut state = EmbeddedReader::with_capacity(40, Cursor::new(input));
         let mut instance = EmbeddedStream::new(b"foo.txt", b"cafebabe", &mut state).unwrap();
-        let mut buf = vec![0; 300 as usize];
+        let mut buf = vec![0; 300];
         let mut total = 0;
         loop {
             let count = instance.read(&mut buf[total..]).unwrap();
@@ -354,7 +351,7 @@ mod tests {
         let input = concat!("Some text\n", "More text\n").as_bytes();
         let mut state = EmbeddedReader::with_capacity(40, Cursor::new(input));
         let mut instance = EmbeddedStream::new(b"foo.txt", b"cafebabe", &mut state).unwrap();
-        let mut buf = vec![0; 300 as usize];
+        let mut buf = vec![0; 300];
         let mut total = 0;
         loop {
             let count = instance.read(&mut buf[total..]).unwrap();
@@ -381,7 +378,7 @@ mod tests {
         .as_bytes();
         let mut state = EmbeddedReader::with_capacity(40, Cursor::new(input));
         let mut instance = EmbeddedStream::new(b"foo.txt", b"cafebabe", &mut state).unwrap();
-        let mut buf = vec![0; 300 as usize];
+        let mut buf = vec![0; 300];
         let mut total = 0;
         loop {
             let count = instance.read(&mut buf[total..]).unwrap();
@@ -423,7 +420,7 @@ mod tests {
         let mut out = Vec::new();
 
         EmbeddedStreams::new(Cursor::new(input), &mut out).for_each(|stream| {
-            let mut buf = vec![0; 300 as usize];
+            let mut buf = vec![0; 300];
             let mut total = 0;
             loop {
                 let count = stream.read(&mut buf[total..]).unwrap();
@@ -12,6 +12,7 @@
  * limitations under the License.
  */
 
+use std::fmt;
 use std::str::FromStr;
 
 use uuid::Uuid;
@@ -105,7 +106,7 @@ impl Needle {
     }
 
     pub fn find(buf: &[u8]) -> Option<(usize, usize)> {
-        if let Some(start) = Self::find_start(&buf) {
+        if let Some(start) = Self::find_start(buf) {
             if let Some(mid) = buf[start + NEEDLE_START.len()..]
                 .windows(NEEDLE_METADATA.len())
                 .position(|s| s == NEEDLE_METADATA)
@@ -127,10 +128,11 @@ impl Needle {
     }
 }
 
-impl ToString for Needle {
-    fn to_string(&self) -> String {
+impl fmt::Display for Needle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match &self.metadata {
-            None => format!(
+            None => write!(
+                f,
                 "{}{}{}{}{}",
                 String::from_utf8_lossy(NEEDLE_START),
                 self.id,
@@ -138,7 +140,8 @@ impl ToString for Needle {
                 self.filename,
                 String::from_utf8_lossy(NEEDLE_END)
             ),
-            Some(kind) => format!(
+            Some(kind) => write!(
+                f,
                 "{}{}{}{}{}{}{}",
                 String::from_utf8_lossy(NEEDLE_START),
                 self.id,
@@ -226,7 +229,7 @@ mod tests {
     #[test]
     fn find_valid_within() {
         let n = Needle::new("/foo/bar.txt");
-        let n = format!("prefix{}suffix", n.to_string()).into_bytes();
+        let n = format!("prefix{}suffix", n).into_bytes();
         assert_eq!(Needle::find(&n), Some((6, n.len() - 6)))
     }
 
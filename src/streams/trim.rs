@@ -30,10 +30,7 @@ impl<R: Read> TrimFilterReader<R> {
     }
 
     pub fn with_capacity(inner: R, capacity: usize) -> TrimFilterReader<R> {
-        let mut buf = Vec::<u8>::with_capacity(capacity);
-        unsafe {
-            buf.set_len(capacity);
-        }
+        let buf = vec![0u8; capacity];
         TrimFilterReader {
             inner,
             buffer: buf,
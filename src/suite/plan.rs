@@ -12,12 +12,15 @@
  * limitations under the License.
  */
 
+use crate::reports::TestCase as ReportCase;
 use crate::suite::{PlanCommand, PlanTest};
 use serde::Deserialize;
 use serde::Serialize;
 use std::collections::BTreeMap;
 use std::io;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
 
 #[derive(Clone, Debug, PartialEq, Default)]
 pub struct Plan {
@@ -33,15 +36,22 @@ impl Plan {
     where
         R: io::Read,
     {
-        serde_yaml::from_reader(rdr).map(Self::from_map)
+        serde_yaml::from_reader(rdr).and_then(Self::from_map)
     }
 
-    fn from_map(p: BTreeMap<String, TestCase>) -> Self {
+    fn from_map(p: BTreeMap<String, TestCase>) -> serde_yaml::Result<Self> {
         let mut tests = BTreeMap::new();
         for (k, v) in p {
-            tests.insert(k, v.into());
+            let test: PlanTest = v.into();
+            if test.timeout == Some(0) {
+                return Err(serde::de::Error::custom(format!(
+                    "the timeout of {} must be at least 1 second",
+                    k
+                )));
+            }
+            tests.insert(k, test);
         }
-        Self { tests }
+        Ok(Self { tests })
     }
 
     pub fn to_string(&self) -> serde_yaml::Result<String> {
@@ -59,13 +69,58 @@ impl Plan {
     pub fn insert(&mut self, name: &str, test: PlanTest) -> Option<PlanTest> {
         self.tests.insert(name.to_string(), test)
     }
+
+    /// runs the tests with up to `jobs` of them at a time, the tests that are not `parallel` run on
+    /// their own once the others have finished. With a single job the tests run in the order of
+    /// their names. The test cases are in the order of the test names whatever order the tests
+    /// completed in.
+    pub fn run<'a>(&'a self, class: &'_ str, jobs: usize) -> Vec<ReportCase<'a>> {
+        if jobs <= 1 {
+            return self
+                .tests
+                .iter()
+                .filter_map(|(name, test)| test.run(class, name))
+                .collect();
+        }
+        let tests: Vec<(&String, &PlanTest)> = self.tests.iter().collect();
+        let parallel: Vec<usize> = (0..tests.len())
+            .filter(|&i| tests[i].1.is_parallel())
+            .collect();
+        let next = AtomicUsize::new(0);
+        let mut results: Vec<Option<ReportCase<'a>>> = vec![None; tests.len()];
+        thread::scope(|scope| {
+            let workers: Vec<_> = (0..jobs.min(parallel.len()))
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut completed = Vec::new();
+                        while let Some(&i) = parallel.get(next.fetch_add(1, Ordering::SeqCst)) {
+                            let (name, test) = tests[i];
+                            completed.push((i, test.run(class, name)));
+                        }
+                        completed
+                    })
+                })
+                .collect();
+            for worker in workers {
+                for (i, case) in worker.join().expect("test runner thread panicked") {
+                    results[i] = case;
+                }
+            }
+        });
+        for (i, (name, test)) in tests.iter().enumerate() {
+            if !test.is_parallel() {
+                results[i] = test.run(class, name);
+            }
+        }
+        results.into_iter().flatten().collect()
+    }
 }
 
 impl FromStr for Plan {
     type Err = serde_yaml::Error;
 
     fn from_str(s: &str) -> serde_yaml::Result<Self> {
-        serde_yaml::from_str(s).map(Self::from_map)
+        serde_yaml::from_str(s).and_then(Self::from_map)
     }
 }
 
@@ -77,26 +132,32 @@ enum TestCase {
     Detail(TestPlan),
 }
 
-impl Into<PlanTest> for TestCase {
-    fn into(self) -> PlanTest {
-        match self {
-            Self::Shell(cmd) => PlanTest {
+impl From<TestCase> for PlanTest {
+    fn from(case: TestCase) -> PlanTest {
+        match case {
+            TestCase::Shell(cmd) => PlanTest {
                 command: PlanCommand::Shell(cmd),
                 success: None,
                 failure: None,
                 skipped: None,
+                parallel: None,
+                timeout: None,
             },
-            Self::Exec(args) => PlanTest {
+            TestCase::Exec(args) => PlanTest {
                 command: PlanCommand::Exec(args),
                 success: None,
                 failure: None,
                 skipped: None,
+                parallel: None,
+                timeout: None,
             },
-            Self::Detail(detail) => PlanTest {
+            TestCase::Detail(detail) => PlanTest {
                 command: detail.command,
                 success: detail.success.map(|v| v.into()),
                 failure: detail.failure.map(|v| v.into()),
                 skipped: detail.skipped.map(|v| v.into()),
+                parallel: detail.parallel.or(detail.serial.map(|serial| !serial)),
+                timeout: detail.timeout,
             },
         }
     }
@@ -109,11 +170,11 @@ enum TestExit {
     Multiple(Vec<i32>),
 }
 
-impl Into<Vec<i32>> for TestExit {
-    fn into(self) -> Vec<i32> {
-        match self {
-            Self::Single(n) => vec![n],
-            Self::Multiple(v) => v,
+impl From<TestExit> for Vec<i32> {
+    fn from(exit: TestExit) -> Vec<i32> {
+        match exit {
+            TestExit::Single(n) => vec![n],
+            TestExit::Multiple(v) => v,
         }
     }
 }
@@ -132,6 +193,15 @@ struct TestPlan {
     /// the exit codes to interpret as skipped
     #[serde(default)]
     pub skipped: Option<TestExit>,
+    /// whether the test can run at the same time as other tests
+    #[serde(default)]
+    pub parallel: Option<bool>,
+    /// the test must run on its own, the same as `parallel: false`
+    #[serde(default)]
+    pub serial: Option<bool>,
+    /// the number of seconds after which the command is killed
+    #[serde(default)]
+    pub timeout: Option<u64>,
 }
 
 #[cfg(test)]
@@ -156,6 +226,8 @@ mod tests {
                 success: None,
                 failure: None,
                 skipped: None,
+                parallel: None,
+                timeout: None,
             },
         );
         plan.insert(
@@ -165,6 +237,8 @@ mod tests {
                 success: None,
                 failure: None,
                 skipped: None,
+                parallel: None,
+                timeout: None,
             },
         );
         assert_eq!(plan.tests.len(), 2);
@@ -174,7 +248,9 @@ mod tests {
                 command: PlanCommand::Shell("echo truth".to_string()),
                 success: None,
                 failure: None,
-                skipped: None
+                skipped: None,
+                parallel: None,
+                timeout: None
             })
         );
         assert_eq!(
@@ -183,7 +259,9 @@ mod tests {
                 command: PlanCommand::Exec(vec!["echo".to_string(), "truth".to_string()]),
                 success: None,
                 failure: None,
-                skipped: None
+                skipped: None,
+                parallel: None,
+                timeout: None
             })
         )
     }
@@ -199,7 +277,9 @@ mod tests {
                 command: PlanCommand::Shell("echo truth".to_string()),
                 success: None,
                 failure: None,
-                skipped: None
+                skipped: None,
+                parallel: None,
+                timeout: None
             })
         );
         assert_eq!(
@@ -208,7 +288,9 @@ mod tests {
                 command: PlanCommand::Exec(vec!["echo".to_string(), "truth".to_string()]),
                 success: None,
                 failure: None,
-                skipped: None
+                skipped: None,
+                parallel: None,
+                timeout: None
             })
         )
     }
@@ -223,7 +305,9 @@ mod tests {
                 command: PlanCommand::Shell("echo truth".to_string()),
                 success: None,
                 failure: None,
-                skipped: None
+                skipped: None,
+                parallel: None,
+                timeout: None
             })
         );
         assert_eq!(
@@ -232,11 +316,81 @@ mod tests {
                 command: PlanCommand::Exec(vec!["echo".to_string(), "truth".to_string()]),
                 success: None,
                 failure: None,
-                skipped: None
+                skipped: None,
+                parallel: None,
+                timeout: None
             })
         )
     }
 
+    #[test]
+    fn parse_parallel() {
+        let plan = Plan::from_str(include_str!("../../test/plan/parallel.yaml")).unwrap();
+        assert_eq!(plan.get("slow").map(|t| t.is_parallel()), Some(true));
+        assert_eq!(plan.get("cleanup").map(|t| t.is_parallel()), Some(false));
+        assert_eq!(plan.get("report").map(|t| t.is_parallel()), Some(false));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn run_parallel() {
+        use crate::reports::TestResult;
+
+        // the two slow tests only pass when they see each other's marker while they run
+        let markers = std::env::temp_dir().join(format!("juxr-parallel-{}", std::process::id()));
+        std::fs::create_dir_all(&markers).unwrap();
+        let plan = Plan::from_str(
+            &include_str!("../../test/plan/parallel.yaml")
+                .replace("{markers}", &markers.to_string_lossy()),
+        )
+        .unwrap();
+        let cases = plan.run("parallel", 4);
+        std::fs::remove_dir_all(&markers).unwrap();
+        let results: Vec<(&str, &str, bool)> = cases
+            .iter()
+            .map(|c| {
+                (
+                    c.name(),
+                    c.stdout().trim(),
+                    c.result() == &TestResult::success(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            results,
+            vec![
+                ("cleanup", "cleanup", true),
+                ("report", "", false),
+                ("slow", "slow", true),
+                ("slower", "slower", true),
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn run_in_name_order() {
+        let order = std::env::temp_dir().join(format!("juxr-order-{}", std::process::id()));
+        let plan = Plan::from_str(
+            &include_str!("../../test/plan/order.yaml")
+                .replace("{order}", &order.to_string_lossy()),
+        )
+        .unwrap();
+        let cases = plan.run("order", 1);
+        assert_eq!(cases.len(), 3);
+        assert_eq!(std::fs::read_to_string(&order).unwrap(), "a\nb\nc\n");
+        std::fs::remove_file(&order).unwrap();
+    }
+
+    #[test]
+    fn parse_zero_timeout() {
+        let result = Plan::from_str("hangs:\n  cmd: sleep 30\n  timeout: 0\n");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "the timeout of hangs must be at least 1 second"
+        );
+    }
+
     #[test]
     fn parse_long_form() {
         let plan = Plan::from_str(include_str!("../../test/plan/full.yaml")).unwrap();
@@ -247,7 +401,9 @@ mod tests {
                 command: PlanCommand::Shell("echo truth".to_string()),
                 success: Some(vec![0]),
                 failure: Some(vec![1]),
-                skipped: Some(vec![2])
+                skipped: Some(vec![2]),
+                parallel: None,
+                timeout: None
             })
         );
         assert_eq!(
@@ -256,7 +412,9 @@ mod tests {
                 command: PlanCommand::Exec(vec!["echo".to_string(), "truth".to_string()]),
                 success: Some(vec![0]),
                 failure: Some(vec![1]),
-                skipped: Some(vec![2])
+                skipped: Some(vec![2]),
+                parallel: None,
+                timeout: None
             })
         )
     }
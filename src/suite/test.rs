@@ -17,9 +17,19 @@ use chrono::Utc;
 use serde::Deserialize;
 use serde::Serialize;
 use std::borrow::Cow;
-use std::process::{Command, Stdio};
+use std::io::{self, Read};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
 impl PlanTest {
+    /// can the test run at the same time as other tests
+    pub fn is_parallel(&self) -> bool {
+        self.parallel.unwrap_or(true)
+    }
+
     pub fn run<'a>(
         &'a self,
         class: &'_ str,
@@ -29,7 +39,7 @@ impl PlanTest {
             PlanCommand::Shell(cmd) => {
                 if cfg!(target_os = "windows") {
                     let mut command = Command::new("cmd");
-                    command.args(&["/C", cmd]);
+                    command.args(["/C", cmd]);
                     command
                 } else {
                     let mut command = Command::new("sh");
@@ -41,16 +51,24 @@ impl PlanTest {
                 if args.is_empty() {
                     return None;
                 }
-                let mut child =
-                    Command::new(args.get(0).expect("A command to execute has been supplied"));
+                let mut child = Command::new(
+                    args.first()
+                        .expect("A command to execute has been supplied"),
+                );
                 child.args(&args[1..]);
                 child
             }
         };
+        #[cfg(unix)]
+        if self.timeout.is_some() {
+            use std::os::unix::process::CommandExt;
+            // a process group of its own so that a timeout kills everything the command started
+            child.process_group(0);
+        }
         debug!("Forking {}", self.command.display());
 
         let start = Utc::now();
-        let child = match child.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn() {
+        let mut child = match child.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn() {
             Err(e) => {
                 let test_result = crate::reports::TestResult::error(&format!(
                     "The `{}` command failed to start: {:?}",
@@ -66,7 +84,31 @@ impl PlanTest {
             }
             Ok(child) => child,
         };
-        let output = match child.wait_with_output() {
+        let deadline = self
+            .timeout
+            .map(|timeout| Instant::now() + Duration::from_secs(timeout));
+        // read the output while waiting so that the partial output of a timeout is kept
+        let stdout = PipeReader::new(child.stdout.take());
+        let stderr = PipeReader::new(child.stderr.take());
+        let status = match deadline {
+            Some(deadline) => match wait_deadline(&mut child, deadline) {
+                // processes started in the background can keep the pipes open after the command
+                Ok(Some(_)) if !(stdout.closed_by(deadline) && stderr.closed_by(deadline)) => {
+                    kill(&mut child);
+                    Ok(None)
+                }
+                status => status,
+            },
+            None => child.wait().map(Some),
+        };
+        // once the deadline has passed only the output that is left in the pipes is read
+        let drained = match status {
+            Ok(None) => Some(Instant::now() + Duration::from_millis(500)),
+            _ => None,
+        };
+        let stdout = stdout.output(drained);
+        let stderr = stderr.output(drained);
+        let status = match status {
             Err(e) => {
                 let test_result = crate::reports::TestResult::error(&format!(
                     "The `{}` command didn't start: {:?}",
@@ -80,13 +122,31 @@ impl PlanTest {
                     Utc::now().signed_duration_since(start),
                 ));
             }
-            Ok(status) => status,
+            Ok(Some(status)) => status,
+            Ok(None) => {
+                let test_result = crate::reports::TestResult::error(&format!(
+                    "The `{}` command timed out after {}",
+                    self.command.display(),
+                    match self.timeout.unwrap_or_default() {
+                        1 => "1 second".to_string(),
+                        timeout => format!("{} seconds", timeout),
+                    }
+                ));
+                return Some(crate::reports::TestCase::new_with_output(
+                    method,
+                    class,
+                    &test_result,
+                    Cow::Owned(String::from_utf8_lossy(&stdout).to_string()),
+                    Cow::Owned(String::from_utf8_lossy(&stderr).to_string()),
+                    Utc::now().signed_duration_since(start),
+                ));
+            }
         };
         let duration = Utc::now().signed_duration_since(start);
         let success_codes: Vec<i32> = self.success.clone().unwrap_or_else(|| vec![0]);
-        let skipped_codes: Vec<i32> = self.skipped.clone().unwrap_or_else(Vec::new);
+        let skipped_codes: Vec<i32> = self.skipped.clone().unwrap_or_default();
         let failure_codes: Vec<i32> = self.failure.clone().unwrap_or_else(|| vec![1]);
-        let code = output.status.code().unwrap_or(0);
+        let code = status.code().unwrap_or(0);
         let message = format!(
             "Terminated with exit code {}, expected {:?}",
             code, success_codes
@@ -104,13 +164,97 @@ impl PlanTest {
             method,
             class,
             &test_result,
-            Cow::Owned(String::from_utf8_lossy(&output.stdout).to_string()),
-            Cow::Owned(String::from_utf8_lossy(&output.stderr).to_string()),
+            Cow::Owned(String::from_utf8_lossy(&stdout).to_string()),
+            Cow::Owned(String::from_utf8_lossy(&stderr).to_string()),
             duration,
         ))
     }
 }
 
+/// reads a pipe of the child on a thread of its own until the child and any processes that share
+/// the pipe have closed it
+struct PipeReader {
+    output: Arc<Mutex<Vec<u8>>>,
+    /// disconnected once the pipe is closed
+    closed: Receiver<()>,
+}
+
+impl PipeReader {
+    fn new<R: Read + Send + 'static>(pipe: Option<R>) -> PipeReader {
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let (close, closed) = mpsc::channel();
+        let read = Arc::clone(&output);
+        thread::spawn(move || {
+            let _close = close;
+            if let Some(mut pipe) = pipe {
+                let mut buffer = [0; 8192];
+                loop {
+                    match pipe.read(&mut buffer) {
+                        Ok(0) => break,
+                        Ok(n) => read.lock().unwrap().extend_from_slice(&buffer[..n]),
+                        Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                        Err(_) => break,
+                    }
+                }
+            }
+        });
+        PipeReader { output, closed }
+    }
+
+    /// waits for the pipe to be closed until the deadline, returning whether it was closed
+    fn closed_by(&self, deadline: Instant) -> bool {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        !matches!(
+            self.closed.recv_timeout(timeout),
+            Err(RecvTimeoutError::Timeout)
+        )
+    }
+
+    /// the output read once the pipe is closed, or by the deadline when there is one
+    fn output(self, deadline: Option<Instant>) -> Vec<u8> {
+        match deadline {
+            Some(deadline) => {
+                self.closed_by(deadline);
+            }
+            None => {
+                let _ = self.closed.recv();
+            }
+        }
+        let output = self.output.lock().unwrap();
+        output.clone()
+    }
+}
+
+/// waits for the child to exit, killing it if it does not exit before the deadline
+fn wait_deadline(child: &mut Child, deadline: Instant) -> io::Result<Option<ExitStatus>> {
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            break;
+        }
+        thread::sleep((deadline - now).min(Duration::from_millis(10)));
+    }
+    kill(child);
+    child.wait()?;
+    Ok(None)
+}
+
+/// kills the child and, on unix, the processes it started in its process group. Elsewhere the
+/// processes that the child started are left running.
+fn kill(child: &mut Child) {
+    debug!("Killing process {} after its timeout", child.id());
+    #[cfg(unix)]
+    unsafe {
+        // the child leads its own process group, which outlives it while it has other members
+        libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+    }
+    #[cfg(not(unix))]
+    let _ = child.kill();
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct PlanTest {
     /// the command to execute
@@ -125,12 +269,21 @@ pub struct PlanTest {
     /// the exit codes to interpret as skipped
     #[serde(default)]
     pub skipped: Option<Vec<i32>>,
+    /// whether the test can run at the same time as other tests, the default
+    #[serde(default)]
+    pub parallel: Option<bool>,
+    /// the number of seconds, at least 1, after which the command is killed, along with the
+    /// processes it started on unix
+    #[serde(default)]
+    pub timeout: Option<u64>,
 }
 
 #[cfg(test)]
 mod tests {
     use crate::reports::TestResult;
     use crate::suite::{PlanCommand, PlanTest};
+    #[cfg(unix)]
+    use std::time::{Duration, Instant};
 
     #[test]
     fn successful_test() {
@@ -139,6 +292,8 @@ mod tests {
             success: None,
             failure: None,
             skipped: None,
+            parallel: None,
+            timeout: None,
         };
         let result = instance.run("test.execution", "success").unwrap();
         assert_eq!(result.name(), "success");
@@ -154,6 +309,8 @@ mod tests {
             success: None,
             failure: Some(vec![3]),
             skipped: None,
+            parallel: None,
+            timeout: None,
         };
         let result = instance.run("test.execution", "failure").unwrap();
         assert_eq!(result.name(), "failure");
@@ -171,6 +328,8 @@ mod tests {
             success: None,
             failure: None,
             skipped: Some(vec![3]),
+            parallel: None,
+            timeout: None,
         };
         let result = instance.run("test.execution", "skipped").unwrap();
         assert_eq!(result.name(), "skipped");
@@ -181,6 +340,55 @@ mod tests {
         );
     }
 
+    #[test]
+    #[cfg(unix)]
+    fn timeout_test() {
+        let instance = PlanTest {
+            command: PlanCommand::Shell("echo partial; sleep 30 & sleep 30".to_string()),
+            success: None,
+            failure: None,
+            skipped: None,
+            parallel: None,
+            timeout: Some(1),
+        };
+        let start = Instant::now();
+        let result = instance.run("test.execution", "timeout").unwrap();
+        // the background sleep was killed with its parent rather than holding the output open
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert_eq!(result.name(), "timeout");
+        assert_eq!(
+            result.result(),
+            &TestResult::error(
+                "The `sh -c 'echo partial; sleep 30 & sleep 30'` command timed out after 1 second"
+            )
+        );
+        assert_eq!(result.stdout().trim(), "partial");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn timeout_background_test() {
+        let instance = PlanTest {
+            command: PlanCommand::Shell("echo started; sleep 30 &".to_string()),
+            success: None,
+            failure: None,
+            skipped: None,
+            parallel: None,
+            timeout: Some(2),
+        };
+        let start = Instant::now();
+        let result = instance.run("test.execution", "background").unwrap();
+        // the command exited but the background sleep held its output open past the timeout
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert_eq!(
+            result.result(),
+            &TestResult::error(
+                "The `sh -c 'echo started; sleep 30 &'` command timed out after 2 seconds"
+            )
+        );
+        assert_eq!(result.stdout().trim(), "started");
+    }
+
     #[test]
     fn error_test() {
         let instance = PlanTest {
@@ -188,6 +396,8 @@ mod tests {
             success: None,
             failure: None,
             skipped: None,
+            parallel: None,
+            timeout: None,
         };
         let result = instance.run("test.execution", "error").unwrap();
         assert_eq!(result.name(), "error");
@@ -12,223 +12,1171 @@
  * limitations under the License.
  */
 
-use crate::reports::{TestCase, TestResult, TestSuite};
-use chrono::{DateTime, Duration, Utc};
+mod write;
+
+pub use write::write_tap;
+
+use crate::reports::{Diagnostic, TestCase, TestResult, TestSuite};
+use chrono::{DateTime, Duration, NaiveDateTime, SecondsFormat, Utc};
 use regex::Regex;
+use serde_yaml::Value;
 use std::borrow::Cow;
+use std::collections::{HashSet, VecDeque};
 use std::io::BufRead;
 use std::str::FromStr;
 
-struct TapTestResult {
+#[derive(Clone)]
+struct TapTestResult<'a> {
     result: String,
     number: usize,
     name: Option<String>,
     directive: Option<(String, Option<String>)>,
+    subtests: Vec<TestCase<'a>>,
+    /// the time since the previous test point, when known
+    time: Option<Duration>,
+    /// the error output of the producer after the test point
+    stderr: Vec<String>,
+}
+
+/// A line written by a TAP producer
+#[derive(Debug, Clone, PartialEq)]
+pub enum TapOutput {
+    /// a line of the TAP stream
+    Stdout(String),
+    /// a line of error output, which belongs to the test that last reported a result
+    Stderr(String),
+}
+
+/// How the time of each test is measured. A `duration_ms` in the YAML diagnostics of a test and
+/// timestamps at the start of the lines, e.g. from `ts` or a CI log, are always used when present.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TapTiming {
+    /// the time between test points as the lines are read, for a stream from a running command
+    WallClock,
+    /// only the times recorded in the stream, otherwise no time, for a saved or replayed stream
+    Recorded,
+}
+
+/// How deviations from the TAP specification are handled. Lines that are not TAP are kept as the
+/// output of the test suite in either mode.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TapMode {
+    /// problems with the stream, such as duplicate test numbers or a plan that does not match the
+    /// tests, are reported with their line numbers as an extra error test case
+    Strict,
+    /// problems with the stream are tolerated
+    Lenient,
+}
+
+/// How a test with a `TODO` or `SKIP` directive is recorded. The reason given by the directive is
+/// the message of a failed or skipped test, or a `todo` or `skip` property of a successful test.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TapDirectiveResult {
+    Success,
+    Failure,
+    Skipped,
 }
 
+/// Parses TAP formatted results into a JUnit test suite.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TapParser {
+    timing: TapTiming,
+    mode: TapMode,
+    todo_failed: TapDirectiveResult,
+    todo_passed: TapDirectiveResult,
+    skip: TapDirectiveResult,
+}
+
+impl Default for TapParser {
+    fn default() -> Self {
+        TapParser {
+            timing: TapTiming::WallClock,
+            mode: TapMode::Lenient,
+            todo_failed: TapDirectiveResult::Success,
+            todo_passed: TapDirectiveResult::Failure,
+            skip: TapDirectiveResult::Skipped,
+        }
+    }
+}
+
+/// the lines of a subtest, without their indentation, before the test point that ends it
+#[derive(Default)]
+struct Subtest {
+    lines: Vec<(usize, TapOutput)>,
+    name: Option<String>,
+    start: Option<DateTime<Utc>>,
+}
+
+/// the lines of the output, which can be looked ahead of to find where the next stream starts
+struct Lookahead<I: Iterator> {
+    lines: I,
+    ahead: VecDeque<I::Item>,
+}
+
+impl<I: Iterator> Lookahead<I> {
+    fn new(lines: I) -> Lookahead<I> {
+        Lookahead {
+            lines,
+            ahead: VecDeque::new(),
+        }
+    }
+
+    fn peek(&mut self) -> Option<&I::Item> {
+        self.peek_nth(0)
+    }
+
+    /// the line `n` lines after the next one, without consuming any lines
+    fn peek_nth(&mut self, n: usize) -> Option<&I::Item> {
+        while self.ahead.len() <= n {
+            self.ahead.push_back(self.lines.next()?);
+        }
+        self.ahead.get(n)
+    }
+}
+
+impl<I: Iterator> Iterator for Lookahead<I> {
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<I::Item> {
+        self.ahead.pop_front().or_else(|| self.lines.next())
+    }
+}
+
+/// The patterns that are matched against each line of a TAP stream
+struct LinePatterns {
+    ver: Regex,
+    plan: Regex,
+    test: Regex,
+    test_point: Regex,
+    pragma: Regex,
+    bail: Regex,
+    subtest_comment: Regex,
+    diag: Regex,
+    yaml_start: Regex,
+    yaml_end: Regex,
+    timestamp: Regex,
+}
+
+/// what a stream contained other than test results
+struct StreamReport<'l> {
+    /// the name of the test suite of the stream
+    stream: String,
+    listener: &'l mut dyn TapListener,
+    problems: Vec<Diagnostic>,
+    unknown: Vec<String>,
+    stderr: Vec<String>,
+}
+
+impl StreamReport<'_> {
+    /// tells the listener about a complete test case that did not come from a test point
+    fn notify(&mut self, case: &TestCase<'static>) {
+        self.listener.test_point(&self.stream, case);
+        self.listener.test_case(&self.stream, case);
+    }
+}
+
+/// Follows the progress of the parsing of TAP streams, e.g. to report the tests of a running
+/// command as they complete.
+pub trait TapListener {
+    /// a test point has been read. Its test case can still gain the output and diagnostics of the
+    /// lines that follow before it is complete.
+    fn test_point(&mut self, _stream: &str, _case: &TestCase<'static>) {}
+
+    /// a test case of the named stream is complete, the cases of subtests complete before the
+    /// case of their parent test
+    fn test_case(&mut self, _stream: &str, _case: &TestCase<'static>) {}
+}
+
+/// ignores the progress of the parsing
+impl TapListener for () {}
+
 pub fn read_tap<'a, R: BufRead>(input: &'_ mut R) -> anyhow::Result<TestSuite<'a>> {
-    let ver = Regex::new(r"^TAP version (?P<version>\d+)$").unwrap();
-    let plan = Regex::new(r"^1\.\.(?P<count>\d+)(\s+#.*)?$").unwrap();
-    let test = Regex::new(
-        r"^(?P<result>(not )?ok)(\s+(?P<number>[0-9][0-9]*))?(\s+(?P<name>[^0-9 ][^#]*))?(#\s*(?P<directive>\S+)\s+(?P<message>.*)?)?$",
-    ).unwrap();
-    let bail = Regex::new(r"^Bail out!\s*(?P<description>.*)?$").unwrap();
-    let diag = Regex::new(r"^#\s?(?P<line>.*)").unwrap();
-    let yaml_start = Regex::new(r"^(?P<indent>\s+)---").unwrap();
-    let yaml_end = Regex::new(r"^(?P<indent>\s+)\.\.\.").unwrap();
-
-    let mut test_version: Option<usize> = None;
-    let mut test_plan: Option<usize> = None;
-    let mut test_case: Option<TapTestResult> = None;
-    let mut test_output: Vec<String> = Vec::new();
-    let mut test_number: usize = 0;
-
-    let mut suite_results = TestSuite::new("tap");
-    let mut test_start: DateTime<Utc> = Utc::now();
-    let mut yaml_indent: Option<String> = None;
-
-    for line in input.lines().flat_map(|l| l.ok()) {
-        if test_version.is_none() {
-            // first line should be version if newer version than 12
-            if let Some(cap) = ver.captures(&line) {
-                let v = usize::from_str(cap.name("version").unwrap().as_str())
-                    .expect("only digits should be a valid number");
-                if v < 13 {
-                    return Err(anyhow::anyhow!("TAP version specified as {}. When specified, the TAP version must be at least 13", v));
-                }
-                test_version = Some(v);
-                continue;
-            } else {
-                // no version specified means version 12
-                test_version = Some(12);
+    TapParser::new().parse(input)
+}
+
+/// Parses the TAP streams of an input, such as the concatenated output of several test scripts,
+/// into a test suite for each stream.
+pub fn read_tap_streams<'a, R: BufRead>(input: &'_ mut R) -> anyhow::Result<Vec<TestSuite<'a>>> {
+    TapParser::new().parse_streams(input)
+}
+
+impl TapParser {
+    pub fn new() -> TapParser {
+        TapParser {
+            ..Default::default()
+        }
+    }
+
+    pub fn timing(self, timing: TapTiming) -> TapParser {
+        TapParser { timing, ..self }
+    }
+
+    pub fn mode(self, mode: TapMode) -> TapParser {
+        TapParser { mode, ..self }
+    }
+
+    /// how a `not ok` test with a `TODO` directive, which is expected to fail, is recorded
+    pub fn todo_failed(self, todo_failed: TapDirectiveResult) -> TapParser {
+        TapParser {
+            todo_failed,
+            ..self
+        }
+    }
+
+    /// how an `ok` test with a `TODO` directive, which is unexpectedly passing, is recorded
+    pub fn todo_passed(self, todo_passed: TapDirectiveResult) -> TapParser {
+        TapParser {
+            todo_passed,
+            ..self
+        }
+    }
+
+    /// how a test with a `SKIP` directive is recorded
+    pub fn skip(self, skip: TapDirectiveResult) -> TapParser {
+        TapParser { skip, ..self }
+    }
+
+    /// parses the input into a single test suite, the test cases of each stream after the first
+    /// have the name of their stream as their class
+    pub fn parse<'a, R: BufRead>(&self, input: &'_ mut R) -> anyhow::Result<TestSuite<'a>> {
+        let mut suites = self.parse_streams(input)?;
+        if suites.len() == 1 {
+            return Ok(suites.remove(0));
+        }
+        let mut suite_results = TestSuite::new("tap");
+        let mut stdout = Vec::new();
+        let mut stderr = Vec::new();
+        for suite in suites {
+            if !suite.stdout().is_empty() {
+                stdout.push(suite.stdout().to_string());
+            }
+            if !suite.stderr().is_empty() {
+                stderr.push(suite.stderr().to_string());
+            }
+            for case in suite.cases() {
+                suite_results = suite_results.push(case.clone());
             }
         }
-        if let Some(indent) = &yaml_indent {
-            if let Some(cap) = yaml_end.captures(&line) {
-                if indent == cap.name("indent").unwrap().as_str() {
-                    // this is the matching end
-                    yaml_indent = None;
+        Ok(suite_results
+            .with_stdout(&stdout.join("\n"))
+            .with_stderr(&stderr.join("\n")))
+    }
+
+    /// parses the input into a test suite for each TAP stream. A stream ends at a `TAP version` line
+    /// once it has a plan, or at a plan followed by tests once it has run the tests of its plan. The streams are named `tap`, `tap-2`, `tap-3` and so on.
+    // lines that are not valid UTF-8 are skipped
+    #[allow(clippy::lines_filter_map_ok)]
+    pub fn parse_streams<'a, R: BufRead>(
+        &self,
+        input: &'_ mut R,
+    ) -> anyhow::Result<Vec<TestSuite<'a>>> {
+        self.parse_output(input.lines().flat_map(|l| l.ok()).map(TapOutput::Stdout))
+    }
+
+    /// parses the output of a TAP producer, with its error output in the order it was written,
+    /// into a test suite for each TAP stream
+    pub fn parse_output<'a, I: Iterator<Item = TapOutput>>(
+        &self,
+        output: I,
+    ) -> anyhow::Result<Vec<TestSuite<'a>>> {
+        self.parse_output_with(output, &mut ())
+    }
+
+    /// parses the output of a TAP producer like [`TapParser::parse_output`], telling the listener
+    /// about each test as it is read
+    pub fn parse_output_with<'a, I: Iterator<Item = TapOutput>>(
+        &self,
+        output: I,
+        listener: &mut dyn TapListener,
+    ) -> anyhow::Result<Vec<TestSuite<'a>>> {
+        let patterns = LinePatterns {
+            ver: Regex::new(r"^TAP version (?P<version>\d+)$").unwrap(),
+            plan: Regex::new(r"^1\.\.(?P<count>\d+)(\s+#.*)?$").unwrap(),
+            test: Regex::new(
+                r"^(?P<result>(not )?ok)(\s+(?P<number>[0-9][0-9]*))?(\s+(?P<name>[^0-9 ][^#]*))?(#\s*(?P<directive>\S+)\s+(?P<message>.*)?)?$",
+            )
+            .unwrap(),
+            test_point: Regex::new(r"^(not )?ok\b(\s+[0-9]+)?(?P<rest>.*)$").unwrap(),
+            pragma: Regex::new(r"^pragma [+-]\w+$").unwrap(),
+            bail: Regex::new(r"^Bail out!\s*(?P<description>.*)?$").unwrap(),
+            subtest_comment: Regex::new(r"^#\s*Subtest(:\s*(?P<name>.*))?$").unwrap(),
+            diag: Regex::new(r"^#\s?(?P<line>.*)").unwrap(),
+            yaml_start: Regex::new(r"^(?P<indent>\s+)---").unwrap(),
+            yaml_end: Regex::new(r"^(?P<indent>\s+)\.\.\.").unwrap(),
+            timestamp: Regex::new(
+                r"^(?P<timestamp>\d{4}-\d{2}-\d{2}[T ]\d{2}:\d{2}:\d{2}(\.\d+)?(Z|[+-]\d{2}:?\d{2})?)[ \t]",
+            )
+            .unwrap(),
+        };
+        // the line numbers are those of the TAP stream
+        let mut line_number = 0;
+        let mut lines = Lookahead::new(output.map(|line| {
+            if let TapOutput::Stdout(_) = line {
+                line_number += 1;
+            }
+            (line_number, line)
+        }));
+        let mut suites = Vec::new();
+        loop {
+            let name = match suites.len() {
+                0 => "tap".to_string(),
+                n => format!("tap-{}", n + 1),
+            };
+            suites.push(self.read_suite(&mut lines, &name, &patterns, listener)?);
+            if lines.peek().is_none() {
+                break;
+            }
+        }
+        Ok(suites)
+    }
+
+    fn read_suite<'a, I: Iterator<Item = (usize, TapOutput)>>(
+        &self,
+        lines: &mut Lookahead<I>,
+        name: &str,
+        patterns: &LinePatterns,
+        listener: &mut dyn TapListener,
+    ) -> anyhow::Result<TestSuite<'a>> {
+        let mut report = StreamReport {
+            stream: name.to_string(),
+            listener,
+            problems: Vec::new(),
+            unknown: Vec::new(),
+            stderr: Vec::new(),
+        };
+        let cases = self.read_stream(lines, name, None, None, patterns, &mut report)?;
+        let mut suite_results = TestSuite::new(name);
+        for case in cases {
+            suite_results = suite_results.push(case);
+        }
+        if self.mode == TapMode::Strict && !report.problems.is_empty() {
+            let message = match report.problems.as_slice() {
+                [problem] => problem.to_string(),
+                problems => format!("{} problems in the TAP stream", problems.len()),
+            };
+            let detail: Vec<String> = report.problems.iter().map(|p| p.to_string()).collect();
+            let case = TestCase::new(
+                "[tap]",
+                name,
+                &TestResult::Error {
+                    type_: Cow::Borrowed("invalid TAP"),
+                    message: Cow::Owned(message),
+                    detail: Cow::Owned(detail.join("\n")),
+                },
+                Duration::zero(),
+            );
+            report.notify(&case);
+            suite_results = suite_results.push(case);
+        }
+        if !report.unknown.is_empty() {
+            suite_results = suite_results.with_stdout(&report.unknown.join("\n"));
+        }
+        if !report.stderr.is_empty() {
+            suite_results = suite_results.with_stderr(&report.stderr.join("\n"));
+        }
+        Ok(suite_results)
+    }
+
+    /// parses a TAP stream, or the indented stream of a subtest with the version of its parent,
+    /// into test cases of the class. The cases of subtests follow their parent test with the
+    /// parent's name appended to the class. A stream that is not a subtest ends before the line
+    /// that starts the next stream.
+    fn read_stream<'a, I: Iterator<Item = (usize, TapOutput)>>(
+        &self,
+        lines: &mut Lookahead<I>,
+        class: &str,
+        version: Option<usize>,
+        start: Option<DateTime<Utc>>,
+        patterns: &LinePatterns,
+        report: &mut StreamReport<'_>,
+    ) -> anyhow::Result<Vec<TestCase<'a>>> {
+        let LinePatterns {
+            ver,
+            plan,
+            test,
+            test_point,
+            pragma,
+            bail,
+            subtest_comment,
+            diag,
+            yaml_start,
+            yaml_end,
+            timestamp,
+        } = patterns;
+
+        // subtests do not have a version line
+        let mut test_version: Option<usize> = version;
+        let mut test_plan: Option<usize> = None;
+        let mut plan_line: Option<usize> = None;
+        // the plan came after the tests, so the stream should have ended
+        let mut ended = false;
+        let mut test_case: Option<TapTestResult> = None;
+        let mut test_output: Vec<String> = Vec::new();
+        let mut test_yaml: Vec<String> = Vec::new();
+        let mut test_number: usize = 0;
+        let mut test_points: usize = 0;
+        let mut numbers: HashSet<usize> = HashSet::new();
+        let mut subtest = Subtest::default();
+
+        let mut cases: Vec<TestCase<'a>> = Vec::new();
+        // the time of the previous test point, or of the start of the stream
+        let mut test_start: Option<DateTime<Utc>> = start;
+        let mut yaml_indent: Option<String> = None;
+        // the reason given by a `Bail out!`
+        let mut bailed: Option<String> = None;
+
+        // whether the plan of the next line is followed by tests rather than repeating the plan
+        let plan_has_tests = |lines: &mut Lookahead<I>| {
+            let mut n = 1;
+            while let Some((_, output)) = lines.peek_nth(n) {
+                n += 1;
+                if let TapOutput::Stdout(line) = output {
+                    let line = match timestamp.find(line) {
+                        Some(clock) => &line[clock.end()..],
+                        None => line,
+                    };
+                    if test_point.is_match(line) || bail.is_match(line) {
+                        return true;
+                    } else if plan.is_match(line) || ver.is_match(line) {
+                        return false;
+                    }
+                }
+            }
+            false
+        };
+
+        while let Some((line_number, output)) = lines.peek() {
+            let line_number = *line_number;
+            let line = match output {
+                TapOutput::Stdout(line) => line,
+                TapOutput::Stderr(line) => {
+                    let line = line.clone();
+                    lines.next();
+                    if !subtest.lines.is_empty() {
+                        // the subtest is still running
+                        subtest.lines.push((line_number, TapOutput::Stderr(line)));
+                    } else if let Some(test) = test_case.as_mut() {
+                        test.stderr.push(line);
+                    } else {
+                        report.stderr.push(line);
+                    }
                     continue;
                 }
+            };
+            let (clock, line) = match timestamp.captures(line) {
+                Some(cap) => (
+                    parse_timestamp(cap.name("timestamp").unwrap().as_str()),
+                    line[cap.get(0).unwrap().end()..].to_string(),
+                ),
+                None => (None, line.clone()),
+            };
+            if version.is_none()
+                && ((ver.is_match(&line) && test_plan.is_some())
+                    || (plan.is_match(&line)
+                        && (ended || test_plan.is_some_and(|count| test_points >= count))
+                        && plan_has_tests(lines)))
+            {
+                // the start of the next stream
+                break;
+            }
+            lines.next();
+            let clock = clock.or_else(|| match self.timing {
+                TapTiming::WallClock => Some(Utc::now()),
+                TapTiming::Recorded => None,
+            });
+            if test_start.is_none() {
+                test_start = clock;
             }
-            if line.starts_with(indent) {
-                test_output.push((&line[indent.len()..]).to_string());
+            if test_version.is_none() {
+                // first line should be version if newer version than 12
+                if let Some(cap) = ver.captures(&line) {
+                    let v = usize::from_str(cap.name("version").unwrap().as_str())
+                        .expect("only digits should be a valid number");
+                    if v < 13 {
+                        return Err(anyhow::anyhow!("line {}: TAP version specified as {}. When specified, the TAP version must be at least 13", line_number, v));
+                    }
+                    if v > 14 {
+                        return Err(anyhow::anyhow!(
+                            "line {}: TAP version specified as {}. Only TAP versions up to 14 are supported",
+                            line_number,
+                            v
+                        ));
+                    }
+                    test_version = Some(v);
+                    continue;
+                } else {
+                    if line.starts_with("TAP version") {
+                        report.problems.push(Diagnostic::error(
+                            Some(line_number),
+                            &format!("malformed version line {:?}", line),
+                        ));
+                    }
+                    // no version specified means version 12
+                    test_version = Some(12);
+                }
+            } else if ver.is_match(&line) {
+                report.problems.push(Diagnostic::error(
+                    Some(line_number),
+                    "the TAP version must be the first line",
+                ));
                 continue;
-            } else {
-                yaml_indent = None;
             }
-        }
+            if ended {
+                if test.is_match(&line) {
+                    report.problems.push(Diagnostic::error(
+                        Some(line_number),
+                        "test point after the plan at the end of the tests",
+                    ));
+                } else if plan.is_match(&line) {
+                    report
+                        .problems
+                        .push(Diagnostic::error(Some(line_number), "more than one plan"));
+                }
+                if !line.trim().is_empty() {
+                    report.unknown.push(line);
+                }
+                continue;
+            }
+            if let Some(indent) = &yaml_indent {
+                if let Some(cap) = yaml_end.captures(&line) {
+                    if indent == cap.name("indent").unwrap().as_str() {
+                        // this is the matching end
+                        yaml_indent = None;
+                        continue;
+                    }
+                }
+                if line.starts_with(indent) {
+                    test_yaml.push(line[indent.len()..].to_string());
+                    continue;
+                } else if line.trim().is_empty() && test_version >= Some(14) {
+                    // TAP 14 allows the blank lines of YAML block scalars
+                    test_yaml.push(String::new());
+                    continue;
+                } else {
+                    yaml_indent = None;
+                }
+            }
 
-        if let Some(cap) = plan.captures(&line) {
-            if test_plan.is_some() {
-                return Err(anyhow::anyhow!(
-                    "More than one test plan in the supplied input"
-                ));
+            if line.starts_with("    ") || (!subtest.lines.is_empty() && line.trim().is_empty()) {
+                let line = line.get(4..).unwrap_or("");
+                if subtest.lines.is_empty() && subtest.start.is_none() {
+                    subtest.start = clock;
+                }
+                match subtest_comment.captures(line) {
+                    Some(cap) if subtest.lines.is_empty() => {
+                        subtest.name = cap.name("name").map(|m| m.as_str().to_string());
+                    }
+                    // keep the time of the line for the subtest's own timing
+                    _ => subtest.lines.push((
+                        line_number,
+                        TapOutput::Stdout(match clock {
+                            Some(clock) => format!(
+                                "{} {}",
+                                clock.to_rfc3339_opts(SecondsFormat::AutoSi, true),
+                                line
+                            ),
+                            None => line.to_string(),
+                        }),
+                    )),
+                }
+                continue;
+            }
+            if let Some(cap) = subtest_comment.captures(&line) {
+                cases.append(&mut self.read_orphans(
+                    &mut subtest,
+                    class,
+                    test_version,
+                    patterns,
+                    report,
+                )?);
+                subtest.name = cap.name("name").map(|m| m.as_str().to_string());
+                subtest.start = clock;
+                continue;
             }
-            let test_count = usize::from_str(cap.name("count").unwrap().as_str())
-                .expect("only digits should be a valid version number");
-            test_plan = Some(test_count);
-            if test_number > 0 {
-                // the plan is at the end
-                while test_number < test_count {
-                    suite_results = suite_results.push(TestCase::new(
+            if !test.is_match(&line) {
+                cases.append(&mut self.read_orphans(
+                    &mut subtest,
+                    class,
+                    test_version,
+                    patterns,
+                    report,
+                )?);
+            }
+
+            if let Some(cap) = plan.captures(&line) {
+                if test_plan.is_some() {
+                    if self.mode == TapMode::Strict {
+                        return Err(anyhow::anyhow!(
+                            "More than one test plan in the supplied input"
+                        ));
+                    }
+                    report
+                        .problems
+                        .push(Diagnostic::error(Some(line_number), "more than one plan"));
+                    report.unknown.push(line);
+                    continue;
+                }
+                let test_count = usize::from_str(cap.name("count").unwrap().as_str())
+                    .expect("only digits should be a valid version number");
+                test_plan = Some(test_count);
+                plan_line = Some(line_number);
+                if test_number > 0 {
+                    // the plan is at the end
+                    ended = true;
+                    continue;
+                }
+                test_start = clock;
+            } else if let Some(cap) = test.captures(&line) {
+                if let Some(previous) = test_case.take() {
+                    // record the previous test result
+                    push_test_case(
+                        self,
+                        report,
+                        &mut cases,
+                        &test_output,
+                        &test_yaml,
+                        class,
+                        previous,
+                    );
+                }
+                // walk up any missing test numbers as failed
+
+                test_number += 1;
+                test_points += 1;
+                let result = cap.name("result").map(|m| m.as_str().to_string()).unwrap();
+                let number = cap
+                    .name("number")
+                    .map(|m| usize::from_str(m.as_str()).unwrap())
+                    .unwrap_or(test_number);
+                if number < test_number {
+                    let problem = if numbers.contains(&number) {
+                        format!("duplicate test number {}", number)
+                    } else {
+                        format!(
+                            "test number {} is out of order, expected {}",
+                            number, test_number
+                        )
+                    };
+                    report
+                        .problems
+                        .push(Diagnostic::error(Some(line_number), &problem));
+                }
+                numbers.insert(number);
+                while test_number < number {
+                    let case = TestCase::new(
                         &format!("test {}", test_number),
-                        "tap",
+                        class,
                         &TestResult::failure("missing"),
                         Duration::milliseconds(0),
-                    ));
+                    );
+                    report.notify(&case);
+                    cases.push(case);
                     test_number += 1;
                 }
+                let (name, directive) = if test_version >= Some(14) {
+                    let rest = test_point.captures(&line).unwrap();
+                    parse_description(rest.name("rest").unwrap().as_str())
+                } else {
+                    (
+                        cap.name("name").map(|m| m.as_str().to_string()),
+                        cap.name("directive")
+                            .map(|m| m.as_str().to_string().to_uppercase())
+                            .map(|d| (d, cap.name("message").map(|m| m.as_str().to_string()))),
+                    )
+                };
+                let subtests = if subtest.lines.is_empty() {
+                    subtest = Subtest::default();
+                    Vec::new()
+                } else {
+                    let parent = name
+                        .as_deref()
+                        .map(|n| n.trim().trim_start_matches("- ").trim().to_string())
+                        .filter(|n| !n.is_empty())
+                        .or_else(|| subtest.name.take())
+                        .unwrap_or_else(|| format!("test {}", number));
+                    let Subtest { lines, start, .. } = std::mem::take(&mut subtest);
+                    self.read_stream(
+                        &mut Lookahead::new(lines.into_iter()),
+                        &format!("{}.{}", class, parent),
+                        test_version,
+                        start,
+                        patterns,
+                        report,
+                    )?
+                };
+                let point = TapTestResult {
+                    result,
+                    number,
+                    name,
+                    directive,
+                    subtests: Vec::new(),
+                    time: clock.zip(test_start).map(|(end, start)| end - start),
+                    stderr: Vec::new(),
+                };
+                let case = to_test_case(
+                    self,
+                    point.clone(),
+                    &[],
+                    &[],
+                    class,
+                    &failed_subtests(&subtests),
+                );
+                report.listener.test_point(&report.stream, &case);
+                test_case.replace(TapTestResult { subtests, ..point });
+                test_output.clear();
+                test_yaml.clear();
+                test_start = clock;
+            } else if let Some(cap) = bail.captures(&line) {
+                bailed = Some(
+                    cap.name("description")
+                        .map(|m| m.as_str().trim().to_string())
+                        .unwrap_or_default(),
+                );
+                // nothing after a bail out belongs to the tests
+                for (_, output) in lines.by_ref() {
+                    match output {
+                        TapOutput::Stdout(line) if !line.trim().is_empty() => {
+                            report.unknown.push(line)
+                        }
+                        TapOutput::Stderr(line) => report.stderr.push(line),
+                        _ => {}
+                    }
+                }
                 break;
+            } else if test_version >= Some(14) && pragma.is_match(&line) {
+                debug!("Ignoring {}", line);
+            } else if let Some(cap) = diag.captures(&line) {
+                test_output.push(cap.name("line").unwrap().as_str().to_string());
+            } else if let Some(cap) = yaml_start.captures(&line) {
+                yaml_indent = Some(cap.name("indent").unwrap().as_str().to_string());
+            } else if !line.trim().is_empty() {
+                report.unknown.push(line);
             }
-            test_start = Utc::now();
-        } else if let Some(cap) = test.captures(&line) {
-            if let Some(TapTestResult {
-                result,
-                number,
-                name,
-                directive,
-            }) = test_case.take()
-            {
-                // record the previous test result
-                let case = to_test_case(&test_output, test_start, result, number, name, directive);
-                suite_results = suite_results.push(case);
+        }
+        cases.append(&mut self.read_orphans(
+            &mut subtest,
+            class,
+            test_version,
+            patterns,
+            report,
+        )?);
+        if let Some(previous) = test_case.take() {
+            // record the previous test result
+            push_test_case(
+                self,
+                report,
+                &mut cases,
+                &test_output,
+                &test_yaml,
+                class,
+                previous,
+            );
+        }
+        if let Some(reason) = &bailed {
+            let case = TestCase::new(
+                "Bail out!",
+                class,
+                &TestResult::Error {
+                    type_: Cow::Borrowed("bail out"),
+                    message: Cow::Owned(reason.clone()),
+                    detail: Cow::Borrowed(""),
+                },
+                Duration::milliseconds(0),
+            );
+            report.notify(&case);
+            cases.push(case);
+        }
+        match test_plan {
+            Some(test_count) if bailed.is_none() && test_points != test_count => {
+                report.problems.push(Diagnostic::error(
+                    plan_line,
+                    &format!("planned {} tests but {} ran", test_count, test_points),
+                ));
             }
-            // walk up any missing test numbers as failed
-
-            test_number += 1;
-            let result = cap.name("result").map(|m| m.as_str().to_string()).unwrap();
-            let number = cap
-                .name("number")
-                .map(|m| usize::from_str(m.as_str()).unwrap())
-                .unwrap_or(test_number);
-            while test_number < number {
-                suite_results = suite_results.push(TestCase::new(
-                    &format!("test {}", test_number),
-                    "tap",
-                    &TestResult::failure("missing"),
-                    Duration::milliseconds(0),
+            None if bailed.is_none() => {
+                report.problems.push(Diagnostic::error(
+                    None,
+                    &match class {
+                        "tap" => "no plan".to_string(),
+                        class => format!("no plan for the subtest {}", class),
+                    },
                 ));
+            }
+            _ => {}
+        }
+        if let Some(test_count) = test_plan {
+            // the planned tests that did not report a result
+            let missing = match &bailed {
+                Some(reason) => TestResult::Error {
+                    type_: Cow::Borrowed("not run"),
+                    message: Cow::Owned(format!("Bail out! {}", reason).trim().to_string()),
+                    detail: Cow::Borrowed(""),
+                },
+                None => TestResult::failure("missing"),
+            };
+            while test_number < test_count {
                 test_number += 1;
+                let case = TestCase::new(
+                    &format!("test {}", test_number),
+                    class,
+                    &missing,
+                    Duration::milliseconds(0),
+                );
+                report.notify(&case);
+                cases.push(case);
             }
-            let name = cap.name("name").map(|m| m.as_str().to_string());
-            let directive = cap
-                .name("directive")
-                .map(|m| m.as_str().to_string().to_uppercase())
-                .map(|d| (d, cap.name("message").map(|m| m.as_str().to_string())));
-            test_case.replace(TapTestResult {
-                result,
-                number,
-                name,
-                directive,
-            });
-            test_output.clear();
-            test_start = Utc::now();
-        } else if bail.is_match(&line) {
-            break;
-        } else if let Some(cap) = diag.captures(&line) {
-            test_output.push(cap.name("line").unwrap().as_str().to_string());
-        } else if let Some(cap) = yaml_start.captures(&line) {
-            yaml_indent = Some(cap.name("indent").unwrap().as_str().to_string());
-            test_output.push("---".to_string());
-        } else {
-            // unknown
         }
+        Ok(cases)
     }
-    if let Some(TapTestResult {
-        result,
-        number,
-        name,
-        directive,
-    }) = test_case.take()
-    {
-        // record the previous test result
-        let case = to_test_case(&test_output, test_start, result, number, name, directive);
-        suite_results = suite_results.push(case);
-    }
-    if let Some(test_count) = test_plan {
-        while test_number < test_count {
-            suite_results = suite_results.push(TestCase::new(
-                &format!("test {}", test_number),
-                "tap",
-                &TestResult::failure("missing"),
-                Duration::milliseconds(0),
-            ));
-            test_number += 1;
+
+    /// parses a subtest that is not followed by its parent test
+    fn read_orphans<'a>(
+        &self,
+        subtest: &mut Subtest,
+        class: &str,
+        version: Option<usize>,
+        patterns: &LinePatterns,
+        report: &mut StreamReport<'_>,
+    ) -> anyhow::Result<Vec<TestCase<'a>>> {
+        let Subtest { lines, name, start } = std::mem::take(subtest);
+        if lines.is_empty() {
+            return Ok(Vec::new());
         }
+        let class = match name {
+            Some(name) => format!("{}.{}", class, name),
+            None => class.to_string(),
+        };
+        self.read_stream(
+            &mut Lookahead::new(lines.into_iter()),
+            &class,
+            version,
+            start,
+            patterns,
+            report,
+        )
     }
-    Ok(suite_results)
 }
 
-fn to_test_case<'a>(
-    test_output: &'_ [String],
-    test_start: DateTime<Utc>,
-    result: String,
-    number: usize,
-    name: Option<String>,
-    directive: Option<(String, Option<String>)>,
-) -> TestCase<'a> {
-    let test_result = match result.as_str() {
-        "ok" => match directive {
-            None => TestResult::success(),
-            Some(d) => {
-                if &d.0 == "SKIP" {
-                    TestResult::skipped(&d.1.unwrap_or_else(|| "".to_string()))
-                } else if &d.0 == "TODO" {
-                    TestResult::failure(&d.1.unwrap_or_else(|| "".to_string()))
-                } else {
-                    TestResult::success()
+/// parses the timestamp at the start of a line, without an offset the time is taken to be UTC
+fn parse_timestamp(timestamp: &str) -> Option<DateTime<Utc>> {
+    let timestamp = timestamp.replacen(' ', "T", 1);
+    if let Ok(time) = DateTime::parse_from_rfc3339(&timestamp) {
+        return Some(time.with_timezone(&Utc));
+    }
+    if let Ok(time) = DateTime::parse_from_str(&timestamp, "%Y-%m-%dT%H:%M:%S%.f%z") {
+        return Some(time.with_timezone(&Utc));
+    }
+    NaiveDateTime::parse_from_str(&timestamp, "%Y-%m-%dT%H:%M:%S%.f")
+        .ok()
+        .map(|time| DateTime::from_utc(time, Utc))
+}
+
+/// splits the rest of a TAP 14 test point into the description and directive, where `\#` and
+/// `\\` are escapes and the description may be preceded by ` - `
+fn parse_description(rest: &str) -> (Option<String>, Option<(String, Option<String>)>) {
+    let mut description = String::new();
+    let mut directive = None;
+    let mut chars = rest.char_indices();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some((_, escaped)) if escaped == '#' || escaped == '\\' => {
+                    description.push(escaped)
+                }
+                Some((_, other)) => {
+                    description.push('\\');
+                    description.push(other);
                 }
+                None => description.push('\\'),
+            },
+            '#' => {
+                directive = Some(&rest[index + 1..]);
+                break;
+            }
+            c => description.push(c),
+        }
+    }
+    let description = description.trim();
+    let description = description
+        .strip_prefix('-')
+        .map(|d| d.trim_start())
+        .unwrap_or(description);
+    let directive = directive.map(str::trim).and_then(|d| {
+        let mut parts = d.splitn(2, char::is_whitespace);
+        let word = parts.next().filter(|w| !w.is_empty())?;
+        let message = parts.next().map(|m| m.trim().to_string());
+        Some((word.to_uppercase(), message.filter(|m| !m.is_empty())))
+    });
+    (
+        Some(description.to_string()).filter(|d| !d.is_empty()),
+        directive,
+    )
+}
+
+fn push_test_case<'a>(
+    parser: &TapParser,
+    report: &mut StreamReport<'_>,
+    cases: &mut Vec<TestCase<'a>>,
+    test_output: &'_ [String],
+    test_yaml: &'_ [String],
+    class: &str,
+    mut test: TapTestResult<'a>,
+) {
+    let subtests = std::mem::take(&mut test.subtests);
+    let failed = failed_subtests(&subtests);
+    let case = to_test_case(parser, test, test_output, test_yaml, class, &failed);
+    report.listener.test_case(&report.stream, &case);
+    cases.push(case);
+    cases.extend(subtests);
+}
+
+/// the names of the failed direct subtests, which come first, as a parent test cannot pass when
+/// its subtests did not
+fn failed_subtests<'s>(subtests: &'s [TestCase<'_>]) -> Vec<&'s str> {
+    let direct = subtests.first().map(|c| c.class()).unwrap_or_default();
+    subtests
+        .iter()
+        .filter(|c| {
+            c.class() == direct
+                && matches!(
+                    c.result(),
+                    TestResult::Failure { .. } | TestResult::Error { .. }
+                )
+        })
+        .map(|c| c.name())
+        .collect()
+}
+
+/// The YAML diagnostics of a test point, with the keys used by `write_tap` and the common TAP
+/// producers mapped
+#[derive(Debug, Default)]
+struct Diagnostics {
+    message: Option<String>,
+    severity: Option<String>,
+    type_: Option<String>,
+    file: Option<String>,
+    line: Option<u32>,
+    /// the wanted and found values and the stack trace
+    detail: Option<String>,
+    duration: Option<Duration>,
+    stdout: Option<String>,
+    stderr: Option<String>,
+    /// the diagnostics that are not mapped, as YAML
+    other: Option<String>,
+}
+
+fn parse_diagnostics(yaml: &[String]) -> Diagnostics {
+    if yaml.is_empty() {
+        return Diagnostics::default();
+    }
+    let text = yaml.join("\n");
+    let mut mapping = match serde_yaml::from_str::<Value>(&text) {
+        Ok(Value::Mapping(mapping)) => mapping,
+        _ => {
+            // not something that can be mapped, keep it as it was
+            return Diagnostics {
+                other: Some(format!("---\n{}", text)),
+                ..Default::default()
+            };
+        }
+    };
+    let mut take = |key: &str| mapping.remove(&Value::String(key.to_string()));
+    let message = take("message").as_ref().and_then(scalar);
+    let severity = take("severity").as_ref().and_then(scalar);
+    let type_ = take("type").as_ref().and_then(scalar);
+    let (at_file, at_line) = match take("at") {
+        Some(Value::Mapping(at)) => (
+            at.get(&Value::String("file".to_string())).and_then(scalar),
+            at.get(&Value::String("line".to_string())).and_then(scalar),
+        ),
+        Some(Value::String(at)) => match at.rsplit_once(':') {
+            Some((file, line)) if line.parse::<u32>().is_ok() => {
+                (Some(file.to_string()), Some(line.to_string()))
             }
+            _ => (Some(at), None),
         },
-        "not ok" => match directive {
-            None => TestResult::failure(""),
-            Some(d) => {
-                if &d.0 == "SKIP" {
-                    TestResult::skipped(&d.1.unwrap_or_else(|| "".to_string()))
-                } else if &d.0 == "TODO" {
+        _ => (None, None),
+    };
+    let file = take("file").as_ref().and_then(scalar).or(at_file);
+    let line = take("line")
+        .as_ref()
+        .and_then(scalar)
+        .or(at_line)
+        .and_then(|l| l.parse().ok());
+    let mut detail = Vec::new();
+    for key in &["wanted", "found"] {
+        if let Some(value) = take(key) {
+            let value = scalar(&value).unwrap_or_else(|| "~".to_string());
+            if value.contains('\n') {
+                detail.push(format!("{}:\n{}", key, value));
+            } else {
+                detail.push(format!("{}: {}", key, value));
+            }
+        }
+    }
+    if let Some(stack) = take("stack").as_ref().and_then(scalar) {
+        detail.push(stack);
+    }
+    let duration = take("duration_ms")
+        .and_then(|d| d.as_f64())
+        .map(|d| Duration::milliseconds(d as i64));
+    let stdout = take("stdout").as_ref().and_then(scalar);
+    let stderr = take("stderr").as_ref().and_then(scalar);
+    let other = if mapping.is_empty() {
+        None
+    } else {
+        serde_yaml::to_string(&Value::Mapping(mapping))
+            .ok()
+            .map(|y| y.trim_end().to_string())
+    };
+    Diagnostics {
+        message,
+        severity,
+        type_,
+        file,
+        line,
+        detail: Some(detail.join("\n")).filter(|d| !d.is_empty()),
+        duration,
+        stdout,
+        stderr,
+        other,
+    }
+}
+
+/// the text of a YAML value, with collections as YAML
+fn scalar(value: &Value) -> Option<String> {
+    match value {
+        Value::Null => None,
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        other => serde_yaml::to_string(other)
+            .ok()
+            .map(|y| y.trim_start_matches("---\n").trim_end().to_string()),
+    }
+}
+
+fn to_test_case<'b>(
+    parser: &TapParser,
+    test: TapTestResult<'_>,
+    test_output: &'_ [String],
+    test_yaml: &'_ [String],
+    class: &str,
+    failed_subtests: &[&str],
+) -> TestCase<'b> {
+    let TapTestResult {
+        result,
+        number,
+        name,
+        directive,
+        time,
+        mut stderr,
+        ..
+    } = test;
+    let mut property = None;
+    let test_result = match (result.as_str(), directive) {
+        ("ok", Some((d, reason))) | ("not ok", Some((d, reason))) if d == "SKIP" || d == "TODO" => {
+            let reason = reason.unwrap_or_default();
+            let (record, message) = match (d.as_str(), result.as_str()) {
+                ("SKIP", _) => (parser.skip, reason.clone()),
+                ("TODO", "ok") => (
+                    parser.todo_passed,
+                    match reason.as_str() {
+                        "" => "unexpectedly passing".to_string(),
+                        reason => format!("unexpectedly passing: {}", reason),
+                    },
+                ),
+                _ => (parser.todo_failed, reason.clone()),
+            };
+            match record {
+                TapDirectiveResult::Success => {
+                    property = Some((d.to_lowercase(), reason));
                     TestResult::success()
-                } else {
-                    TestResult::failure(&d.1.unwrap_or_else(|| "".to_string()))
                 }
+                TapDirectiveResult::Failure => TestResult::failure(&message),
+                TapDirectiveResult::Skipped => TestResult::skipped(&message),
             }
-        },
+        }
+        ("ok", _) => TestResult::success(),
+        ("not ok", Some((_, reason))) => TestResult::failure(&reason.unwrap_or_default()),
+        ("not ok", None) => TestResult::failure(""),
         _ => TestResult::error("unexpected test result"),
     };
+    let diagnostics = parse_diagnostics(test_yaml);
+    let test_result = match test_result {
+        TestResult::Success if !failed_subtests.is_empty() => TestResult::failure(""),
+        other => other,
+    };
+    let test_result = match test_result {
+        TestResult::Failure { message, .. } => {
+            let message = Some(message.to_string())
+                .filter(|m| !m.is_empty())
+                .or_else(|| diagnostics.message.clone())
+                .or_else(|| {
+                    Some(format!("subtests failed: {}", failed_subtests.join(", ")))
+                        .filter(|_| !failed_subtests.is_empty())
+                })
+                .unwrap_or_default();
+            let detail = diagnostics.detail.clone().unwrap_or_default();
+            if diagnostics.severity.as_deref() == Some("error") {
+                TestResult::Error {
+                    type_: Cow::Owned(
+                        diagnostics
+                            .type_
+                            .clone()
+                            .unwrap_or_else(|| "error".to_string()),
+                    ),
+                    message: Cow::Owned(message),
+                    detail: Cow::Owned(detail),
+                }
+            } else {
+                TestResult::Failure {
+                    type_: Cow::Owned(
+                        diagnostics
+                            .type_
+                            .clone()
+                            .unwrap_or_else(|| "assertion".to_string()),
+                    ),
+                    message: Cow::Owned(message),
+                    detail: Cow::Owned(detail),
+                }
+            }
+        }
+        other => other,
+    };
+    let mut stdout = test_output.to_vec();
+    stdout.extend(diagnostics.stdout);
+    stdout.extend(diagnostics.other);
     let name = name.unwrap_or_else(|| format!("test {}", number));
-    TestCase::new_with_output(
+    let case = TestCase::new_with_output(
         &name,
-        "tap",
+        class,
         &test_result,
-        Cow::Owned(test_output.join("\n")),
-        Cow::Borrowed(""),
-        Utc::now().signed_duration_since(test_start),
-    )
+        Cow::Owned(stdout.join("\n")),
+        Cow::Owned({
+            stderr.extend(diagnostics.stderr);
+            stderr.join("\n")
+        }),
+        diagnostics.duration.or(time).unwrap_or_else(Duration::zero),
+    );
+    let case = match property {
+        Some((name, value)) => case.with_property(&name, &value),
+        None => case,
+    };
+    match diagnostics.file {
+        Some(file) => case.with_location(&file, diagnostics.line),
+        None => case,
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::tap::read_tap;
+    use crate::reports::{TestCase, TestResult};
+    use crate::tap::{
+        read_tap, read_tap_streams, TapDirectiveResult, TapListener, TapMode, TapOutput, TapParser,
+        TapTiming,
+    };
+    use chrono::Duration;
     use std::io::{BufReader, Cursor};
 
     #[test]
@@ -236,7 +1184,7 @@ mod tests {
         let input = include_str!("../../test/tap/13/common.txt");
         let mut reader = BufReader::new(Cursor::new(input.as_bytes()));
         let result = read_tap(&mut reader);
-        assert_eq!(result.is_ok(), true);
+        assert!(result.is_ok());
         let result = result.unwrap();
         assert_eq!(result.test_count(), 6);
         assert_eq!(result.failure_count(), 0);
@@ -249,7 +1197,7 @@ mod tests {
         let input = include_str!("../../test/tap/13/missing.txt");
         let mut reader = BufReader::new(Cursor::new(input.as_bytes()));
         let result = read_tap(&mut reader);
-        assert_eq!(result.is_ok(), true);
+        assert!(result.is_ok());
         let result = result.unwrap();
         assert_eq!(result.test_count(), 6);
         assert_eq!(result.failure_count(), 4);
@@ -262,7 +1210,7 @@ mod tests {
         let input = include_str!("../../test/tap/13/trailing-output.txt");
         let mut reader = BufReader::new(Cursor::new(input.as_bytes()));
         let result = read_tap(&mut reader);
-        assert_eq!(result.is_ok(), true);
+        assert!(result.is_ok());
         let result = result.unwrap();
         assert_eq!(result.test_count(), 1);
         assert_eq!(result.failure_count(), 0);
@@ -275,7 +1223,7 @@ mod tests {
         let input = include_str!("../../test/tap/13/unknown.txt");
         let mut reader = BufReader::new(Cursor::new(input.as_bytes()));
         let result = read_tap(&mut reader);
-        assert_eq!(result.is_ok(), true);
+        assert!(result.is_ok());
         let result = result.unwrap();
         assert_eq!(result.test_count(), 7);
         assert_eq!(result.failure_count(), 2);
@@ -288,7 +1236,7 @@ mod tests {
         let input = include_str!("../../test/tap/13/unknown9.txt");
         let mut reader = BufReader::new(Cursor::new(input.as_bytes()));
         let result = read_tap(&mut reader);
-        assert_eq!(result.is_ok(), true);
+        assert!(result.is_ok());
         let result = result.unwrap();
         assert_eq!(result.test_count(), 9);
         assert_eq!(result.failure_count(), 4);
@@ -301,12 +1249,26 @@ mod tests {
         let input = include_str!("../../test/tap/13/giveup.txt");
         let mut reader = BufReader::new(Cursor::new(input.as_bytes()));
         let result = read_tap(&mut reader);
-        assert_eq!(result.is_ok(), true);
+        assert!(result.is_ok());
         let result = result.unwrap();
-        assert_eq!(result.test_count(), 573);
-        assert_eq!(result.failure_count(), 573);
+        assert_eq!(result.test_count(), 574);
+        assert_eq!(result.failure_count(), 1);
         assert_eq!(result.skipped_count(), 0);
-        assert_eq!(result.error_count(), 0);
+        assert_eq!(result.error_count(), 573);
+        let bail = &result.cases()[1];
+        assert_eq!(bail.name(), "Bail out!");
+        assert_eq!(
+            bail.result().message(),
+            Some("Couldn't connect to database.")
+        );
+        assert_eq!(result.cases()[2].name(), "test 2");
+        assert_eq!(
+            result.cases()[2].result().message(),
+            Some("Bail out! Couldn't connect to database.")
+        );
+        assert!(result.as_end_str().contains(
+            "Bail out!(tap) Time elapsed: 0 <<< ERROR!\n\tbail out: Couldn't connect to database."
+        ));
     }
 
     #[test]
@@ -314,7 +1276,7 @@ mod tests {
         let input = include_str!("../../test/tap/13/skip-some.txt");
         let mut reader = BufReader::new(Cursor::new(input.as_bytes()));
         let result = read_tap(&mut reader);
-        assert_eq!(result.is_ok(), true);
+        assert!(result.is_ok());
         let result = result.unwrap();
         assert_eq!(result.test_count(), 5);
         assert_eq!(result.failure_count(), 0);
@@ -327,7 +1289,7 @@ mod tests {
         let input = include_str!("../../test/tap/13/skip-all.txt");
         let mut reader = BufReader::new(Cursor::new(input.as_bytes()));
         let result = read_tap(&mut reader);
-        assert_eq!(result.is_ok(), true);
+        assert!(result.is_ok());
         let result = result.unwrap();
         assert_eq!(result.test_count(), 0);
         assert_eq!(result.failure_count(), 0);
@@ -340,7 +1302,7 @@ mod tests {
         let input = include_str!("../../test/tap/13/todos.txt");
         let mut reader = BufReader::new(Cursor::new(input.as_bytes()));
         let result = read_tap(&mut reader);
-        assert_eq!(result.is_ok(), true);
+        assert!(result.is_ok());
         let result = result.unwrap();
         assert_eq!(result.test_count(), 4);
         assert_eq!(result.failure_count(), 0);
@@ -353,7 +1315,7 @@ mod tests {
         let input = include_str!("../../test/tap/13/liberties.txt");
         let mut reader = BufReader::new(Cursor::new(input.as_bytes()));
         let result = read_tap(&mut reader);
-        assert_eq!(result.is_ok(), true);
+        assert!(result.is_ok());
         let result = result.unwrap();
         assert_eq!(result.test_count(), 9);
         assert_eq!(result.failure_count(), 0);
@@ -366,7 +1328,164 @@ mod tests {
         let input = include_str!("../../test/tap/13/yaml-no-end.txt");
         let mut reader = BufReader::new(Cursor::new(input.as_bytes()));
         let result = read_tap(&mut reader);
-        assert_eq!(result.is_ok(), true);
+        assert!(result.is_ok());
+        let result = result.unwrap();
+        assert_eq!(result.test_count(), 9);
+        assert_eq!(result.failure_count(), 0);
+        assert_eq!(result.skipped_count(), 0);
+        assert_eq!(result.error_count(), 0);
+    }
+
+    #[test]
+    fn tap_spec_14_common_example() {
+        let input = include_str!("../../test/tap/14/common.txt");
+        let mut reader = BufReader::new(Cursor::new(input.as_bytes()));
+        let result = read_tap(&mut reader);
+        assert!(result.is_ok());
+        let result = result.unwrap();
+        assert_eq!(result.test_count(), 6);
+        assert_eq!(result.failure_count(), 0);
+        assert_eq!(result.skipped_count(), 0);
+        assert_eq!(result.error_count(), 0);
+    }
+
+    #[test]
+    fn tap_spec_14_missing() {
+        let input = include_str!("../../test/tap/14/missing.txt");
+        let mut reader = BufReader::new(Cursor::new(input.as_bytes()));
+        let result = read_tap(&mut reader);
+        assert!(result.is_ok());
+        let result = result.unwrap();
+        assert_eq!(result.test_count(), 6);
+        assert_eq!(result.failure_count(), 4);
+        assert_eq!(result.skipped_count(), 0);
+        assert_eq!(result.error_count(), 0);
+    }
+
+    #[test]
+    fn tap_spec_14_trailing_output() {
+        let input = include_str!("../../test/tap/14/trailing-output.txt");
+        let mut reader = BufReader::new(Cursor::new(input.as_bytes()));
+        let result = read_tap(&mut reader);
+        assert!(result.is_ok());
+        let result = result.unwrap();
+        assert_eq!(result.test_count(), 1);
+        assert_eq!(result.failure_count(), 0);
+        assert_eq!(result.skipped_count(), 0);
+        assert_eq!(result.error_count(), 0);
+    }
+
+    #[test]
+    fn tap_spec_14_unknown_example() {
+        let input = include_str!("../../test/tap/14/unknown.txt");
+        let mut reader = BufReader::new(Cursor::new(input.as_bytes()));
+        let result = read_tap(&mut reader);
+        assert!(result.is_ok());
+        let result = result.unwrap();
+        assert_eq!(result.test_count(), 7);
+        assert_eq!(result.failure_count(), 2);
+        assert_eq!(result.skipped_count(), 0);
+        assert_eq!(result.error_count(), 0);
+    }
+
+    #[test]
+    fn tap_spec_14_unknown9() {
+        let input = include_str!("../../test/tap/14/unknown9.txt");
+        let mut reader = BufReader::new(Cursor::new(input.as_bytes()));
+        let result = read_tap(&mut reader);
+        assert!(result.is_ok());
+        let result = result.unwrap();
+        assert_eq!(result.test_count(), 9);
+        assert_eq!(result.failure_count(), 4);
+        assert_eq!(result.skipped_count(), 0);
+        assert_eq!(result.error_count(), 0);
+    }
+
+    #[test]
+    fn tap_spec_14_giveup_example() {
+        let input = include_str!("../../test/tap/14/giveup.txt");
+        let mut reader = BufReader::new(Cursor::new(input.as_bytes()));
+        let result = read_tap(&mut reader);
+        assert!(result.is_ok());
+        let result = result.unwrap();
+        assert_eq!(result.test_count(), 574);
+        assert_eq!(result.failure_count(), 1);
+        assert_eq!(result.skipped_count(), 0);
+        assert_eq!(result.error_count(), 573);
+        let bail = &result.cases()[1];
+        assert_eq!(bail.name(), "Bail out!");
+        assert_eq!(
+            bail.result().message(),
+            Some("Couldn't connect to database.")
+        );
+        assert_eq!(result.cases()[2].name(), "test 2");
+        assert_eq!(
+            result.cases()[2].result().message(),
+            Some("Bail out! Couldn't connect to database.")
+        );
+        assert!(result.as_end_str().contains(
+            "Bail out!(tap) Time elapsed: 0 <<< ERROR!\n\tbail out: Couldn't connect to database."
+        ));
+    }
+
+    #[test]
+    fn tap_spec_14_skip_some_example() {
+        let input = include_str!("../../test/tap/14/skip-some.txt");
+        let mut reader = BufReader::new(Cursor::new(input.as_bytes()));
+        let result = read_tap(&mut reader);
+        assert!(result.is_ok());
+        let result = result.unwrap();
+        assert_eq!(result.test_count(), 5);
+        assert_eq!(result.failure_count(), 0);
+        assert_eq!(result.skipped_count(), 4);
+        assert_eq!(result.error_count(), 0);
+    }
+
+    #[test]
+    fn tap_spec_14_skip_all_example() {
+        let input = include_str!("../../test/tap/14/skip-all.txt");
+        let mut reader = BufReader::new(Cursor::new(input.as_bytes()));
+        let result = read_tap(&mut reader);
+        assert!(result.is_ok());
+        let result = result.unwrap();
+        assert_eq!(result.test_count(), 0);
+        assert_eq!(result.failure_count(), 0);
+        assert_eq!(result.skipped_count(), 0);
+        assert_eq!(result.error_count(), 0);
+    }
+
+    #[test]
+    fn tap_spec_14_todos_example() {
+        let input = include_str!("../../test/tap/14/todos.txt");
+        let mut reader = BufReader::new(Cursor::new(input.as_bytes()));
+        let result = read_tap(&mut reader);
+        assert!(result.is_ok());
+        let result = result.unwrap();
+        assert_eq!(result.test_count(), 4);
+        assert_eq!(result.failure_count(), 0);
+        assert_eq!(result.skipped_count(), 0);
+        assert_eq!(result.error_count(), 0);
+    }
+
+    #[test]
+    fn tap_spec_14_liberties_example() {
+        let input = include_str!("../../test/tap/14/liberties.txt");
+        let mut reader = BufReader::new(Cursor::new(input.as_bytes()));
+        let result = read_tap(&mut reader);
+        assert!(result.is_ok());
+        let result = result.unwrap();
+        assert_eq!(result.test_count(), 9);
+        assert_eq!(result.failure_count(), 0);
+        assert_eq!(result.skipped_count(), 0);
+        assert_eq!(result.error_count(), 0);
+    }
+
+    #[test]
+    fn tap_spec_14_yaml_no_end_example() {
+        let input = include_str!("../../test/tap/14/yaml-no-end.txt");
+        let mut reader = BufReader::new(Cursor::new(input.as_bytes()));
+        let result = read_tap(&mut reader);
+        assert!(result.is_ok());
         let result = result.unwrap();
         assert_eq!(result.test_count(), 9);
         assert_eq!(result.failure_count(), 0);
@@ -374,12 +1493,307 @@ mod tests {
         assert_eq!(result.error_count(), 0);
     }
 
+    #[test]
+    fn tap_spec_14_escapes() {
+        let input = include_str!("../../test/tap/14/escapes.txt");
+        let mut reader = BufReader::new(Cursor::new(input.as_bytes()));
+        let result = read_tap(&mut reader).unwrap();
+        let names: Vec<&str> = result.cases().iter().map(|c| c.name()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "issue #42 is fixed",
+                "backslash \\ is kept",
+                "hash # and backslash \\#",
+                "no dash separator"
+            ]
+        );
+        assert_eq!(
+            result.cases()[1].result(),
+            &TestResult::skipped("not on windows")
+        );
+        assert_eq!(result.cases()[2].result(), &TestResult::success());
+        assert_eq!(result.failure_count(), 0);
+    }
+
+    #[test]
+    fn tap_spec_14_pragma() {
+        let input = include_str!("../../test/tap/14/pragma.txt");
+        let mut reader = BufReader::new(Cursor::new(input.as_bytes()));
+        let result = read_tap(&mut reader).unwrap();
+        assert_eq!(result.test_count(), 2);
+        assert_eq!(result.failure_count(), 0);
+        assert_eq!(result.cases()[0].stdout(), "");
+    }
+
+    #[test]
+    fn tap_spec_14_subtests() {
+        let input = include_str!("../../test/tap/14/subtests.txt");
+        let mut reader = BufReader::new(Cursor::new(input.as_bytes()));
+        let result = read_tap(&mut reader).unwrap();
+        let names: Vec<(&str, &str)> = result
+            .cases()
+            .iter()
+            .map(|c| (c.class(), c.name()))
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                ("tap", "lexer"),
+                ("tap.lexer", "tokens"),
+                ("tap.lexer", "comments"),
+                ("tap", "parser"),
+                ("tap.parser", "statements"),
+            ]
+        );
+        assert_eq!(result.failure_count(), 2);
+    }
+
+    #[test]
+    fn tap_spec_14_yaml_relaxed() {
+        let input = include_str!("../../test/tap/14/yaml-relaxed.txt");
+        let mut reader = BufReader::new(Cursor::new(input.as_bytes()));
+        let result = read_tap(&mut reader).unwrap();
+        assert_eq!(result.test_count(), 2);
+        assert_eq!(result.failure_count(), 1);
+        assert_eq!(
+            result.cases()[0].result(),
+            &TestResult::failure("values differ")
+                .with_detail("wanted: 42\nfound:\nfirst line\n\nthird line\n")
+        );
+        assert_eq!(result.cases()[1].stdout(), "");
+    }
+
+    #[test]
+    fn tap_timestamps() {
+        let input = include_str!("../../test/tap/timing/timestamps.txt");
+        let mut reader = BufReader::new(Cursor::new(input.as_bytes()));
+        let result = TapParser::new()
+            .timing(TapTiming::Recorded)
+            .parse(&mut reader)
+            .unwrap();
+        let times: Vec<(&str, Duration)> = result
+            .cases()
+            .iter()
+            .map(|c| (c.name(), c.time()))
+            .collect();
+        assert_eq!(
+            times,
+            vec![
+                ("- first", Duration::milliseconds(1500)),
+                ("- nested", Duration::milliseconds(750)),
+                ("- inner", Duration::milliseconds(250)),
+                ("- slow", Duration::milliseconds(42)),
+                ("- last", Duration::milliseconds(750)),
+            ]
+        );
+        assert_eq!(result.failure_count(), 1);
+    }
+
+    #[test]
+    fn tap_recorded_timing() {
+        let input = include_str!("../../test/tap/timing/untimed.txt");
+        let mut reader = BufReader::new(Cursor::new(input.as_bytes()));
+        let result = TapParser::new()
+            .timing(TapTiming::Recorded)
+            .parse(&mut reader)
+            .unwrap();
+        assert_eq!(result.cases()[0].time(), Duration::zero());
+        assert_eq!(result.cases()[1].time(), Duration::milliseconds(7));
+    }
+
+    #[test]
+    fn tap_strict() {
+        let input = include_str!("../../test/tap/strict/problems.txt");
+        let mut reader = BufReader::new(Cursor::new(input.as_bytes()));
+        let result = TapParser::new()
+            .mode(TapMode::Strict)
+            .parse(&mut reader)
+            .unwrap();
+        assert_eq!(result.test_count(), 7);
+        assert_eq!(result.failure_count(), 2);
+        assert_eq!(result.error_count(), 1);
+        let problems = result.cases().last().unwrap();
+        assert_eq!(problems.name(), "[tap]");
+        assert_eq!(
+            problems.result(),
+            &TestResult::Error {
+                type_: "invalid TAP".into(),
+                message: "5 problems in the TAP stream".into(),
+                detail: "error: line 4: duplicate test number 1\n\
+                error: line 6: test number 3 is out of order, expected 6\n\
+                error: line 7: the TAP version must be the first line\n\
+                error: line 9: test point after the plan at the end of the tests\n\
+                error: line 8: planned 3 tests but 4 ran"
+                    .into(),
+            }
+        );
+        assert_eq!(
+            result.stdout(),
+            "npm WARN something\nok 6 - late\n# tests 6"
+        );
+    }
+
+    #[test]
+    fn tap_strict_malformed() {
+        let input = "TAP version thirteen\nok 1\n";
+        let mut reader = BufReader::new(Cursor::new(input.as_bytes()));
+        let result = TapParser::new()
+            .mode(TapMode::Strict)
+            .parse(&mut reader)
+            .unwrap();
+        assert_eq!(result.test_count(), 2);
+        assert_eq!(
+            result.cases()[1].result().message(),
+            Some("2 problems in the TAP stream")
+        );
+        assert_eq!(
+            result.cases()[1].result().detail(),
+            Some("error: line 1: malformed version line \"TAP version thirteen\"\nerror: no plan")
+        );
+    }
+
+    #[test]
+    fn tap_lenient() {
+        let input = include_str!("../../test/tap/strict/problems.txt");
+        let mut reader = BufReader::new(Cursor::new(input.as_bytes()));
+        let result = read_tap(&mut reader).unwrap();
+        assert_eq!(result.test_count(), 6);
+        assert_eq!(result.failure_count(), 2);
+        assert_eq!(result.error_count(), 0);
+        assert_eq!(
+            result.stdout(),
+            "npm WARN something\nok 6 - late\n# tests 6"
+        );
+    }
+
+    #[test]
+    fn tap_directives() {
+        let input = include_str!("../../test/tap/directives.txt");
+        let mut reader = BufReader::new(Cursor::new(input.as_bytes()));
+        let result = read_tap(&mut reader).unwrap();
+        let cases = result.cases();
+        assert_eq!(cases[0].result(), &TestResult::success());
+        assert_eq!(
+            cases[0].properties(),
+            &[("todo".into(), "not implemented".into())]
+        );
+        assert_eq!(
+            cases[1].result(),
+            &TestResult::failure("unexpectedly passing: flaky fix")
+        );
+        assert_eq!(cases[2].result(), &TestResult::skipped("no network"));
+        assert_eq!(cases[3].result(), &TestResult::success());
+        assert!(cases[3].properties().is_empty());
+
+        let mut reader = BufReader::new(Cursor::new(input.as_bytes()));
+        let result = TapParser::new()
+            .todo_failed(TapDirectiveResult::Skipped)
+            .todo_passed(TapDirectiveResult::Success)
+            .skip(TapDirectiveResult::Failure)
+            .parse(&mut reader)
+            .unwrap();
+        let cases = result.cases();
+        assert_eq!(cases[0].result(), &TestResult::skipped("not implemented"));
+        assert_eq!(cases[1].result(), &TestResult::success());
+        assert_eq!(
+            cases[1].properties(),
+            &[("todo".into(), "flaky fix".into())]
+        );
+        assert_eq!(cases[2].result(), &TestResult::failure("no network"));
+    }
+
+    #[test]
+    fn tap_stderr() {
+        let output = vec![
+            TapOutput::Stderr("starting".to_string()),
+            TapOutput::Stdout("TAP version 13".to_string()),
+            TapOutput::Stdout("1..2".to_string()),
+            TapOutput::Stdout("not ok 1 - fails".to_string()),
+            TapOutput::Stderr("#   Failed test 'fails'".to_string()),
+            TapOutput::Stdout("# Subtest: nested".to_string()),
+            TapOutput::Stdout("    ok 1 - inner".to_string()),
+            TapOutput::Stderr("inner warning".to_string()),
+            TapOutput::Stdout("    1..1".to_string()),
+            TapOutput::Stdout("ok 2 - nested".to_string()),
+        ];
+        let suites = TapParser::new().parse_output(output.into_iter()).unwrap();
+        assert_eq!(suites.len(), 1);
+        let suite = &suites[0];
+        assert_eq!(suite.stderr(), "starting");
+        let stderr: Vec<(&str, &str)> = suite
+            .cases()
+            .iter()
+            .map(|c| (c.name(), c.stderr()))
+            .collect();
+        assert_eq!(
+            stderr,
+            vec![
+                ("- fails", "#   Failed test 'fails'"),
+                ("- nested", ""),
+                ("- inner", "inner warning"),
+            ]
+        );
+    }
+
+    #[derive(Default)]
+    struct Events(Vec<String>);
+
+    impl TapListener for Events {
+        fn test_point(&mut self, stream: &str, case: &TestCase<'static>) {
+            self.0
+                .push(format!("point {} {}", stream, case.as_progress_str()));
+        }
+
+        fn test_case(&mut self, stream: &str, case: &TestCase<'static>) {
+            self.0
+                .push(format!("case {} {}", stream, case.as_progress_str()));
+        }
+    }
+
+    #[test]
+    fn tap_listener() {
+        let input = "TAP version 14\n1..3\n# Subtest: nested\n    not ok 1 - inner\n    1..1\nok 1 - nested\nok 3 - last\n  ---\n  duration_ms: 5\n  ...\nTAP version 14\n1..1\nok 1 - other\n";
+        let mut events = Events::default();
+        let suites = TapParser::new()
+            .timing(TapTiming::Recorded)
+            .parse_output_with(
+                input.lines().map(|l| TapOutput::Stdout(l.to_string())),
+                &mut events,
+            )
+            .unwrap();
+        assert_eq!(suites.len(), 2);
+        assert_eq!(
+            events.0,
+            vec![
+                "point tap inner(tap.nested) Time elapsed: 0 <<< FAILURE!",
+                "case tap inner(tap.nested) Time elapsed: 0 <<< FAILURE!",
+                "point tap nested(tap) Time elapsed: 0 <<< FAILURE!",
+                "case tap nested(tap) Time elapsed: 0 <<< FAILURE!",
+                "point tap test 2(tap) Time elapsed: 0 <<< FAILURE!",
+                "case tap test 2(tap) Time elapsed: 0 <<< FAILURE!",
+                "point tap last(tap) Time elapsed: 0",
+                "case tap last(tap) Time elapsed: 0.005",
+                "point tap-2 other(tap-2) Time elapsed: 0",
+                "case tap-2 other(tap-2) Time elapsed: 0",
+            ]
+        );
+    }
+
+    #[test]
+    fn tap_invalid_future_version() {
+        let input = "TAP version 15\n";
+        let mut reader = BufReader::new(Cursor::new(input.as_bytes()));
+        let result = read_tap(&mut reader);
+        assert!(result.is_err());
+    }
+
     #[test]
     fn tap_spec_12_common_example() {
         let input = include_str!("../../test/tap/12/common.txt");
         let mut reader = BufReader::new(Cursor::new(input.as_bytes()));
         let result = read_tap(&mut reader);
-        assert_eq!(result.is_ok(), true);
+        assert!(result.is_ok());
         let result = result.unwrap();
         assert_eq!(result.test_count(), 6);
         assert_eq!(result.failure_count(), 0);
@@ -392,7 +1806,7 @@ mod tests {
         let input = include_str!("../../test/tap/12/missing.txt");
         let mut reader = BufReader::new(Cursor::new(input.as_bytes()));
         let result = read_tap(&mut reader);
-        assert_eq!(result.is_ok(), true);
+        assert!(result.is_ok());
         let result = result.unwrap();
         assert_eq!(result.test_count(), 6);
         assert_eq!(result.failure_count(), 4);
@@ -405,7 +1819,7 @@ mod tests {
         let input = include_str!("../../test/tap/12/trailing-output.txt");
         let mut reader = BufReader::new(Cursor::new(input.as_bytes()));
         let result = read_tap(&mut reader);
-        assert_eq!(result.is_ok(), true);
+        assert!(result.is_ok());
         let result = result.unwrap();
         assert_eq!(result.test_count(), 1);
         assert_eq!(result.failure_count(), 0);
@@ -418,7 +1832,7 @@ mod tests {
         let input = include_str!("../../test/tap/12/unknown.txt");
         let mut reader = BufReader::new(Cursor::new(input.as_bytes()));
         let result = read_tap(&mut reader);
-        assert_eq!(result.is_ok(), true);
+        assert!(result.is_ok());
         let result = result.unwrap();
         assert_eq!(result.test_count(), 7);
         assert_eq!(result.failure_count(), 2);
@@ -431,7 +1845,7 @@ mod tests {
         let input = include_str!("../../test/tap/12/unknown9.txt");
         let mut reader = BufReader::new(Cursor::new(input.as_bytes()));
         let result = read_tap(&mut reader);
-        assert_eq!(result.is_ok(), true);
+        assert!(result.is_ok());
         let result = result.unwrap();
         assert_eq!(result.test_count(), 9);
         assert_eq!(result.failure_count(), 4);
@@ -444,12 +1858,26 @@ mod tests {
         let input = include_str!("../../test/tap/12/giveup.txt");
         let mut reader = BufReader::new(Cursor::new(input.as_bytes()));
         let result = read_tap(&mut reader);
-        assert_eq!(result.is_ok(), true);
+        assert!(result.is_ok());
         let result = result.unwrap();
-        assert_eq!(result.test_count(), 573);
-        assert_eq!(result.failure_count(), 573);
+        assert_eq!(result.test_count(), 574);
+        assert_eq!(result.failure_count(), 1);
         assert_eq!(result.skipped_count(), 0);
-        assert_eq!(result.error_count(), 0);
+        assert_eq!(result.error_count(), 573);
+        let bail = &result.cases()[1];
+        assert_eq!(bail.name(), "Bail out!");
+        assert_eq!(
+            bail.result().message(),
+            Some("Couldn't connect to database.")
+        );
+        assert_eq!(result.cases()[2].name(), "test 2");
+        assert_eq!(
+            result.cases()[2].result().message(),
+            Some("Bail out! Couldn't connect to database.")
+        );
+        assert!(result.as_end_str().contains(
+            "Bail out!(tap) Time elapsed: 0 <<< ERROR!\n\tbail out: Couldn't connect to database."
+        ));
     }
 
     #[test]
@@ -457,7 +1885,7 @@ mod tests {
         let input = include_str!("../../test/tap/12/skip-some.txt");
         let mut reader = BufReader::new(Cursor::new(input.as_bytes()));
         let result = read_tap(&mut reader);
-        assert_eq!(result.is_ok(), true);
+        assert!(result.is_ok());
         let result = result.unwrap();
         assert_eq!(result.test_count(), 5);
         assert_eq!(result.failure_count(), 0);
@@ -470,7 +1898,7 @@ mod tests {
         let input = include_str!("../../test/tap/12/skip-all.txt");
         let mut reader = BufReader::new(Cursor::new(input.as_bytes()));
         let result = read_tap(&mut reader);
-        assert_eq!(result.is_ok(), true);
+        assert!(result.is_ok());
         let result = result.unwrap();
         assert_eq!(result.test_count(), 0);
         assert_eq!(result.failure_count(), 0);
@@ -483,7 +1911,7 @@ mod tests {
         let input = include_str!("../../test/tap/12/todos.txt");
         let mut reader = BufReader::new(Cursor::new(input.as_bytes()));
         let result = read_tap(&mut reader);
-        assert_eq!(result.is_ok(), true);
+        assert!(result.is_ok());
         let result = result.unwrap();
         assert_eq!(result.test_count(), 4);
         assert_eq!(result.failure_count(), 0);
@@ -496,7 +1924,7 @@ mod tests {
         let input = include_str!("../../test/tap/12/liberties.txt");
         let mut reader = BufReader::new(Cursor::new(input.as_bytes()));
         let result = read_tap(&mut reader);
-        assert_eq!(result.is_ok(), true);
+        assert!(result.is_ok());
         let result = result.unwrap();
         assert_eq!(result.test_count(), 9);
         assert_eq!(result.failure_count(), 0);
@@ -504,12 +1932,103 @@ mod tests {
         assert_eq!(result.error_count(), 0);
     }
 
+    #[test]
+    fn tap_subtests() {
+        let input = include_str!("../../test/tap/13/subtests.txt");
+        let mut reader = BufReader::new(Cursor::new(input.as_bytes()));
+        let result = read_tap(&mut reader).unwrap();
+        assert_eq!(result.test_count(), 8);
+        assert_eq!(result.failure_count(), 3);
+        let names: Vec<(&str, &str)> = result
+            .cases()
+            .iter()
+            .map(|c| (c.class(), c.name()))
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                ("tap", "- parser"),
+                ("tap.parser", "- parses numbers"),
+                ("tap.parser", "- strings"),
+                ("tap.parser.strings", "- quoted"),
+                ("tap.parser.strings", "- escaped"),
+                ("tap", "- printer"),
+                ("tap.printer", "- prints numbers"),
+                ("tap", "- standalone"),
+            ]
+        );
+        // the parent passed but its subtests did not
+        assert_eq!(
+            result.cases()[0].result(),
+            &TestResult::failure("subtests failed: - strings")
+        );
+        assert_eq!(
+            result.cases()[2].result(),
+            &TestResult::failure("subtests failed: - escaped")
+        );
+    }
+
     #[test]
     fn tap_invalid_two_plans() {
         let input = include_str!("../../test/tap/invalid/two-plans.txt");
         let mut reader = BufReader::new(Cursor::new(input.as_bytes()));
-        let result = read_tap(&mut reader);
-        assert_eq!(result.is_err(), true);
+        let result = TapParser::new().mode(TapMode::Strict).parse(&mut reader);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn tap_lenient_two_plans() {
+        // the repeated plan is ignored rather than losing the tests that were read
+        let input = "1..2\nok 1\nok 2\n1..2\n";
+        let mut reader = BufReader::new(Cursor::new(input.as_bytes()));
+        let result = read_tap(&mut reader).unwrap();
+        assert_eq!(result.test_count(), 2);
+        assert_eq!(result.failure_count(), 0);
+        assert_eq!(result.error_count(), 0);
+        assert_eq!(result.stdout(), "1..2");
+    }
+
+    #[test]
+    fn tap_streams_repeated_plan() {
+        // a plan that is not followed by tests does not start another stream
+        let input = "ok 1 - first\nok 2 - second\n1..2\n# done\n1..2\n";
+        let mut reader = BufReader::new(Cursor::new(input.as_bytes()));
+        let suites = TapParser::new()
+            .mode(TapMode::Strict)
+            .parse_streams(&mut reader)
+            .unwrap();
+        assert_eq!(suites.len(), 1);
+        assert_eq!(suites[0].test_count(), 3);
+        assert_eq!(
+            suites[0].cases()[2].result(),
+            &TestResult::Error {
+                type_: "invalid TAP".into(),
+                message: "error: line 5: more than one plan".into(),
+                detail: "error: line 5: more than one plan".into(),
+            }
+        );
+    }
+
+    #[test]
+    fn tap_streams() {
+        let input = include_str!("../../test/tap/streams.txt");
+        let mut reader = BufReader::new(Cursor::new(input.as_bytes()));
+        let suites = read_tap_streams(&mut reader).unwrap();
+        let counts: Vec<(&str, i32, i32)> = suites
+            .iter()
+            .map(|s| (s.name(), s.test_count(), s.failure_count()))
+            .collect();
+        assert_eq!(
+            counts,
+            vec![("tap", 2, 0), ("tap-2", 1, 1), ("tap-3", 2, 0)]
+        );
+        assert_eq!(suites[1].cases()[0].name(), "- fails");
+
+        let mut reader = BufReader::new(Cursor::new(input.as_bytes()));
+        let result = read_tap(&mut reader).unwrap();
+        assert_eq!(result.test_count(), 5);
+        assert_eq!(result.failure_count(), 1);
+        assert_eq!(result.cases()[2].class(), "tap-2");
     }
 
     #[test]
@@ -517,6 +2036,6 @@ mod tests {
         let input = "TAP version 12\n";
         let mut reader = BufReader::new(Cursor::new(input.as_bytes()));
         let result = read_tap(&mut reader);
-        assert_eq!(result.is_ok(), false);
+        assert!(result.is_err());
     }
 }
@@ -0,0 +1,199 @@
+/*
+ * Copyright (c) 2020 Stephen Connolly and CloudBees, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use crate::reports::{TestCase, TestResult, TestSuite};
+use serde::Serialize;
+use std::io::Write;
+
+/// The YAML diagnostics of a test that did not pass
+#[derive(Serialize)]
+struct Diagnostics<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<&'a str>,
+    severity: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    r#type: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    at: Option<Location<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stack: Option<&'a str>,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    stdout: &'a str,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    stderr: &'a str,
+    duration_ms: i64,
+}
+
+#[derive(Serialize)]
+struct Location<'a> {
+    file: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    line: Option<u32>,
+}
+
+/// Writes test suites as a single TAP version 14 stream, with the failure details and captured
+/// output of any failed tests as YAML diagnostics.
+pub fn write_tap<W: Write>(suites: &[TestSuite<'_>], writer: &mut W) -> anyhow::Result<()> {
+    let count: i32 = suites.iter().map(|s| s.test_count()).sum();
+    writeln!(writer, "TAP version 14")?;
+    writeln!(writer, "1..{}", count)?;
+    let mut number = 0;
+    for suite in suites {
+        writeln!(writer, "# {}", suite.name())?;
+        for case in suite.cases() {
+            number += 1;
+            write_test(number, case, writer)?;
+        }
+    }
+    Ok(())
+}
+
+fn write_test<W: Write>(number: usize, case: &TestCase<'_>, writer: &mut W) -> anyhow::Result<()> {
+    let description = escape(&format!("{}({})", case.name(), case.class()));
+    let (severity, type_) = match case.result() {
+        TestResult::Success => {
+            writeln!(writer, "ok {} - {}", number, description)?;
+            return Ok(());
+        }
+        TestResult::Skipped { message } => {
+            writeln!(
+                writer,
+                "ok {} - {} # SKIP {}",
+                number,
+                description,
+                // the reason ends the line, so only the description needs escaping
+                message.replace('\n', " ")
+            )?;
+            return Ok(());
+        }
+        TestResult::Failure { type_, .. } => ("fail", type_),
+        TestResult::Error { type_, .. } => ("error", type_),
+    };
+    writeln!(writer, "not ok {} - {}", number, description)?;
+    let diagnostics = Diagnostics {
+        message: case.result().message().filter(|m| !m.is_empty()),
+        severity,
+        r#type: Some(type_.as_ref()),
+        at: case.file().map(|file| Location {
+            file,
+            line: case.line(),
+        }),
+        stack: case.result().detail(),
+        stdout: case.stdout(),
+        stderr: case.stderr(),
+        duration_ms: case.time().num_milliseconds(),
+    };
+    let yaml = serde_yaml::to_string(&diagnostics)?;
+    for line in yaml.lines() {
+        writeln!(writer, "  {}", line)?;
+    }
+    writeln!(writer, "  ...")?;
+    Ok(())
+}
+
+/// escapes the characters that would otherwise start a directive
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('#', "\\#")
+        .replace('\n', " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::reports::{TestCase, TestResult, TestSuite};
+    use crate::tap::{read_tap, write_tap};
+    use chrono::Duration;
+    use std::borrow::Cow;
+    use std::io::{BufReader, Cursor};
+
+    #[test]
+    fn write() {
+        let suites = vec![
+            TestSuite::new("first")
+                .push(TestCase::new(
+                    "passes",
+                    "foo",
+                    &TestResult::success(),
+                    Duration::milliseconds(5),
+                ))
+                .push(
+                    TestCase::new_with_output(
+                        "fails",
+                        "foo",
+                        &TestResult::failure("expected: 1\nactual: 2").with_detail("at foo.rs:42"),
+                        Cow::Borrowed("some output"),
+                        Cow::Borrowed(""),
+                        Duration::milliseconds(12),
+                    )
+                    .with_location("src/foo.rs", Some(42)),
+                ),
+            TestSuite::new("second")
+                .push(TestCase::new(
+                    "skips",
+                    "bar",
+                    &TestResult::skipped("not today"),
+                    Duration::zero(),
+                ))
+                .push(TestCase::new(
+                    "errors",
+                    "bar",
+                    &TestResult::error("boom"),
+                    Duration::milliseconds(1),
+                )),
+        ];
+        let mut out = Vec::new();
+        write_tap(&suites, &mut out).unwrap();
+        let tap = String::from_utf8(out).unwrap();
+        assert_eq!(
+            tap.trim(),
+            include_str!("../../test/tap/written.txt").trim()
+        );
+
+        let result = read_tap(&mut BufReader::new(Cursor::new(tap.as_bytes()))).unwrap();
+        assert_eq!(result.test_count(), 4);
+        assert_eq!(result.failure_count(), 1);
+        assert_eq!(result.error_count(), 1);
+        assert_eq!(result.skipped_count(), 1);
+        let fails = &result.cases()[1];
+        assert_eq!(
+            fails.result(),
+            &TestResult::failure("expected: 1\nactual: 2").with_detail("at foo.rs:42")
+        );
+        assert_eq!(fails.file(), Some("src/foo.rs"));
+        assert_eq!(fails.line(), Some(42));
+        assert!(fails.stdout().ends_with("some output"));
+        assert_eq!(fails.time(), Duration::milliseconds(12));
+        assert_eq!(result.cases()[3].result(), &TestResult::error("boom"));
+    }
+
+    #[test]
+    fn write_escapes() {
+        let suites = vec![TestSuite::new("first").push(TestCase::new(
+            "issue #12 in a\\b",
+            "foo",
+            &TestResult::skipped("see #12"),
+            Duration::zero(),
+        ))];
+        let mut out = Vec::new();
+        write_tap(&suites, &mut out).unwrap();
+        let tap = String::from_utf8(out).unwrap();
+        assert!(tap.starts_with("TAP version 14\n"));
+        assert!(tap.contains("ok 1 - issue \\#12 in a\\\\b(foo) # SKIP see #12"));
+
+        // the escapes are those of TAP 14, so they are undone when read back
+        let result = read_tap(&mut BufReader::new(Cursor::new(tap.as_bytes()))).unwrap();
+        assert_eq!(result.cases()[0].name(), "issue #12 in a\\b(foo)");
+        assert_eq!(result.cases()[0].result(), &TestResult::skipped("see #12"));
+    }
+}